@@ -0,0 +1,715 @@
+// Static grammar analysis
+
+use crate::bnf::*;
+type HashSet<K> = std::collections::HashSet::<K, crate::HashBuilder>;
+
+/// The tokens that something (a rule, an alternation, or the rest of an alternation) can start with. See [`first_sets`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FirstSet {
+    /// Interned string IDs of literals it can start with, sorted.
+    pub literals : Vec<u32>,
+    /// Patterns of regexes it can start with, sorted.
+    pub regexes : Vec<String>,
+    /// Can it start with any token at all (because of `$any` or a hook)?
+    pub any : bool,
+    /// Can it match without consuming any tokens?
+    pub empty : bool,
+}
+
+impl FirstSet {
+    fn merge(&mut self, other : &FirstSet) -> bool
+    {
+        let old = (self.literals.len(), self.regexes.len(), self.any);
+        self.literals.extend_from_slice(&other.literals);
+        self.literals.sort();
+        self.literals.dedup();
+        self.regexes.extend_from_slice(&other.regexes);
+        self.regexes.sort();
+        self.regexes.dedup();
+        self.any |= other.any;
+        old != (self.literals.len(), self.regexes.len(), self.any)
+    }
+    /// Could it start with the given token? Regexes are looked up in the given grammar.
+    pub fn may_start_with(&self, g : &Grammar, text : u32) -> bool
+    {
+        if self.any || self.literals.binary_search(&text).is_ok() { return true; }
+        let s = &g.string_cache_inv[text as usize];
+        g.regexes.iter().any(|(_, r)| self.regexes.iter().any(|p| p == r.pattern()) && r.is_match(s))
+    }
+}
+
+// FIRST set of a sequence of terms, given the FIRST sets of every rule so far.
+fn terms_first(terms : &[MatchingTerm], sets : &[FirstSet]) -> FirstSet
+{
+    let mut ret = FirstSet::default();
+    let mut i = 0;
+    while i < terms.len()
+    {
+        match &terms[i].t
+        {
+            MatchingTermE::TermLit(s) =>
+            {
+                ret.literals.push(*s);
+                return ret;
+            }
+            MatchingTermE::TermRegex(r) =>
+            {
+                ret.regexes.push(r.pattern().to_string());
+                return ret;
+            }
+            MatchingTermE::Directive(MatchDirective::Any) | MatchingTermE::Hook(_) =>
+            {
+                // @auto is a peek followed by $any
+                match (i, &terms[0].t, &terms[i].t)
+                {
+                    (1, MatchingTermE::Peek(0, s), MatchingTermE::Directive(MatchDirective::Any)) => ret.literals.push(*s),
+                    (1, MatchingTermE::PeekR(0, r), MatchingTermE::Directive(MatchDirective::Any)) => ret.regexes.push(r.pattern().to_string()),
+                    _ => ret.any = true,
+                }
+                return ret;
+            }
+            MatchingTermE::Rule(id) =>
+            {
+                ret.merge(&sets[*id]);
+                if !sets[*id].empty { return ret; }
+            }
+            MatchingTermE::Directive(MatchDirective::Become | MatchDirective::BecomeAs) =>
+            {
+                if let Some(MatchingTermE::Rule(id)) = terms.get(i + 1).map(|x| &x.t)
+                {
+                    ret.merge(&sets[*id]);
+                    ret.empty = sets[*id].empty;
+                    return ret;
+                }
+            }
+            // the rule after $rename is just a name
+            MatchingTermE::Directive(MatchDirective::Rename) => i += 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    ret.empty = true;
+    ret
+}
+
+/// Computes the FIRST set of every rule (index = grammar point ID), i.e. the tokens that it can start with, and whether it can match nothing.
+///
+/// Guards (`@peek` etc) are ignored, because they don't consume anything. Hooks can consume anything, so they count as [`FirstSet::any`].
+pub fn first_sets(g : &Grammar) -> Vec<FirstSet>
+{
+    let mut sets = vec!(FirstSet::default(); g.points.len());
+    let mut changed = true;
+    while changed
+    {
+        changed = false;
+        for point in &g.points
+        {
+            for alt in &point.forms
+            {
+                let first = terms_first(&alt.matching_terms, &sets);
+                let set = &mut sets[point.id as usize];
+                changed |= set.merge(&first);
+                if first.empty && !set.empty
+                {
+                    set.empty = true;
+                    changed = true;
+                }
+            }
+        }
+    }
+    sets
+}
+
+/// Computes the FIRST set of one alternation, not counting its leading guard (if any), except that `@auto` counts as what it peeks at. Takes the output of [`first_sets`].
+pub fn alt_first_set(g : &Grammar, sets : &[FirstSet], rule : usize, alt : usize) -> FirstSet
+{
+    terms_first(&g.points[rule].forms[alt].matching_terms, sets)
+}
+
+// Most tokens that a sequence of terms can consume, given the same for every rule, or None if unbounded.
+fn terms_max_tokens(terms : &[MatchingTerm], lens : &[Option<usize>]) -> Option<usize>
+{
+    let mut n = 0usize;
+    let mut i = 0;
+    while i < terms.len()
+    {
+        n = n.checked_add(match &terms[i].t
+        {
+            MatchingTermE::TermLit(_) | MatchingTermE::TermRegex(_) | MatchingTermE::Directive(MatchDirective::Any) => 1,
+            MatchingTermE::Hook(_) => return None,
+            MatchingTermE::Rule(id) => lens[*id]?,
+            MatchingTermE::Directive(MatchDirective::Become | MatchDirective::BecomeAs) => match terms.get(i + 1).map(|x| &x.t)
+            {
+                Some(MatchingTermE::Rule(id)) => return n.checked_add(lens[*id]?),
+                _ => 0,
+            }
+            MatchingTermE::Directive(MatchDirective::Rename) => { i += 1; 0 }
+            _ => 0,
+        })?;
+        i += 1;
+    }
+    Some(n)
+}
+
+// Most tokens that each rule can consume, or None if unbounded.
+fn max_tokens(g : &Grammar) -> Vec<Option<usize>>
+{
+    let step = |lens : &[Option<usize>]| g.points.iter().map(|p|
+        p.forms.iter().map(|a| terms_max_tokens(&a.matching_terms, lens)).try_fold(0, |a, b| Some(a.max(b?)))).collect::<Vec<_>>();
+    // without cycles that consume tokens, this settles within one round per rule; rules that keep growing after that are unbounded
+    let mut lens = vec!(Some(0); g.points.len());
+    for _ in 0..=g.points.len() { lens = step(&lens); }
+    let mut later = lens.clone();
+    for _ in 0..=g.points.len() { later = step(&later); }
+    let mut lens = lens.iter().zip(later).map(|(a, b)| if *a == b { b } else { None }).collect::<Vec<_>>();
+    loop
+    {
+        let next = step(&lens);
+        if next == lens { return lens; }
+        lens = next;
+    }
+}
+
+/// What's wrong with an alternation. See [`Diagnostic`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// Comes after an alternation without a guard (which is always taken), so it's never chosen.
+    Unreachable {
+        /// Index of the unguarded alternation.
+        after : usize
+    },
+    /// Its guard only accepts what an earlier alternation's guard already accepts, so it's never chosen.
+    Shadowed {
+        /// Index of the earlier alternation.
+        by : usize
+    },
+    /// Empty, but not the last alternation, so the ones after it are never chosen.
+    EmptyNotLast,
+    /// Starts with `@peek(0, ...)` for a token that the rest of the alternation can't start with, so it always fails once chosen.
+    PeekNeverMatches,
+    /// Peeks past the most tokens that the alternation can consume, i.e. at tokens that belong to whatever comes after the rule. Not necessarily a mistake.
+    PeekBeyondRule {
+        /// How far ahead the guard peeks.
+        distance : isize,
+        /// Most tokens the alternation can consume.
+        max_tokens : usize,
+    },
+    /// Can reach its own rule again without consuming any tokens, through rules that it calls or `$become`s. Left recursion makes parsing hit the depth limit (or run out of memory), and a `$become` cycle makes it loop forever.
+    LeftRecursion {
+        /// Grammar point IDs along the cycle, starting and ending with this alternation's rule.
+        path : Vec<usize>
+    },
+}
+
+/// Something suspicious about an alternation, found by [`analyze`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Grammar point ID.
+    pub rule : usize,
+    /// Alternation index.
+    pub alt : usize,
+    /// What's wrong with it.
+    pub kind : DiagnosticKind,
+}
+
+impl Diagnostic {
+    /// Human-readable description, using the given grammar for names. Format is not guaranteed and may change arbitrarily.
+    pub fn describe(&self, g : &Grammar) -> String
+    {
+        let name = &g.points[self.rule].name;
+        let what = match &self.kind
+        {
+            DiagnosticKind::Unreachable { after } => format!("is never chosen, because alt {after} has no guard"),
+            DiagnosticKind::Shadowed { by } => format!("is never chosen, because alt {by} accepts everything that it accepts"),
+            DiagnosticKind::EmptyNotLast => "is empty but not last, so the alternations after it are never chosen".to_string(),
+            DiagnosticKind::PeekNeverMatches => "peeks for a token that it can't start with, so it always fails".to_string(),
+            DiagnosticKind::PeekBeyondRule { distance, max_tokens } => format!("peeks {distance} tokens ahead, but consumes at most {max_tokens}"),
+            DiagnosticKind::LeftRecursion { path } =>
+            {
+                let path = path.iter().map(|id| g.points[*id].name.as_str()).collect::<Vec<_>>();
+                format!("can reach {name} again without consuming any tokens: {}", path.join(" -> "))
+            }
+        };
+        format!("{name} alt {} {what}", self.alt)
+    }
+}
+
+// Does guard `a` accept everything that guard `b` accepts?
+fn guard_covers(g : &Grammar, a : &MatchingTermE, b : &MatchingTermE) -> bool
+{
+    match (a, b)
+    {
+        (MatchingTermE::Eof, MatchingTermE::Eof) => true,
+        (MatchingTermE::Peek(n, s), MatchingTermE::Peek(m, t)) => n == m && s == t,
+        (MatchingTermE::PeekR(n, r), MatchingTermE::Peek(m, t)) => n == m && r.is_match_interned(*t, &g.string_cache_inv),
+        (MatchingTermE::PeekRes(n, r), MatchingTermE::Peek(m, t)) =>
+            n == m && r.is_match_interned(*t, &g.string_cache_inv) && !is_reserved(g, *t),
+        (MatchingTermE::PeekR(n, r), MatchingTermE::PeekR(m, q) | MatchingTermE::PeekRes(m, q))
+        | (MatchingTermE::PeekRes(n, r), MatchingTermE::PeekRes(m, q)) => n == m && r.pattern() == q.pattern(),
+        _ => false,
+    }
+}
+
+// Rules that an alternation can call (or $become) before consuming any tokens.
+fn left_calls(g : &Grammar, terms : &[MatchingTerm], sets : &[FirstSet]) -> Vec<usize>
+{
+    // a rule that can match nothing still consumes the next token if it can start with it, and a guard can say what that token is
+    let consumes = |id : usize| match terms.first().map(|x| &x.t)
+    {
+        Some(MatchingTermE::Peek(0, s)) => sets[id].may_start_with(g, *s),
+        Some(MatchingTermE::PeekR(0, r) | MatchingTermE::PeekRes(0, r)) => sets[id].any || sets[id].regexes.iter().any(|p| p == r.pattern()),
+        _ => false,
+    };
+    let mut ret = vec!();
+    let mut i = 0;
+    while i < terms.len()
+    {
+        match &terms[i].t
+        {
+            MatchingTermE::Rule(id) =>
+            {
+                ret.push(*id);
+                if !sets[*id].empty || consumes(*id) { break; }
+            }
+            MatchingTermE::Directive(MatchDirective::Become | MatchDirective::BecomeAs) =>
+            {
+                if let Some(MatchingTermE::Rule(id)) = terms.get(i + 1).map(|x| &x.t) { ret.push(*id); }
+                break;
+            }
+            MatchingTermE::Directive(MatchDirective::Rename) => i += 1,
+            // hooks almost always consume something, so assume that they do
+            MatchingTermE::TermLit(_) | MatchingTermE::TermRegex(_) | MatchingTermE::Directive(MatchDirective::Any) | MatchingTermE::Hook(_) => break,
+            _ => {}
+        }
+        i += 1;
+    }
+    ret
+}
+
+// Finds the shortest cycle of calls and $becomes without consumption through each rule, and reports each cycle once.
+fn left_recursion(g : &Grammar, sets : &[FirstSet], ret : &mut Vec<Diagnostic>)
+{
+    let calls = g.points.iter().map(|p| p.forms.iter().map(|f| left_calls(g, &f.matching_terms, sets)).collect::<Vec<_>>()).collect::<Vec<_>>();
+    let mut seen = std::collections::HashSet::new();
+    for rule in 0..g.points.len()
+    {
+        // breadth-first search back to the rule, remembering which of its alternations each path started in
+        let mut prev = vec!(None; g.points.len());
+        let mut queue = std::collections::VecDeque::new();
+        for (alt, callees) in calls[rule].iter().enumerate()
+        {
+            for c in callees
+            {
+                if prev[*c].is_none()
+                {
+                    prev[*c] = Some((rule, alt));
+                    queue.push_back(*c);
+                }
+            }
+        }
+        while let Some(x) = queue.pop_front() && x != rule
+        {
+            for c in calls[x].iter().flatten()
+            {
+                if prev[*c].is_none()
+                {
+                    prev[*c] = Some((x, prev[x].unwrap().1));
+                    queue.push_back(*c);
+                }
+            }
+        }
+        let Some((mut x, alt)) = prev[rule] else { continue };
+        let mut path = vec!(rule);
+        while x != rule
+        {
+            path.push(x);
+            x = prev[x].unwrap().0;
+        }
+        path.push(rule);
+        path.reverse();
+        let mut key = path[1..].to_vec();
+        key.sort();
+        if seen.insert(key) { ret.push(Diagnostic { rule, alt, kind : DiagnosticKind::LeftRecursion { path } }); }
+    }
+}
+
+/// Looks for alternations that can never be chosen or that look like mistakes. See [`DiagnosticKind`] for what it finds.
+///
+/// Alternations that consume nothing (like `@peek(0, ")")` on its own) look past the rule on purpose, so they aren't reported as [`DiagnosticKind::PeekBeyondRule`].
+pub fn analyze(g : &Grammar) -> Vec<Diagnostic>
+{
+    let sets = first_sets(g);
+    let lens = max_tokens(g);
+    let mut ret = vec!();
+    for point in &g.points
+    {
+        let rule = point.id as usize;
+        let mut unguarded = None;
+        for (alt, form) in point.forms.iter().enumerate()
+        {
+            let mut diag = |kind| ret.push(Diagnostic { rule, alt, kind });
+            if let Some(after) = unguarded
+            {
+                diag(DiagnosticKind::Unreachable { after });
+                continue;
+            }
+            let Some(guard) = form.matching_terms.first().filter(|t| t.is_guard())
+            else
+            {
+                if form.matching_terms.is_empty() && alt + 1 < point.forms.len() { diag(DiagnosticKind::EmptyNotLast); }
+                unguarded = Some(alt);
+                continue;
+            };
+            if let Some(by) = point.forms[..alt].iter().position(|f| guard_covers(g, &f.matching_terms[0].t, &guard.t))
+            {
+                diag(DiagnosticKind::Shadowed { by });
+                continue;
+            }
+            let (MatchingTermE::Peek(n, _) | MatchingTermE::PeekR(n, _) | MatchingTermE::PeekRes(n, _)) = guard.t else { continue };
+            let first = terms_first(&form.matching_terms[1..], &sets);
+            if let MatchingTermE::Peek(0, s) = guard.t && !first.empty && !first.may_start_with(g, s)
+            {
+                diag(DiagnosticKind::PeekNeverMatches);
+            }
+            if let Some(max_tokens) = terms_max_tokens(&form.matching_terms, &lens) && max_tokens > 0 && n >= max_tokens as isize
+            {
+                diag(DiagnosticKind::PeekBeyondRule { distance : n, max_tokens });
+            }
+        }
+    }
+    left_recursion(g, &sets, &mut ret);
+    ret
+}
+
+/// Rules and tokens that a grammar defines but can't use, found by [`usage_report`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsageReport {
+    /// Grammar point IDs that can't be reached from the root rule, sorted.
+    pub unreachable : Vec<usize>,
+    /// Grammar point IDs that no other rule refers to, not counting the root rule, sorted. These are a subset of `unreachable`.
+    pub unreferenced : Vec<usize>,
+    /// Literals that register with the tokenizer, but are only matched or peeked at by unreachable rules, sorted.
+    pub unused_literals : Vec<String>,
+    /// Patterns of ```r`...`r``` regexes that register with the tokenizer, but are only matched or peeked at by unreachable rules, sorted.
+    pub unused_regexes : Vec<String>,
+}
+
+impl UsageReport {
+    /// Format the report as human-readable text, using the given grammar for names. Format is not guaranteed and may change arbitrarily.
+    pub fn format(&self, g : &Grammar) -> String
+    {
+        let mut s = String::new();
+        s += &format!("Rules unreachable from the root: {} of {}\n", self.unreachable.len(), g.points.len());
+        for i in &self.unreachable
+        {
+            s += &format!("  {}\n", g.points[*i].name);
+        }
+        s += &format!("Rules never referenced: {}\n", self.unreferenced.len());
+        for i in &self.unreferenced
+        {
+            s += &format!("  {}\n", g.points[*i].name);
+        }
+        s += &format!("Literals never matched: {}\n", self.unused_literals.len());
+        for l in &self.unused_literals
+        {
+            s += &format!("  \"{l}\"\n");
+        }
+        s += &format!("Regexes never matched: {}\n", self.unused_regexes.len());
+        for r in &self.unused_regexes
+        {
+            s += &format!("  r`{r}`r\n");
+        }
+        s
+    }
+}
+
+/// Lists the rules that can't be reached from the given root rule, the rules that nothing refers to, and the literals and regexes that the tokenizer looks for but that can only be matched by unreachable rules. Returns `None` if the root rule doesn't exist.
+///
+/// The rule named by `$rename` counts as reachable, even though it's never entered.
+pub fn usage_report(g : &Grammar, root : &str) -> Option<UsageReport>
+{
+    let root = *g.by_name.get(root)?;
+    let callees = |rule : usize| g.points[rule].forms.iter().flat_map(|f| f.matching_terms.iter()).filter_map(|t| match t.t
+    {
+        MatchingTermE::Rule(id) => Some(id),
+        _ => None,
+    });
+    
+    let mut reachable = vec!(false; g.points.len());
+    reachable[root] = true;
+    let mut stack = vec!(root);
+    while let Some(rule) = stack.pop()
+    {
+        for id in callees(rule)
+        {
+            if !reachable[id] { reachable[id] = true; stack.push(id); }
+        }
+    }
+    
+    let mut referenced = vec!(false; g.points.len());
+    referenced[root] = true;
+    for rule in 0..g.points.len()
+    {
+        for id in callees(rule) { referenced[id] |= id != rule; }
+    }
+    
+    let mut literals = HashSet::default();
+    let mut regexes = HashSet::default();
+    for point in g.points.iter().filter(|p| reachable[p.id as usize])
+    {
+        for t in point.forms.iter().flat_map(|f| f.matching_terms.iter())
+        {
+            match &t.t
+            {
+                MatchingTermE::TermLit(s) | MatchingTermE::Peek(_, s) => { literals.insert(&**g.string_cache_inv[*s as usize]); }
+                MatchingTermE::TermRegex(r) | MatchingTermE::PeekR(_, r) | MatchingTermE::PeekRes(_, r) => { regexes.insert(r.pattern()); }
+                _ => {}
+            }
+        }
+    }
+    
+    let mut ret = UsageReport::default();
+    ret.unreachable = (0..g.points.len()).filter(|i| !reachable[*i]).collect();
+    ret.unreferenced = (0..g.points.len()).filter(|i| !referenced[*i]).collect();
+    ret.unused_literals = g.literals.iter().filter(|l| !literals.contains(l.as_str())).cloned().collect();
+    ret.unused_regexes = g.regexes.iter().map(|(_, r)| r.pattern()).filter(|p| !regexes.contains(p))
+        .map(|p| p.trim_start_matches("\\A(?:").trim_end_matches(")\\z").to_string()).collect();
+    ret.unused_regexes.sort();
+    Some(ret)
+}
+
+/// Why [`infer_peeks`] couldn't give an alternation a predicate. See [`PeekConflict`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PeekConflictKind {
+    /// Can start with the same token as a later alternation.
+    Overlap {
+        /// Index of the later alternation.
+        with : usize,
+        /// A literal or regex that both can start with, as written in a grammar.
+        token : String,
+    },
+    /// Can match without consuming any tokens, so the next token doesn't say whether to take it.
+    Empty,
+    /// Can start with any token at all (because of `$any` or a hook).
+    Any,
+}
+
+/// An alternation that needs a predicate written by hand, found by [`infer_peeks`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeekConflict {
+    /// Grammar point ID.
+    pub rule : usize,
+    /// Alternation index.
+    pub alt : usize,
+    /// Why it couldn't be given one.
+    pub kind : PeekConflictKind,
+}
+
+impl PeekConflict {
+    /// Human-readable description, using the given grammar for names. Format is not guaranteed and may change arbitrarily.
+    pub fn describe(&self, g : &Grammar) -> String
+    {
+        let what = match &self.kind
+        {
+            PeekConflictKind::Overlap { with, token } => format!("can start with {token}, like alt {with}"),
+            PeekConflictKind::Empty => "can match nothing".to_string(),
+            PeekConflictKind::Any => "can start with any token".to_string(),
+        };
+        format!("{} alt {} needs a guard: it {what}", g.points[self.rule].name, self.alt)
+    }
+}
+
+// Characters that a regex can start with, and whether it can match nothing, or None if it can't be parsed.
+fn regex_first_chars(pattern : &str) -> Option<(regex_syntax::hir::ClassUnicode, bool)>
+{
+    use regex_syntax::hir::{Hir, HirKind, Class, ClassUnicode, ClassUnicodeRange};
+    fn first(h : &Hir) -> (ClassUnicode, bool)
+    {
+        let one = |c : char| ClassUnicode::new([ClassUnicodeRange::new(c, c)]);
+        match h.kind()
+        {
+            HirKind::Empty | HirKind::Look(_) => (ClassUnicode::empty(), true),
+            HirKind::Literal(l) => match String::from_utf8_lossy(&l.0).chars().next()
+            {
+                Some(c) => (one(c), false),
+                None => (ClassUnicode::empty(), true),
+            },
+            HirKind::Class(Class::Unicode(c)) => (c.clone(), false),
+            HirKind::Class(Class::Bytes(c)) => (ClassUnicode::new(c.ranges().iter().map(|r| ClassUnicodeRange::new(r.start() as char, r.end() as char))), false),
+            HirKind::Repetition(rep) =>
+            {
+                let (chars, empty) = first(&rep.sub);
+                (chars, empty || rep.min == 0)
+            }
+            HirKind::Capture(cap) => first(&cap.sub),
+            HirKind::Concat(items) =>
+            {
+                let mut chars = ClassUnicode::empty();
+                for item in items
+                {
+                    let (c, empty) = first(item);
+                    chars.union(&c);
+                    if !empty { return (chars, false); }
+                }
+                (chars, true)
+            }
+            HirKind::Alternation(items) =>
+            {
+                let mut chars = ClassUnicode::empty();
+                let mut any_empty = false;
+                for item in items
+                {
+                    let (c, empty) = first(item);
+                    chars.union(&c);
+                    any_empty |= empty;
+                }
+                (chars, any_empty)
+            }
+        }
+    }
+    regex_syntax::parse(pattern).ok().map(|h| first(&h))
+}
+
+// Can two regexes match the same token? Only says no if they can't start with the same character.
+fn regexes_overlap(a : &str, b : &str) -> bool
+{
+    if a == b { return true; }
+    match (regex_first_chars(a), regex_first_chars(b))
+    {
+        (Some((mut a, false)), Some((b, false))) =>
+        {
+            a.intersect(&b);
+            a.ranges().len() != 0
+        }
+        _ => true,
+    }
+}
+
+// A literal or regex as written in a grammar.
+fn token_text(g : &Grammar, literal : Option<u32>, regex : Option<&str>) -> String
+{
+    match (literal, regex)
+    {
+        (Some(s), _) => format!("{:?}", g.string_cache_inv[s as usize]),
+        (_, Some(p)) => format!("r`{}`r", p.trim_start_matches("\\A(?:").trim_end_matches(")\\z")),
+        _ => String::new(),
+    }
+}
+
+// Something that both sets can start with, if any.
+fn first_overlap(g : &Grammar, a : &FirstSet, b : &FirstSet, compiled : &mut std::collections::HashMap<String, Option<regex::Regex>>) -> Option<String>
+{
+    let mut matches = |p : &str, s : u32| compiled.entry(p.to_string()).or_insert_with(|| new_regex(p).ok())
+        .as_ref().is_none_or(|r| r.is_match(&g.string_cache_inv[s as usize]));
+    for s in &a.literals
+    {
+        if b.literals.contains(s) || b.regexes.iter().any(|p| matches(p, *s)) { return Some(token_text(g, Some(*s), None)); }
+    }
+    for p in &a.regexes
+    {
+        if b.literals.iter().any(|s| matches(p, *s)) || b.regexes.iter().any(|q| regexes_overlap(p, q)) { return Some(token_text(g, None, Some(p))); }
+    }
+    None
+}
+
+// A guard that accepts exactly the tokens in a FIRST set, at the given position.
+fn first_set_guard(g : &Grammar, set : &FirstSet, k : isize) -> MatchingTerm
+{
+    if let ([s], []) = (&set.literals[..], &set.regexes[..])
+    {
+        return MatchingTermE::Peek(k, *s).to();
+    }
+    let mut alts = set.literals.iter().map(|s| regex::escape(&g.string_cache_inv[*s as usize])).collect::<Vec<_>>();
+    for p in &set.regexes
+    {
+        // A`...`r regexes only match the start of the token
+        alts.push(match p.strip_suffix(")\\z")
+        {
+            Some(p) => format!("(?:{})", p.trim_start_matches("\\A(?:")),
+            None => format!("(?:{})(?s:.*)", p.trim_start_matches("\\A(?:").strip_suffix(")").unwrap_or(p)),
+        });
+    }
+    MatchingTermE::PeekR(k, RegexCacher::new_s(&format!("\\A(?:{})\\z", alts.join("|")))).to()
+}
+
+// FIRST set of the token at position k of a sequence of terms (see terms_first), or None if where it is depends on the input, because something before it can consume more or less than one token.
+fn terms_first_at(terms : &[MatchingTerm], sets : &[FirstSet], lens : &[Option<usize>], k : usize) -> Option<FirstSet>
+{
+    let mut pos = 0;
+    let mut i = 0;
+    while i < terms.len()
+    {
+        let single = match &terms[i].t
+        {
+            MatchingTermE::TermLit(_) | MatchingTermE::TermRegex(_) | MatchingTermE::Directive(MatchDirective::Any) => true,
+            MatchingTermE::Rule(id) => lens[*id] == Some(1) && !sets[*id].empty,
+            MatchingTermE::Hook(_) | MatchingTermE::Directive(MatchDirective::Become | MatchDirective::BecomeAs) => false,
+            MatchingTermE::Directive(MatchDirective::Rename) => { i += 2; continue; }
+            _ => { i += 1; continue; }
+        };
+        // everything before the first token is a guard or directive, and @auto needs its peek
+        if pos == k { return Some(terms_first(&terms[if pos == 0 { 0 } else { i }..], sets)); }
+        if !single { return None; }
+        pos += 1;
+        i += 1;
+    }
+    (pos == k).then(|| terms_first(&[], sets))
+}
+
+/// Gives every alternation of the given rules that doesn't start with a guard, except the last alternation of each rule, a `@peek` or `@peekr` for the tokens that it can start with (see [`first_sets`]), if none of the alternations after it can start with any of them. Returns the alternations that couldn't be given one, which are left alone.
+///
+/// If the first tokens overlap, the tokens at later positions are tried instead, e.g. `@peek(1, "=")` for `name "=" expr | name "(" args ")"`, as long as everything before that position is exactly one token. Conflicts are described in terms of the first token.
+///
+/// A literal and a regex overlap if the regex matches the literal. Two different regexes are assumed to overlap unless they can't start with the same character.
+pub fn infer_peeks(g : &mut Grammar, rules : &[usize]) -> Vec<PeekConflict>
+{
+    let sets = first_sets(g);
+    let lens = max_tokens(g);
+    let mut compiled = std::collections::HashMap::new();
+    let mut ret = vec!();
+    for &rule in rules
+    {
+        let count = g.points[rule].forms.len();
+        let first_at = |alt : usize, k : usize| terms_first_at(&g.points[rule].forms[alt].matching_terms, &sets, &lens, k);
+        let mut guards = vec!();
+        for alt in 0..count.saturating_sub(1)
+        {
+            if g.points[rule].forms[alt].matching_terms.first().is_some_and(|t| t.is_guard()) { continue; }
+            let mut reason = None;
+            let mut guard = None;
+            let mut k = 0;
+            while let Some(first) = first_at(alt, k)
+            {
+                let kind = if first.any { Some(PeekConflictKind::Any) }
+                else if first.empty { Some(PeekConflictKind::Empty) }
+                else
+                {
+                    (alt + 1..count).find_map(|with| match first_at(with, k)
+                    {
+                        Some(other) if other.any => Some(PeekConflictKind::Overlap { with, token : "any token".to_string() }),
+                        Some(other) => first_overlap(g, &first, &other, &mut compiled).map(|token| PeekConflictKind::Overlap { with, token }),
+                        None => Some(PeekConflictKind::Overlap { with, token : "something".to_string() }),
+                    })
+                };
+                match kind
+                {
+                    Some(kind) => { reason.get_or_insert(kind); }
+                    None => { guard = Some(first_set_guard(g, &first, k as isize)); break; }
+                }
+                if first.empty { break; }
+                k += 1;
+            }
+            match (guard, reason)
+            {
+                (Some(guard), _) => guards.push((alt, guard)),
+                (None, Some(kind)) => ret.push(PeekConflict { rule, alt, kind }),
+                (None, None) => {}
+            }
+        }
+        for (alt, guard) in guards
+        {
+            g.points[rule].forms[alt].matching_terms.insert(0, guard);
+        }
+    }
+    ret
+}
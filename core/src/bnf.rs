@@ -0,0 +1,2443 @@
+type HashMap<K, V> = std::collections::HashMap::<K, V, crate::HashBuilder>;
+type HashSet<K> = std::collections::HashSet::<K, crate::HashBuilder>;
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use regex::Regex as Regex;
+//use resharp::Regex as Regex;
+//use regex::bytes::Regex as Regex;
+//use pcre2::bytes::Regex as Regex;
+
+// Yes this is normal for low-level parsers.
+// No the rust stdlib does not have an equivalent function.
+pub (crate) fn get_char_at_byte(s : &str, i : usize) -> char
+{
+    s[i..].chars().next().unwrap()
+}
+/// One-to-one wrapper around Regex::new().
+pub fn new_regex(s : &str) -> Result<Regex, regex::Error>
+//pub fn new_regex(s : &str) -> Result<Regex, resharp::Error>
+//pub (crate) fn new_regex(s : &str) -> Result<Regex, pcre2::Error>
+{
+    //println!("{}", s);
+    Regex::new(s)
+    //regex::RegexBuilder::new(s).unicode(false).build()
+    //pcre2::bytes::RegexBuilder::new().jit(true).build(s)
+}
+
+pub (crate) fn regex_find<'a>(r : &Regex, s : &'a str) -> Option<regex::Match<'a>>
+//pub (crate) fn regex_find<'a>(r : &Regex, s : &'a str) -> Option<resharp::Match>
+//pub (crate) fn regex_find<'a>(r : &Regex, s : &'a str) -> Option<pcre2::bytes::Match<'a>>
+//pub (crate) fn regex_find<'a>(r : &Regex, s : &'a str) -> Option<regex::bytes::Match<'a>>
+{
+    //r.find(s.as_bytes())
+    //r.find(s.as_bytes()).unwrap()
+    r.find(s)
+    //r.find_all(s.as_bytes()).unwrap().into_iter().nth(0)
+}
+#[doc(hidden)]
+pub fn regex_is_match<'a>(r : &Regex, s : &'a str) -> bool
+{
+    //r.is_match(s.as_bytes())
+    //r.is_match(s.as_bytes()).unwrap()
+    r.is_match(s)
+    //r.is_match(s.as_bytes()).unwrap()
+}
+
+/// Is the given interned string one of the grammar's reserved words? Used by generated parsers.
+pub fn is_reserved(g : &Grammar, text : u32) -> bool
+{
+    g.reserved.as_ref().is_some_and(|r| regex_is_match(r, &g.string_cache_inv[text as usize]))
+}
+
+//pub (crate) fn rem_end(r : &resharp::Match) -> usize { r.end }
+//pub (crate) fn rem_start(r : &resharp::Match) -> usize { r.start }
+pub (crate) fn rem_end(r : &regex::Match) -> usize { r.end() }
+pub (crate) fn rem_start(r : &regex::Match) -> usize { r.start() }
+
+
+#[derive(Debug, Clone, Hash)]
+#[doc(hidden)]
+pub struct K<T> {
+    pub (crate) k : Rc<T>
+}
+
+impl<T : PartialEq> PartialEq for K<T> {
+    fn eq(&self, other: &Self) -> bool {
+        if Rc::as_ptr(&self.k) == Rc::as_ptr(&other.k) { return true; }
+        self.k == other.k
+    }
+}
+impl<T : Eq> Eq for K<T> { }
+
+/// Wrapper around a Regex, allowing it to remember whether a given interned `Rc<String>` or `u32` (interned string ID) matches. This is an optimization.
+pub struct RegexCacher {
+    p : String,
+    r : Regex,
+    cache : Rc<RefCell<HashMap<K<String>, bool>>>,
+    cache2 : Rc<RefCell<HashMap<u32, bool>>>,
+    misses : Rc<RegexMisses>,
+}
+
+// Cache miss statistics, for profiling. Reading the clock on every miss is measurably slow, so timing is opt-in.
+#[derive(Default)]
+#[doc(hidden)]
+pub struct RegexMisses {
+    count : Cell<u64>,
+    time : Cell<std::time::Duration>,
+    timed : Cell<bool>,
+}
+
+pub (crate) type RegexCachePool = HashMap<String, (Rc<RefCell<HashMap<K<String>, bool>>>, Rc<RefCell<HashMap<u32, bool>>>, Rc<RegexMisses>)>;
+
+impl std::fmt::Debug for RegexCacher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RegexCacher")
+           .finish() // Must call .finish() to complete the output
+    }
+}
+
+impl Clone for RegexCacher {
+    fn clone(&self) -> Self {
+        Self {
+            p : self.p.clone(),
+            r : new_regex(&self.p).unwrap(),
+            cache : self.cache.clone(),
+            cache2 : self.cache2.clone(),
+            misses : self.misses.clone(),
+        }
+    }
+}
+
+impl RegexCacher {
+    /// Wrap a Regex with a fast-path cache that checks if a given known input is already known.
+    ///
+    /// This is faster than whatever caching Regex does internally because of string interning.
+    #[allow(unused)]
+    pub fn new(s : String, r : Regex) -> RegexCacher
+    {
+        let cache = Rc::new(RefCell::new(HashMap::default()));
+        let cache2 = Rc::new(RefCell::new(HashMap::default()));
+        RegexCacher { p : s, r, cache, cache2, misses : <_>::default() }
+    }
+    #[allow(unused)]
+    /// Build one from a `&str`.
+    pub fn new_s(s : &str) -> RegexCacher
+    {
+        let r = new_regex(s).unwrap();
+        let cache = Rc::new(RefCell::new(HashMap::default()));
+        let cache2 = Rc::new(RefCell::new(HashMap::default()));
+        RegexCacher { p : s.to_string(), r, cache, cache2, misses : <_>::default() }
+    }
+    pub (crate) fn new_with_pool(s : String, r : Regex, cache_pool : &mut RegexCachePool) -> RegexCacher
+    {
+        let mut cache = Rc::new(RefCell::new(HashMap::default()));
+        let mut cache2 = Rc::new(RefCell::new(HashMap::default()));
+        let mut misses = <_>::default();
+        if let Some(cached) = cache_pool.get(&s)
+        {
+            cache = Rc::clone(&cached.0);
+            cache2 = Rc::clone(&cached.1);
+            misses = Rc::clone(&cached.2);
+        }
+        else
+        {
+            cache_pool.insert(s.clone(), (cache.clone(), cache2.clone(), Rc::clone(&misses)));
+        }
+        
+        RegexCacher { p : s, r, cache, cache2, misses }
+    }
+    /// The regex's source pattern.
+    pub fn pattern(&self) -> &str { &self.p }
+    /// How many times the cache missed (i.e. the regex actually had to run), and how much time was spent running it. Shared between all copies of the same regex within a grammar.
+    ///
+    /// Time is only measured after turning it on with [`RegexCacher::set_miss_timing`].
+    pub fn cache_misses(&self) -> (u64, std::time::Duration) { (self.misses.count.get(), self.misses.time.get()) }
+    /// Turn timing of cache misses on or off. See [`RegexCacher::cache_misses`].
+    pub fn set_miss_timing(&self, timed : bool) { self.misses.timed.set(timed); }
+    #[inline(never)]
+    fn run_uncached(&self, s : &str) -> bool
+    {
+        self.misses.count.set(self.misses.count.get() + 1);
+        if !self.misses.timed.get() { return regex_is_match(&self.r, s); }
+        let start = std::time::Instant::now();
+        let ret = regex_is_match(&self.r, s);
+        self.misses.time.set(self.misses.time.get() + start.elapsed());
+        ret
+    }
+    /// Does the regex match the string?
+    #[inline(never)]
+    pub fn is_match(&self, s : &Rc<String>) -> bool
+    {
+        let mut cache = self.cache.borrow_mut();
+        let k = K { k : Rc::clone(s) };
+        if let Some(result) = cache.get(&k) { return *result; }
+        let ret = self.run_uncached(s);
+        cache.insert(k, ret);
+        ret
+    }
+    /// Does the regex match the string based on its interned ID? See also: [`Grammar::string_cache_inv`]
+    #[inline(never)]
+    pub fn is_match_interned(&self, i : u32, string_cache_inv : &Vec<Rc<String>>) -> bool
+    {
+        let mut cache = self.cache2.borrow_mut();
+        if let Some(result) = cache.get(&i) { return *result; }
+        let s = &string_cache_inv[i as usize];
+        let ret = self.run_uncached(s);
+        cache.insert(i, ret);
+        ret
+    }
+}
+
+#[derive(Default)]
+/// Produced by [`bnf_to_grammar`].
+///
+/// Next step: [`tokenize`].
+pub struct Grammar {
+    /// Arena for all grammar points in the grammar.
+    pub points: Vec<GrammarPoint>,
+    /// Lookup table for grammar point IDs (indexes in the arena) by their name.
+    pub by_name: HashMap<String, usize>,
+    
+    #[doc(hidden)]
+    pub literals: Vec<String>,
+    #[doc(hidden)]
+    pub regexes: Vec<(Regex, RegexCacher)>,
+    
+    /// String interning cache: from string to interned ID.
+    pub string_cache : HashMap<String, u32>,
+    /// Inverse string interning cache. Index = string ID. The given `Rc<String>` is the canonical object for that interned string.
+    pub string_cache_inv : Vec<Rc<String>>,
+    
+    pub (crate) bracket_pairs : Vec<(String, String)>,
+    #[doc(hidden)]
+    pub comments : Vec<String>,
+    #[doc(hidden)]
+    pub comment_pairs : Vec<(String, String)>,
+    #[doc(hidden)]
+    pub comment_pairs_nested : Vec<(String, String)>,
+    #[doc(hidden)]
+    pub comment_regexes : Vec<Regex>,
+    #[doc(hidden)]
+    pub reserved : Option<Regex>,
+    pub (crate) reserved_words : Vec<String>,
+    
+    // sorted names of every guard and hook used, so parsing can check for them without going through the whole grammar
+    #[doc(hidden)]
+    pub used_guards : Vec<String>,
+    #[doc(hidden)]
+    pub used_hooks : Vec<String>,
+    
+    // rules as they were before templates and EBNF sugar were expanded, for building overlays on top of
+    pub (crate) source : Vec<(String, Vec<Vec<String>>)>,
+}
+
+#[derive(Debug, Clone)]
+/// A grammar rule.
+/// 
+/// More specifically, every production/alternation associated with a given name, in order. Alternations are stored and tested in the same order as written in the grammar.
+pub struct GrammarPoint {
+    /// Name of the grammar point (LHS).
+    pub name: Rc<String>,
+    /// Interned string ID of the grammar point's name, see [`Grammar::string_cache_inv`].
+    pub name_id: u32,
+    /// ID of the grammar point (index in [`Grammar::points`]).
+    pub id: u32,
+    /// List of productions/alternations under this grammar point's LHS
+    pub forms: Vec<Alternation>,
+    #[doc(hidden)]
+    pub recover: Option<(RegexCacher, bool)>,
+}
+
+#[derive(Debug, Clone)]
+/// A particular production of a grammar rule.
+///
+/// Named "Alternation" because they're tested in order.
+pub struct Alternation {
+    /// List of terms, in order, for this alternation.
+    pub matching_terms: Vec<MatchingTerm>,
+    /// Does this alternation want terminals to be added to it, or dropped?
+    #[doc(hidden)]
+    pub pruned: bool,
+}
+
+#[derive(Debug, Clone)]
+#[doc(hidden)]
+pub enum MatchDirective {
+    Any, Become, BecomeAs, Hoist, HoistIfUnit, Drop, DropIfEmpty, Rename,
+}
+
+impl MatchDirective {
+    pub(crate) fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            MatchDirective::Any => "$any",
+            MatchDirective::Become => "$become",
+            MatchDirective::BecomeAs => "$become_as",
+            MatchDirective::Hoist => "$hoist",
+            MatchDirective::HoistIfUnit => "$hoist_unit",
+            MatchDirective::Drop => "$drop",
+            MatchDirective::DropIfEmpty => "$drop_empty",
+            MatchDirective::Rename => "$rename",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Intentionally opaque for API stability reasons. Don't worry, it's just a single enum internally.
+pub struct MatchingTerm { #[doc(hidden)] pub t : MatchingTermE }
+
+#[derive(Debug, Clone)]
+#[doc(hidden)]
+pub enum MatchingTermE {
+    Rule(usize),
+    TermLit(u32),
+    TermRegex(RegexCacher),
+    Directive(MatchDirective),
+    Hook(Rc<String>),
+    _AutoTemp,
+    
+    Eof,
+    Peek(isize, u32),
+    PeekR(isize, RegexCacher),
+    PeekRes(isize, RegexCacher),
+    Guard(Rc<String>),
+}
+impl MatchingTermE { pub(crate) fn to(self) -> MatchingTerm { MatchingTerm { t : self } } }
+
+impl MatchingTerm {
+    /// Can this term decide whether its alternation gets chosen (when it's the first term)?
+    pub(crate) fn is_guard(&self) -> bool
+    {
+        matches!(self.t, MatchingTermE::Peek(..) | MatchingTermE::PeekR(..) | MatchingTermE::PeekRes(..) | MatchingTermE::Guard(_) | MatchingTermE::Eof)
+    }
+    /// Short human-readable description of this term, for use in reports and error messages. Format is not guaranteed and may change arbitrarily.
+    pub fn describe(&self, g : &Grammar) -> String
+    {
+        match &self.t
+        {
+            MatchingTermE::Rule(id) => g.points[*id].name.to_string(),
+            MatchingTermE::TermLit(s) => format!("{:?}", g.string_cache_inv[*s as usize]),
+            MatchingTermE::TermRegex(r) => format!("regex {:?}", r.p),
+            MatchingTermE::Directive(d) => d.as_str().to_string(),
+            MatchingTermE::Hook(name) => format!("!hook({name})"),
+            MatchingTermE::_AutoTemp => "@auto".to_string(),
+            MatchingTermE::Eof => "@eof".to_string(),
+            MatchingTermE::Peek(n, s) => format!("@peek({n}, {:?})", g.string_cache_inv[*s as usize]),
+            MatchingTermE::PeekR(n, r) => format!("@peekr({n}, regex {:?})", r.p),
+            MatchingTermE::PeekRes(n, r) => format!("@peekres({n}, regex {:?})", r.p),
+            MatchingTermE::Guard(name) => format!("@guard({name})"),
+        }
+    }
+}
+
+/// Look up a string in the string interning cache.
+pub fn string_cache_lookup(
+    string_cache : &mut HashMap<String, u32>,
+    string_cache_inv : &mut Vec<Rc<String>>,
+    s : &str) -> (Rc<String>, u32)
+{
+    if let Some(sn) = string_cache.get(s)
+    {
+        return (Rc::clone(&string_cache_inv[*sn as usize]), *sn);
+    }
+    let rc = Rc::new(s.to_string());
+    let n = string_cache_inv.len().try_into().unwrap();
+    string_cache.insert(s.to_string(), n);
+    string_cache_inv.push(Rc::clone(&rc));
+    (rc, n)
+}
+/// Look up a string in the string interning cache, but skip the `Rc::clone()` overhead because we only need the ID.
+pub fn string_cache_lookup_id(
+    string_cache : &mut HashMap<String, u32>,
+    string_cache_inv : &mut Vec<Rc<String>>,
+    s : &str) -> u32
+{
+    if let Some(sn) = string_cache.get(s)
+    {
+        return *sn;
+    }
+    let rc = Rc::new(s.to_string());
+    let n = string_cache_inv.len().try_into().unwrap();
+    string_cache.insert(s.to_string(), n);
+    string_cache_inv.push(rc);
+    n
+}
+
+// Rules with special meaning to the tokenizer (or, for __INFER_PEEKS, to grammar_convert). Their contents are bare strings, not grammar terms.
+#[doc(hidden)]
+pub fn is_magic_rule(name : &str) -> bool
+{
+    matches!(name, "__BRACKET_PAIRS" | "__COMMENT_PAIRS" | "__COMMENT_PAIRS_NESTED" | "__COMMENT_REGEXES" | "__COMMENTS" | "__RESERVED_WORDS" | "__INFER_PEEKS")
+}
+
+/// What kind of mistake a [`GrammarError`] is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum GrammarErrorKind {
+    /// Malformed BNF, like a missing `::=` or an unclosed group.
+    Syntax,
+    /// A string literal that's empty or never closed.
+    BrokenLiteral,
+    /// A regex literal that's never closed with `` `r ``.
+    UnterminatedRegex,
+    /// A regex that doesn't compile.
+    InvalidRegex,
+    /// Malformed `@peek`, `@peekr`, `@peekres`, `@guard`, `@recover`, `@auto` or `!hook`.
+    BadPredicate,
+    /// A reference to a rule that isn't defined.
+    UnknownRule,
+    /// A rule that's defined more than once.
+    DuplicateRule,
+    /// A guard or directive somewhere that it can't work, like `$become` without a rule name after it.
+    Placement,
+    /// A misused template or `$sep_by`.
+    Template,
+    /// A problem with `%include`, `%import`, `%append`, `%prepend` or `%replace`, including the loader failing.
+    Include,
+    /// An alternation that `$infer_peeks` or `__INFER_PEEKS` couldn't give a predicate, because the tokens it starts with don't tell it apart from a later one.
+    PeekConflict,
+    /// Anything else, like size limits.
+    Other,
+}
+
+/// Error from loading a grammar, e.g. with [`bnf_to_grammar`].
+///
+/// Converts to and from `String`, for code that only needs the message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrammarError {
+    /// What kind of mistake it is.
+    pub kind : GrammarErrorKind,
+    /// Human-readable error message, without the location. Format is not guaranteed and may change arbitrarily.
+    pub err_message : String,
+    /// Name of the rule that the mistake is in, if known.
+    pub rule : Option<String>,
+    /// The `%include`d or `%import`ed file that the mistake is in, as given to the loader, or `None` for the grammar source itself.
+    pub file : Option<String>,
+    /// Line of the mistake (1-indexed), or 0 if unknown. Lines continued with `\` count as the line they started on.
+    pub line : usize,
+    /// Column of the mistake, in characters (1-indexed), or 0 if unknown.
+    pub column : usize,
+    /// The offending text, e.g. a term, or empty if unknown.
+    pub snippet : String,
+}
+
+impl GrammarError {
+    #[doc(hidden)]
+    pub fn new(kind : GrammarErrorKind, err_message : String) -> Self
+    {
+        Self { kind, err_message, rule : None, file : None, line : 0, column : 0, snippet : String::new() }
+    }
+    #[doc(hidden)]
+    pub fn in_rule(mut self, rule : &str) -> Self
+    {
+        self.rule.get_or_insert(rule.to_string());
+        self
+    }
+    #[doc(hidden)]
+    pub fn at(mut self, snippet : &str) -> Self
+    {
+        self.snippet = snippet.to_string();
+        self
+    }
+    // Fills in the line and column of the snippet, if they're unknown, by looking for it in the definition of the rule in the given source.
+    pub (crate) fn locate(mut self, source : &str) -> Self
+    {
+        let Some(rule) = &self.rule else { return self };
+        if self.line != 0 || self.snippet.is_empty() || self.file.is_some() { return self; }
+        // rules made from EBNF groups and template instances are found in the rule they came from
+        let rule = match rule.find("__") { Some(i) if i > 0 => &rule[..i], _ => rule };
+        let rule = rule.split(['(', '<']).next().unwrap_or_default();
+        // name of the rule that a line starts the definition of, if any
+        fn defined_rule(line : &str) -> Option<&str>
+        {
+            let split = line.split_whitespace().collect::<Vec<_>>();
+            let split = if split.first().is_some_and(|x| matches!(*x, "%append" | "%prepend" | "%replace")) { &split[1..] } else { &split[..] };
+            if !split.contains(&"::=") { return None; }
+            split.first().map(|x| x.split('(').next().unwrap_or_default())
+        }
+        let mut in_rule = false;
+        for (i, line) in source.lines().enumerate()
+        {
+            let mut text = line;
+            if let Some(name) = defined_rule(line)
+            {
+                in_rule = name == rule;
+                if let Some(sep) = line.find("::=") { text = &line[sep..]; }
+            }
+            if let Some(pos) = text.find(&*self.snippet) && in_rule
+            {
+                let pos = pos + (line.len() - text.len());
+                self.line = i + 1;
+                self.column = line[..pos].chars().count() + 1;
+                break;
+            }
+        }
+        self
+    }
+}
+
+impl std::fmt::Display for GrammarError {
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        write!(f, "{}", self.err_message)?;
+        match (self.line, &self.file)
+        {
+            (0, Some(file)) => write!(f, " (in {file})"),
+            (0, None) => Ok(()),
+            (line, Some(file)) => write!(f, " (line {line}, column {} of {file})", self.column),
+            (line, None) => write!(f, " (line {line}, column {})", self.column),
+        }
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+impl From<String> for GrammarError {
+    fn from(err_message : String) -> Self { Self::new(GrammarErrorKind::Other, err_message) }
+}
+
+impl From<GrammarError> for String {
+    fn from(e : GrammarError) -> Self { e.to_string() }
+}
+
+// Length of the literal at the start of `rest`, including both quotes, or None if it's never closed.
+fn bnf_literal_len(rest : &str) -> Option<usize>
+{
+    let mut in_escape = false;
+    for (i, c) in rest.char_indices().skip(1)
+    {
+        if !in_escape && c == '"' { return Some(i + 1); }
+        in_escape = !in_escape && c == '\\';
+    }
+    None
+}
+
+// Length of the name at the start of `rest`. Brackets only end names outside of magic rules.
+fn bnf_name_len(rest : &str, brackets : bool) -> usize
+{
+    // template instance names (like `list<item,",">`) can contain anything between their angle brackets
+    let mut angle = 0;
+    let mut in_quote = false;
+    let mut in_escape = false;
+    for (i, ch) in rest.char_indices()
+    {
+        if angle > 0
+        {
+            if in_quote && !in_escape && ch == '\\' { in_escape = true; continue; }
+            if ch == '"' && !in_escape { in_quote = !in_quote; }
+            if !in_quote && ch == '<' { angle += 1; }
+            if !in_quote && ch == '>' { angle -= 1; }
+            in_escape = false;
+            continue;
+        }
+        if ch == '<' && i > 0 && rest[i..].contains('>')
+        {
+            angle = 1;
+            continue;
+        }
+        if ch.is_whitespace() || ch == '|' || ch == '(' || ch == ')' || ch == ',' || ch == '"' || ch == '#'
+            || (brackets && (ch == '[' || ch == ']'))
+            || rest[i..].starts_with("::=") || rest[i..].starts_with("r`")
+        {
+            return i;
+        }
+    }
+    rest.len()
+}
+
+// Splits BNF source into rules: name, the line the rule starts on, and its alternations' terms.
+pub (crate) fn bnf_parse(input: &str) -> Result<Vec<(String, usize, Vec<Vec<String>>)>, GrammarError>
+{
+    let mut rules = Vec::new();
+    let mut name_line = 0;
+    
+    let mut metalist = Vec::new();
+    let mut current = Vec::new();
+    
+    let mut name : Option<String> = None;
+    let mut found_separator = false;
+    // errors for the groups that are open right now, at their opening brackets, for EBNF sugar; magic rules don't have groups
+    let mut groups : Vec<GrammarError> = vec!();
+    let mut magic = false;
+    
+    let lines = input.lines().map(|x| x.to_string()).collect::<Vec<_>>();
+    // (line number, text); continued lines keep the number of the line they started on
+    let mut lines2 : Vec<(usize, String)> = vec!();
+    for (i, l) in lines.into_iter().enumerate()
+    {
+        if let Some((_, l0)) = lines2.last_mut()
+        {
+            if l0.ends_with("\\")
+            {
+                *l0 = format!("{}{l}", &l0[..l0.len()-1]);
+                continue;
+            }
+        }
+        
+        lines2.push((i + 1, l)); // user-facing line numbers are 1-indexed
+    }
+    for (linenum, line) in lines2.iter()
+    {
+        let mut rest : &str = line;
+        let linenum = *linenum;
+        // error at the start of `rest`, with the given snippet (or its first word)
+        let err = |kind, msg : &str, rest : &str, snippet : Option<&str>|
+        {
+            let mut e = GrammarError::new(kind, msg.to_string());
+            e.line = linenum;
+            e.column = line[..line.len() - rest.len()].chars().count() + 1;
+            e.snippet = snippet.unwrap_or(rest.split_whitespace().next().unwrap_or_default()).to_string();
+            e
+        };
+        
+        let _split = rest.trim().split_whitespace().collect::<Vec<_>>();
+        
+        // ::= is only allowed as the second token on a line and must be space-separated
+        // (or after a template's parameter list, like `list(X, SEP) ::=`)
+        let sep_pos = _split.iter().position(|x| *x == "::=");
+        if sep_pos == Some(1) || sep_pos.is_some_and(|p| p > 1 && _split[0].contains('(') && !_split[0].starts_with('(') && _split[p - 1].ends_with(')'))
+        {
+            if !groups.is_empty() { return Err(groups.swap_remove(0)); }
+            if name.is_some()
+            {
+                metalist.push(current);
+                rules.push((name.unwrap(), name_line, metalist));
+            }
+            
+            name = None;
+            found_separator = false;
+            metalist = Vec::new();
+            current = Vec::new();
+        }
+        
+        while !rest.is_empty()
+        {
+            // skip extra whitespace
+            if get_char_at_byte(rest, 0).is_whitespace()
+            {
+                rest = rest.trim_start();
+            }
+            // comment
+            else if rest.starts_with("#")
+            {
+                break;
+            }
+            // literal
+            else if rest.starts_with("\"")
+            {
+                if !found_separator { return Err(err(GrammarErrorKind::Syntax, "Missing ::=", rest, None)); }
+                let len = match bnf_literal_len(rest)
+                {
+                    Some(len) if len > 2 => len,
+                    len => return Err(err(GrammarErrorKind::BrokenLiteral, "Broken literal text rule", rest, Some(&rest[..len.unwrap_or(rest.len())]))),
+                };
+                current.push(rest[..len].to_string());
+                rest = &rest[len..];
+            }
+            // regex
+            else if rest.starts_with("r`") || rest.starts_with("R`") || rest.starts_with("A`")
+            {
+                if !found_separator { return Err(err(GrammarErrorKind::Syntax, "Missing ::=", rest, None)); }
+                let end = rest[2..].find("`r").ok_or_else(|| err(GrammarErrorKind::UnterminatedRegex, "Unterminated regex", rest, Some(rest)))?;
+                let len = end + 4;
+                current.push(rest[..len].to_string());
+                rest = &rest[len..];
+            }
+            // split
+            else if rest.starts_with("::=")
+            {
+                if found_separator { return Err(err(GrammarErrorKind::Syntax, "Unexpected ::=", rest, Some("::="))); }
+                if name.is_none() { return Err(err(GrammarErrorKind::Syntax, "Missing name", rest, Some("::="))); }
+                found_separator = true;
+                rest = &rest[3..];
+            }
+            // template parameters
+            else if rest.starts_with("(") && !found_separator && name.as_ref().is_some_and(|n| !n.contains('('))
+            {
+                let end = rest.find(")").ok_or_else(|| err(GrammarErrorKind::Template, "Unclosed template parameter list", rest, Some(rest)))?;
+                let params = rest[1..end].split(",").map(|x| x.trim()).collect::<Vec<_>>();
+                if params.iter().any(|x| x.is_empty() || !x.chars().all(|c| c.is_alphanumeric() || c == '_'))
+                {
+                    return Err(err(GrammarErrorKind::Template, "Template parameters must be plain names, separated by commas", rest, Some(&rest[..end + 1])));
+                }
+                name = Some(format!("{}({})", name.unwrap(), params.join(",")));
+                rest = &rest[end + 1..];
+            }
+            // groups
+            else if !magic && (rest.starts_with("(") || rest.starts_with("["))
+            {
+                if !found_separator { return Err(err(GrammarErrorKind::Syntax, "Missing ::=", rest, None)); }
+                let rule = name.clone().unwrap_or_default();
+                groups.push(err(GrammarErrorKind::Syntax, &format!("Unclosed group in rule {rule}"), rest, Some(&rest[0..1])).in_rule(&rule));
+                current.push(rest[0..1].to_string());
+                rest = &rest[1..];
+            }
+            else if !magic && (rest.starts_with(")") || rest.starts_with("]"))
+            {
+                if groups.pop().is_none() { return Err(err(GrammarErrorKind::Syntax, &format!("Unexpected {}", &rest[0..1]), rest, Some(&rest[0..1]))); }
+                current.push(rest[0..1].to_string());
+                rest = &rest[1..];
+                // repetition operators must directly follow the group
+                if rest.starts_with("*") || rest.starts_with("+") || rest.starts_with("?")
+                {
+                    current.push(rest[0..1].to_string());
+                    rest = &rest[1..];
+                }
+            }
+            // operators
+            else if  rest.starts_with("(") || rest.starts_with(")") || rest.starts_with(",")
+            {
+                current.push(rest[0..1].to_string());
+                rest = &rest[1..];
+            }
+            else if rest.starts_with("|")
+            {
+                if !found_separator { return Err(err(GrammarErrorKind::Syntax, "Missing ::=", rest, None)); }
+                if !groups.is_empty()
+                {
+                    current.push("|".to_string());
+                }
+                else
+                {
+                    metalist.push(current);
+                    current = vec!();
+                }
+                rest = &rest[1..];
+            }
+            // name
+            else
+            {
+                let end = bnf_name_len(rest, !magic);
+                if name.is_none()
+                {
+                    name = Some(rest[..end].to_string());
+                    name_line = linenum;
+                    magic = is_magic_rule(&rest[..end]);
+                }
+                else
+                {
+                    if !found_separator { return Err(err(GrammarErrorKind::Syntax, "Missing ::=", rest, None)); }
+                    current.push(rest[..end].to_string());
+                }
+                rest = &rest[end..];
+            }
+        }
+    }
+    
+    if !groups.is_empty()
+    {
+        return Err(groups.swap_remove(0));
+    }
+    if name.is_some()
+    {
+        metalist.push(current);
+        rules.push((name.unwrap(), name_line, metalist));
+    }
+    
+    Ok(rules)
+}
+
+const MAX_TEMPLATE_INSTANCES : usize = 10000;
+const MAX_TEMPLATE_NESTING : usize = 32;
+
+struct Templates {
+    // template name -> (parameters, forms)
+    defs : HashMap<String, (Vec<String>, Vec<Vec<String>>)>,
+    // instance name -> how deeply template calls are nested in its arguments
+    instances : HashMap<String, usize>,
+    // instance name, template name, arguments
+    queue : Vec<(String, String, Vec<String>)>,
+}
+
+impl Templates {
+    // Replaces every template call (`name(arg, arg)`) in an alternation with the name of the instance it refers to, queueing new instances.
+    fn expand_alt(&mut self, rule : &str, toks : &[String]) -> Result<Vec<String>, String>
+    {
+        let mut ret = vec!();
+        let mut i = 0;
+        while i < toks.len()
+        {
+            let t = &toks[i];
+            i += 1;
+            if !self.defs.contains_key(t) { ret.push(t.clone()); continue; }
+            if toks.get(i).map(|x| &**x) != Some("(") { return Err(format!("Template {t} used without arguments (in rule {rule})")); }
+            
+            // split arguments on top-level commas
+            let mut args = vec!(vec!());
+            let mut depth = 0;
+            i += 1;
+            loop
+            {
+                let a = toks.get(i).ok_or_else(|| format!("Unclosed argument list for template {t} (in rule {rule})"))?;
+                i += 1;
+                match &**a
+                {
+                    ")" if depth == 0 => break,
+                    "," if depth == 0 => args.push(vec!()),
+                    _ =>
+                    {
+                        if a == "(" { depth += 1; }
+                        if a == ")" { depth -= 1; }
+                        args.last_mut().unwrap().push(a.clone());
+                    }
+                }
+            }
+            let mut expanded = vec!();
+            for arg in args
+            {
+                let arg = self.expand_alt(rule, &arg)?;
+                if arg.len() != 1 { return Err(format!("Template arguments must be a single rule name, literal, or template call; got `{}` (in rule {rule})", arg.join(" "))); }
+                expanded.push(arg.into_iter().next().unwrap());
+            }
+            let params = &self.defs[t].0;
+            if expanded.len() != params.len() { return Err(format!("Template {t} takes {} arguments, but was given {} (in rule {rule})", params.len(), expanded.len())); }
+            
+            let instance = format!("{t}<{}>", expanded.join(","));
+            if !self.instances.contains_key(&instance)
+            {
+                let nesting = 1 + expanded.iter().filter_map(|a| self.instances.get(a)).max().unwrap_or(&0);
+                if self.instances.len() >= MAX_TEMPLATE_INSTANCES || nesting > MAX_TEMPLATE_NESTING
+                {
+                    return Err(format!("Too many or too deeply nested template instances; is template {t} infinitely recursive? (in rule {rule})"));
+                }
+                self.instances.insert(instance.clone(), nesting);
+                self.queue.push((instance.clone(), t.clone(), expanded));
+            }
+            ret.push(instance);
+        }
+        Ok(ret)
+    }
+}
+
+// Expands rule templates (`list(X, SEP) ::= X $become list_tail(X, SEP)`) into concrete rules, one per distinct set of arguments.
+// Instances are named like `list<item,",">`. Templates that are never used don't produce any rules.
+pub (crate) fn bnf_expand_templates(input : Vec<(String, Vec<Vec<String>>)>) -> Result<Vec<(String, Vec<Vec<String>>)>, String>
+{
+    let mut t = Templates { defs : <_>::default(), instances : <_>::default(), queue : vec!() };
+    let mut rules = vec!();
+    for (name, forms) in input
+    {
+        if let Some((base, params)) = name.split_once('(')
+        {
+            let params = params.trim_end_matches(')').split(",").map(|x| x.to_string()).collect::<Vec<_>>();
+            if t.defs.insert(base.to_string(), (params, forms)).is_some() { return Err(format!("Duplicate template {base}")); }
+        }
+        else
+        {
+            rules.push((name, forms));
+        }
+    }
+    if t.defs.is_empty() { return Ok(rules); }
+    if let Some((name, _)) = rules.iter().find(|(name, _)| t.defs.contains_key(name))
+    {
+        return Err(format!("{name} is defined both as a template and as a plain rule"));
+    }
+    
+    let mut ret = vec!();
+    for (name, forms) in rules
+    {
+        if is_magic_rule(&name) { ret.push((name, forms)); continue; }
+        let forms = forms.iter().map(|alt| t.expand_alt(&name, alt)).collect::<Result<Vec<_>, _>>()?;
+        ret.push((name, forms));
+    }
+    while let Some((instance, template, args)) = t.queue.pop()
+    {
+        let (params, forms) = t.defs[&template].clone();
+        let mut new_forms = vec!();
+        for alt in forms
+        {
+            let alt = alt.into_iter().map(|x| params.iter().position(|p| *p == x).map(|i| args[i].clone()).unwrap_or(x)).collect::<Vec<_>>();
+            new_forms.push(t.expand_alt(&instance, &alt)?);
+        }
+        ret.push((instance, new_forms));
+    }
+    Ok(ret)
+}
+
+#[derive(Clone, Debug)]
+enum EbnfItem {
+    Term(String),
+    // alternatives, and one of ' ' (plain group), '?', '*', '+'
+    Group(Vec<Vec<EbnfItem>>, char),
+    SepBy(SepBy),
+}
+
+#[derive(Clone, Debug)]
+struct SepBy {
+    element : String,
+    separator : String,
+    // the token that follows the list; needed to tell where an empty list or trailing separator ends
+    end : Option<String>,
+    trailing : bool,
+    empty : bool,
+}
+
+fn is_literal_or_regex(term : &str) -> bool
+{
+    term.starts_with("\"") || term.starts_with("r`") || term.starts_with("R`") || term.starts_with("A`")
+}
+
+// Parses the arguments of `$sep_by(element, separator[, end[, trailing][, empty]])`, including the parens.
+fn parse_sep_by(rule : &str, args : &[String]) -> Result<SepBy, String>
+{
+    let args = args[1..args.len() - 1].split(|x| x == ",").collect::<Vec<_>>();
+    let usage = "$sep_by(element, separator) or $sep_by(element, separator, end, [trailing], [empty])";
+    if args.iter().any(|a| a.len() != 1) || args.len() < 2 || args.len() > 5
+    {
+        return Err(format!("Malformed $sep_by in rule {rule}; expected {usage}"));
+    }
+    let element = args[0][0].clone();
+    if element.starts_with("$") || element.starts_with("@") || element.starts_with("!") || matches!(&*element, "(" | ")" | "[" | "]" | "|")
+    {
+        return Err(format!("The element of a $sep_by must be a rule name or a literal, not `{element}` (in rule {rule})"));
+    }
+    let separator = args[1][0].clone();
+    if !is_literal_or_regex(&separator) { return Err(format!("The separator of a $sep_by must be a string literal or regex literal, not `{separator}` (in rule {rule})")); }
+    let end = args.get(2).map(|a| a[0].clone());
+    if let Some(end) = &end && !is_literal_or_regex(end)
+    {
+        return Err(format!("The end of a $sep_by must be a string literal or regex literal, not `{end}` (in rule {rule})"));
+    }
+    let (mut trailing, mut empty) = (false, false);
+    for flag in args.iter().skip(3).map(|a| &*a[0])
+    {
+        match flag
+        {
+            "trailing" | "TRAILING" => trailing = true,
+            "empty" | "EMPTY" => empty = true,
+            _ => return Err(format!("Unknown $sep_by option `{flag}` in rule {rule}; expected trailing or empty")),
+        }
+    }
+    Ok(SepBy { element, separator, end, trailing, empty })
+}
+
+// Terms whose parenthesized arguments aren't groups.
+fn takes_arguments(term : &str) -> bool
+{
+    matches!(term, "@peek" | "@PEEK" | "@peekr" | "@PEEKR" | "@peekres" | "@PEEKRES" | "@guard" | "@GUARD" | "!hook" | "!HOOK" | "$sep_by" | "$SEP_BY")
+}
+fn is_predicate_term(term : &str) -> bool
+{
+    (takes_arguments(term) && term.starts_with("@")) || matches!(term, "@eof" | "@EOF" | "@auto" | "@AUTO")
+}
+
+// Parses a flat alternation into a tree of groups, up to the given closing bracket.
+fn ebnf_parse_group(rule : &str, toks : &[String], i : &mut usize, close : Option<&str>) -> Result<Vec<Vec<EbnfItem>>, String>
+{
+    let mut alts = vec!(vec!());
+    while *i < toks.len()
+    {
+        let t = &toks[*i];
+        *i += 1;
+        if matches!(&**t, "$sep_by" | "$SEP_BY")
+        {
+            let start = *i;
+            while *i < toks.len() && toks[*i] != ")" { *i += 1; }
+            if toks.get(start).map(|x| &**x) != Some("(") || *i == toks.len()
+            {
+                return Err(format!("Malformed $sep_by in rule {rule}; expected $sep_by(element, separator)"));
+            }
+            *i += 1;
+            alts.last_mut().unwrap().push(EbnfItem::SepBy(parse_sep_by(rule, &toks[start..*i])?));
+        }
+        else if takes_arguments(t) && toks.get(*i).map(|x| &**x) == Some("(")
+        {
+            alts.last_mut().unwrap().push(EbnfItem::Term(t.clone()));
+            while *i < toks.len()
+            {
+                alts.last_mut().unwrap().push(EbnfItem::Term(toks[*i].clone()));
+                *i += 1;
+                if toks[*i - 1] == ")" { break; }
+            }
+        }
+        else if t == "(" || t == "["
+        {
+            let inner = ebnf_parse_group(rule, toks, i, Some(if t == "(" { ")" } else { "]" }))?;
+            let mut kind = if t == "[" { '?' } else { ' ' };
+            if let Some(op @ ("*" | "+" | "?")) = toks.get(*i).map(|x| &**x)
+            {
+                if t == "[" { return Err(format!("[ ] is already optional and can't be followed by {op} (in rule {rule})")); }
+                kind = op.chars().next().unwrap();
+                *i += 1;
+            }
+            alts.last_mut().unwrap().push(EbnfItem::Group(inner, kind));
+        }
+        else if t == ")" || t == "]"
+        {
+            if close != Some(&**t) { return Err(format!("Mismatched brackets in rule {rule}: unexpected {t}")); }
+            return Ok(alts);
+        }
+        else if t == "|"
+        {
+            alts.push(vec!());
+        }
+        else if matches!(&**t, "*" | "+" | "?")
+        {
+            return Err(format!("{t} must directly follow a group (in rule {rule})"));
+        }
+        else
+        {
+            alts.last_mut().unwrap().push(EbnfItem::Term(t.clone()));
+        }
+    }
+    if let Some(close) = close { return Err(format!("Unclosed group in rule {rule}: missing {close}")); }
+    Ok(alts)
+}
+
+struct Desugarer<'a> {
+    rule : &'a str,
+    out : Vec<(String, Vec<Vec<String>>)>,
+    counter : usize,
+    // a tail-position group can be $become'd into instead of called and hoisted, but that would lose the rule's @recover
+    can_become : bool,
+    // generated rules get $infer_peeks too, so their alternatives don't need predicates
+    infer : bool,
+}
+
+fn is_flag_term(t : &str) -> bool
+{
+    matches!(t, "$pruned" | "$PRUNED" | "$infer_peeks" | "$INFER_PEEKS")
+}
+
+impl<'a> Desugarer<'a> {
+    fn lower_alt(&mut self, items : &[EbnfItem], tail_ok : bool, pruned : bool) -> Result<Vec<String>, String>
+    {
+        // $pruned applies to the contents of groups too
+        let pruned = pruned || items.iter().any(|x| matches!(x, EbnfItem::Term(t) if t == "$pruned" || t == "$PRUNED"));
+        let last = items.iter().rposition(|x| !matches!(x, EbnfItem::Term(t) if is_flag_term(t)));
+        let mut ret = vec!();
+        for (i, item) in items.iter().enumerate()
+        {
+            match item
+            {
+                EbnfItem::Term(t) => ret.push(t.clone()),
+                EbnfItem::Group(..) | EbnfItem::SepBy(..) =>
+                {
+                    let name = match item
+                    {
+                        EbnfItem::Group(alts, kind) => self.lower_group(alts, *kind, pruned)?,
+                        EbnfItem::SepBy(sep_by) => self.lower_sep_by(sep_by, pruned),
+                        _ => unreachable!(),
+                    };
+                    if tail_ok && self.can_become && Some(i) == last
+                    {
+                        ret.push("$become".to_string());
+                        ret.push(name);
+                    }
+                    else
+                    {
+                        ret.push(name);
+                        ret.push("$hoist".to_string());
+                    }
+                }
+            }
+        }
+        Ok(ret)
+    }
+    fn lower_group(&mut self, alts : &[Vec<EbnfItem>], kind : char, pruned : bool) -> Result<String, String>
+    {
+        self.counter += 1;
+        let name = format!("{}__{}{}", self.rule, match kind { ' ' => "group", '?' => "opt", _ => "rep" }, self.counter);
+        if kind != ' ' && !self.infer
+        {
+            for alt in alts
+            {
+                let first = match alt.first()
+                {
+                    Some(EbnfItem::Term(t)) if is_predicate_term(t) => continue,
+                    Some(EbnfItem::Term(t)) => format!("`{t}`"),
+                    Some(EbnfItem::Group(..)) => "a group".to_string(),
+                    Some(EbnfItem::SepBy(..)) => "a $sep_by".to_string(),
+                    None => "nothing".to_string(),
+                };
+                return Err(format!("Every alternative of an optional or repeated group must begin with a predicate (@peek, @peekr, @peekres, @guard, @auto, or @eof), but one in rule {} begins with {first}", self.rule));
+            }
+        }
+        // generated rules go right after the rule they came from, outermost first
+        let slot = self.out.len();
+        self.out.push((name.clone(), vec!()));
+        let more = format!("{name}_more");
+        if kind == '+' { self.out.push((more.clone(), vec!())); }
+        
+        let mut forms = vec!();
+        for alt in alts
+        {
+            let mut lowered = self.lower_alt(alt, kind == ' ' || kind == '?', pruned)?;
+            if pruned { lowered.push("$pruned".to_string()); }
+            forms.push(lowered);
+        }
+        if self.infer { forms[0].insert(0, "$infer_peeks".to_string()); }
+        let with_become = |target : &str| forms.iter().map(|f| { let mut f = f.clone(); f.push("$become".to_string()); f.push(target.to_string()); f }).collect::<Vec<_>>();
+        match kind
+        {
+            ' ' => self.out[slot].1 = forms,
+            '?' => { self.out[slot].1 = forms; self.out[slot].1.push(vec!()); }
+            '*' => { self.out[slot].1 = with_become(&name); self.out[slot].1.push(vec!()); }
+            _ =>
+            {
+                self.out[slot].1 = with_become(&more);
+                self.out[slot + 1].1 = with_become(&more);
+                self.out[slot + 1].1.push(vec!());
+            }
+        }
+        Ok(name)
+    }
+    // Separated lists are a $become loop over two or three generated rules:
+    //     name ::= element $become name_tail
+    //     name_tail ::= @auto sep element $become name_tail | (empty)
+    // With a trailing separator, name_tail becomes `@auto sep $become name_next | (empty)`, where name_next is name but also ends at `end`.
+    fn lower_sep_by(&mut self, sep_by : &SepBy, pruned : bool) -> String
+    {
+        self.counter += 1;
+        let name = format!("{}__sep{}", self.rule, self.counter);
+        let tail = format!("{name}_tail");
+        let next = format!("{name}_next");
+        let peek = |term : &str|
+        {
+            let peek = if term.starts_with("\"") { "@peek" } else { "@peekr" };
+            [peek, "(", "0", ",", term, ")"].map(|x| x.to_string()).to_vec()
+        };
+        let at_end = sep_by.end.as_deref().map(peek);
+        let form = |terms : &[&str]|
+        {
+            let mut f = terms.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            if pruned { f.push("$pruned".to_string()); }
+            f
+        };
+        let element = &*sep_by.element;
+        // @auto turns the separator into $any, which ignores $pruned
+        let mut separator = if pruned { peek(&sep_by.separator) } else { vec!("@auto".to_string()) };
+        separator.push(sep_by.separator.clone());
+        let separator = separator.iter().map(|x| &**x).collect::<Vec<_>>();
+        
+        let mut head = vec!();
+        if sep_by.empty && let Some(at_end) = &at_end { head.push(at_end.clone()); }
+        head.push(form(&[element, "$become", &tail]));
+        self.out.push((name.clone(), head));
+        
+        if sep_by.trailing && let Some(at_end) = &at_end
+        {
+            self.out.push((tail.clone(), vec!(form(&[&separator[..], &["$become", &next]].concat()), vec!())));
+            self.out.push((next, vec!(at_end.clone(), form(&[element, "$become", &tail]))));
+        }
+        else
+        {
+            self.out.push((tail.clone(), vec!(form(&[&separator[..], &[element, "$become", &tail]].concat()), vec!())));
+        }
+        name
+    }
+}
+
+// Turns EBNF-style groups ( `( ... )`, `( ... )?`, `( ... )*`, `( ... )+`, `[ ... ]` ) and `$sep_by(...)` into generated rules.
+pub (crate) fn bnf_desugar(input : Vec<(String, Vec<Vec<String>>)>) -> Result<Vec<(String, Vec<Vec<String>>)>, String>
+{
+    let infer_names = input.iter().filter(|(name, _)| name == "__INFER_PEEKS").flat_map(|(_, forms)| forms.iter().flatten().cloned()).collect::<Vec<_>>();
+    let mut ret = Vec::new();
+    for (name, forms) in input
+    {
+        if is_magic_rule(&name) || !forms.iter().flatten().any(|t| t == "(" || t == "[")
+        {
+            ret.push((name, forms));
+            continue;
+        }
+        let can_become = !forms.iter().flatten().any(|t| matches!(&**t, "@RECOVER" | "@recover" | "@RECOVER_BEFORE" | "@recover_before"));
+        let infer = forms.iter().flatten().any(|t| t == "$infer_peeks" || t == "$INFER_PEEKS") || infer_names.iter().any(|x| x == "*" || *x == name);
+        let mut d = Desugarer { rule : &name, out : vec!(), counter : 0, can_become, infer };
+        let mut new_forms = vec!();
+        for alt in &forms
+        {
+            let mut i = 0;
+            let items = ebnf_parse_group(&name, alt, &mut i, None)?;
+            // top-level alternations were already split up by bnf_parse
+            new_forms.push(d.lower_alt(&items[0], true, false)?);
+        }
+        let generated = std::mem::take(&mut d.out);
+        ret.push((name, new_forms));
+        ret.extend(generated);
+    }
+    Ok(ret)
+}
+
+// Checks that guards and directives are somewhere that they can work.
+fn check_placement(name : &str, alt : usize, terms : &[MatchingTerm], pruned : bool) -> Result<(), GrammarError>
+{
+    // how many children the AST node is known to have so far, if known (hooks can add any number)
+    let mut children = Some(0usize);
+    let mut i = 0;
+    while i < terms.len()
+    {
+        let context = format!("(context: {name} alt {alt} term {i})");
+        let err = |msg : String, snippet : &str| GrammarError::new(GrammarErrorKind::Placement, msg).in_rule(name).at(snippet);
+        let next_is_rule = matches!(terms.get(i + 1).map(|x| &x.t), Some(MatchingTermE::Rule(_)));
+        match &terms[i].t
+        {
+            MatchingTermE::Eof | MatchingTermE::Peek(..) | MatchingTermE::PeekR(..) | MatchingTermE::PeekRes(..) | MatchingTermE::Guard(_) if i > 0 =>
+            {
+                let guard = match &terms[i].t
+                {
+                    MatchingTermE::Eof => "@eof",
+                    MatchingTermE::Peek(..) => "@peek",
+                    MatchingTermE::PeekR(..) => "@peekr",
+                    MatchingTermE::PeekRes(..) => "@peekres",
+                    _ => "@guard",
+                };
+                Err(err(format!("{guard} must be the first term of an alternation {context}"), guard))?
+            }
+            MatchingTermE::Directive(d @ (MatchDirective::Become | MatchDirective::BecomeAs)) =>
+            {
+                if !next_is_rule || i + 2 != terms.len() { Err(err(format!("{} must be followed by a rule name, at the end of the alternation {context}", d.as_str()), d.as_str()))? }
+                return Ok(());
+            }
+            MatchingTermE::Directive(MatchDirective::Rename) =>
+            {
+                if !next_is_rule { Err(err(format!("$rename must be followed by a rule name {context}"), "$rename"))? }
+                i += 1;
+            }
+            MatchingTermE::Directive(d @ (MatchDirective::Hoist | MatchDirective::HoistIfUnit | MatchDirective::Drop | MatchDirective::DropIfEmpty)) =>
+            {
+                if children == Some(0) { Err(err(format!("{} must come after something that adds to the AST {context}", d.as_str()), d.as_str()))? }
+                children = match d
+                {
+                    MatchDirective::Drop => children.map(|n| n - 1),
+                    _ => None,
+                };
+            }
+            MatchingTermE::TermLit(_) | MatchingTermE::TermRegex(_) if pruned => {}
+            MatchingTermE::Rule(_) | MatchingTermE::TermLit(_) | MatchingTermE::TermRegex(_) | MatchingTermE::Directive(MatchDirective::Any) =>
+                children = children.map(|n| n + 1),
+            MatchingTermE::Hook(_) => children = None,
+            _ => {}
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+#[doc(hidden)]
+pub fn grammar_convert(input: &Vec<(String, Vec<Vec<String>>)>) -> Result<Grammar, GrammarError>
+{
+    let mut by_name = HashMap::default();
+    for (name, _) in input.iter()
+    {
+        if is_magic_rule(name) { continue; }
+        if by_name.insert(name.clone(), by_name.len()).is_some()
+        {
+            let msg = format!("Duplicate rule {name}; use alternations (e.g. x ::= a | b), not additional definitions (like x ::= a [...] x ::= b)");
+            return Err(GrammarError::new(GrammarErrorKind::DuplicateRule, msg).in_rule(name).at(name));
+        }
+    }
+    
+    let mut string_cache = HashMap::default();
+    let mut string_cache_inv = Vec::new();
+    let mut points = Vec::new();
+    let mut literals = HashSet::default();
+    let mut lex_regexes = HashMap::default();
+    
+    let mut bracket_pairs = Vec::new();
+    let mut comment_pairs = Vec::new();
+    let mut comment_pairs_nested = Vec::new();
+    let mut comment_regexes = Vec::new();
+    let mut comments = Vec::new();
+    
+    let mut cache_pool = HashMap::<String, _>::default();
+    
+    let mut reserved = None;
+    let mut reserved_words = Vec::new();
+    // rules to give predicates with infer_peeks, and whether each was asked for with $infer_peeks (as opposed to __INFER_PEEKS)
+    let mut infer_names = Vec::new();
+    let mut infer = Vec::new();
+    for (name, raw_forms) in input.iter()
+    {
+        let bad_regex = |pattern : &str, snippet : &str, e : regex::Error|
+            GrammarError::new(GrammarErrorKind::InvalidRegex, format!("Invalid regex '{}': {}", pattern, e)).in_rule(name).at(snippet);
+        let bad_predicate = |msg : String, snippet : &str|
+            GrammarError::new(GrammarErrorKind::BadPredicate, msg).in_rule(name).at(snippet);
+        if name == "__INFER_PEEKS"
+        {
+            infer_names.extend(raw_forms.iter().flatten().cloned());
+            continue;
+        }
+        if name == "__RESERVED_WORDS"
+        {
+            let mut set = Vec::new();
+            for s in raw_forms.iter().map(|x| x.iter()).flatten()
+            {
+                set.push(s.clone());
+            }
+            reserved = Some(build_literal_regex(&set, true));
+            reserved_words = set;
+            continue;
+        }
+        if name == "__BRACKET_PAIRS" || name == "__COMMENT_PAIRS" || name == "__COMMENT_PAIRS_NESTED"
+        {
+            for raw_alt in raw_forms
+            {
+                match (raw_alt.get(0), raw_alt.get(1))
+                {
+                    (Some(l), Some(r)) =>
+                    {
+                        if name == "__BRACKET_PAIRS" { bracket_pairs.push((l.clone(), r.clone())); }
+                        if name == "__COMMENT_PAIRS" { comment_pairs.push((l.clone(), r.clone())); }
+                        if name == "__COMMENT_PAIRS_NESTED" { comment_pairs_nested.push((l.clone(), r.clone())); }
+                    }
+                    _ => Err(GrammarError::new(GrammarErrorKind::Syntax, format!("Alternations of {name} must all contain two bare string items")).in_rule(name).at(raw_alt.first().unwrap_or(name)))?
+                }
+            }
+            continue;
+        }
+        if name == "__COMMENT_REGEXES" || name == "__COMMENTS"
+        {
+            for s in raw_forms.iter().map(|x| x.iter()).flatten()
+            {
+                if name == "__COMMENT_REGEXES" && s.starts_with("r`") && s.ends_with("`r") && s.len() >= 4
+                {
+                    let pattern = &s[2..s.len() - 2];
+                    let pattern = format!("\\A{pattern}");
+                    let re = new_regex(&pattern).map_err(|e| bad_regex(&pattern, s, e))?;
+                    comment_regexes.push(re);
+                }
+                if name == "__COMMENTS" && s.starts_with("\"") && s.ends_with("\"") && s.len() >= 3
+                {
+                    let pattern = s[1..s.len() - 1].replace("\\\"", "\"").replace("\\\\", "\\").replace("\\n", "\n");
+                    comments.push(pattern);
+                }
+            }
+            continue;
+        }
+        let index = *by_name.get(name).unwrap();
+        
+        let mut forms = Vec::new();
+        let mut recover = None;
+        
+        for raw_alt in raw_forms
+        {
+            //println!("{:?}", raw_alt);
+            let mut matching_terms = Vec::new();
+            let mut pruned = false;
+            
+            let mut i = 0;
+            while i < raw_alt.len()
+            {
+                let term_str = &raw_alt[i];
+                i += 1;
+                
+                if term_str.starts_with('"') && term_str.ends_with('"') && term_str.len() >= 2
+                {
+                    let literal = term_str[1..term_str.len() - 1].replace("\\\"", "\"").replace("\\\\", "\\").replace("\\n", "\n");
+                    matching_terms.push(MatchingTermE::TermLit(string_cache_lookup_id(&mut string_cache, &mut string_cache_inv, &literal)).to());
+                    
+                    literals.insert(literal.clone());
+                    continue;
+                }
+                if term_str.starts_with("r`") && term_str.ends_with("`r") && term_str.len() >= 4
+                {
+                    let pattern = &term_str[2..term_str.len() - 2];
+                    let pattern_all = format!("\\A(?:{pattern})\\z"); // full match (for parsing)
+                    let pattern = format!("\\A(?:{pattern})"); // at start (for tokenization)
+                    let re2 = new_regex(&pattern_all).map_err(|e| bad_regex(&pattern_all, term_str, e))?;
+                    let re2 = RegexCacher::new_with_pool(pattern_all, re2, &mut cache_pool);
+                    lex_regexes.insert(pattern, re2.clone());
+                    matching_terms.push(MatchingTermE::TermRegex(re2).to());
+                    continue;
+                }
+                // non-tokenizing regex (optimization: hide redundant regexes from the tokenizer)
+                if term_str.starts_with("R`") && term_str.ends_with("`r") && term_str.len() >= 4
+                {
+                    let pattern = &term_str[2..term_str.len() - 2];
+                    let pattern_all = format!("\\A(?:{pattern})\\z"); // full match (for parsing)
+                    let re2 = new_regex(&pattern_all).map_err(|e| bad_regex(&pattern_all, term_str, e))?;
+                    matching_terms.push(MatchingTermE::TermRegex(RegexCacher::new_with_pool(pattern_all, re2, &mut cache_pool)).to());
+                    continue;
+                }
+                if term_str.starts_with("A`") && term_str.ends_with("`r") && term_str.len() >= 4
+                {
+                    let pattern = &term_str[2..term_str.len() - 2];
+                    let pattern_all = format!("\\A(?:{pattern})");
+                    let re2 = new_regex(&pattern_all).map_err(|e| bad_regex(&pattern_all, term_str, e))?;
+                    matching_terms.push(MatchingTermE::TermRegex(RegexCacher::new_with_pool(pattern_all, re2, &mut cache_pool)).to());
+                    continue;
+                }
+                if matches!(&**term_str, "@RECOVER" | "@recover" | "@RECOVER_BEFORE" | "@recover_before")
+                {
+                    let pattern = raw_alt.get(i).map(|x| &**x).unwrap_or_default();
+                    if !(pattern.starts_with("r`") || pattern.starts_with("R`") || pattern.starts_with("A`"))
+                        || !pattern.ends_with("`r")
+                    {
+                        Err(bad_predicate(format!("@recover guards only accept regex strings"), term_str))?
+                    }
+                    let no_z = pattern.starts_with("A`");
+                    let pattern = &pattern[2..pattern.len() - 2];
+                    let mut pattern_all = format!("\\A(?:{})\\z", pattern);
+                    if no_z { pattern_all = format!("\\A(?:{})", pattern); }
+                    let re2 = new_regex(&pattern_all).map_err(|e| bad_regex(&pattern_all, &raw_alt[i], e))?;
+                    // TODO: make regex cachers use interior mutability and share the cache
+                    if recover.is_some() { Err(bad_predicate(format!("Rule {name} has multiple @recover items. Only one is supported."), term_str))? }
+                    recover = Some((RegexCacher::new_with_pool(pattern_all, re2, &mut cache_pool), matches!(&**term_str, "@RECOVER" | "@recover")));
+                    i += 1;
+                    continue;
+                }
+                if matches!(&**term_str, "@EOF" | "@eof")
+                {
+                    matching_terms.push(MatchingTermE::Eof.to());
+                    continue;
+                }
+                if matches!(&**term_str, "@AUTO" | "@auto")
+                {
+                    matching_terms.push(MatchingTermE::_AutoTemp.to());
+                    continue;
+                }
+                if term_str == "@PEEK" || term_str == "@peek"
+                    || term_str == "@PEEKR" || term_str == "@peekr"
+                    || term_str == "@PEEKRES" || term_str == "@peekres"
+                {
+                    if raw_alt.get(i).map(|x| &**x) != Some("(") || raw_alt.get(i+2).map(|x| &**x) != Some(",") || raw_alt.get(i+4).map(|x| &**x) != Some(")")
+                    {
+                        Err(bad_predicate(format!("Invalid peek syntax: must be @peek(num, str)"), term_str))?
+                    }
+                    let n = raw_alt[i+1].parse::<isize>().map_err(|_| bad_predicate(format!("Not a supported peek distance: {}", raw_alt[i+1]), term_str))?;
+                    if term_str == "@PEEK" || term_str == "@peek"
+                    {
+                        let literal = &raw_alt[i+3];
+                        if literal.len() < 2 || !literal.starts_with("\"") || !literal.ends_with("\"")
+                        {
+                            return Err(bad_predicate(format!("@peek guards only accept plain strings"), term_str));
+                        }
+                        let literal = literal[1..literal.len() - 1].replace("\\\"", "\"").replace("\\\\", "\\").replace("\\n", "\n");
+                        let s = string_cache_lookup_id(&mut string_cache, &mut string_cache_inv, &literal);
+                        matching_terms.push(MatchingTermE::Peek(n, s).to());
+                    }
+                    else
+                    {
+                        let pattern = &raw_alt[i+3];
+                        if !(pattern.starts_with("r`") || pattern.starts_with("R`") || pattern.starts_with("A`"))
+                            || !pattern.ends_with("`r")
+                        {
+                            Err(bad_predicate("@peekr/@peekres only accept regex strings".to_string(), term_str))?
+                        }
+                        let no_z = pattern.starts_with("A`");
+                        let pattern = &pattern[2..pattern.len() - 2];
+                        let mut pattern_all = format!("\\A(?:{})\\z", pattern);
+                        if no_z { pattern_all = format!("\\A(?:{})", pattern); }
+                        
+                        let re2 = new_regex(&pattern_all).map_err(|e| bad_regex(&pattern_all, &raw_alt[i+3], e))?;
+                        // TODO: make regex cachers use interior mutability and share the cache
+                        if term_str == "@PEEKRES" || term_str == "@peekres"
+                        {
+                            matching_terms.push(MatchingTermE::PeekRes(n, RegexCacher::new_with_pool(pattern_all, re2, &mut cache_pool)).to());
+                        }
+                        else
+                        {
+                            matching_terms.push(MatchingTermE::PeekR(n, RegexCacher::new_with_pool(pattern_all, re2, &mut cache_pool)).to());
+                        }
+                    }
+                    i += 5;
+                    continue;
+                }
+                if term_str == "@GUARD" || term_str == "@guard"
+                {
+                    if raw_alt.get(i).map(|x| &**x) != Some("(") || raw_alt.get(i+2).map(|x| &**x) != Some(")")
+                    {
+                        return Err(bad_predicate(format!("Invalid guard syntax: must be @guard(name)"), term_str));
+                    }
+                    let literal = &raw_alt[i+1];
+                    let s = string_cache_lookup(&mut string_cache, &mut string_cache_inv, &literal).0;
+                    matching_terms.push(MatchingTermE::Guard(s).to());
+                    i += 3;
+                    continue;
+                }
+                if term_str == "!HOOK" || term_str == "!hook"
+                {
+                    if raw_alt.get(i).map(|x| &**x) != Some("(") || raw_alt.get(i+2).map(|x| &**x) != Some(")")
+                    {
+                        return Err(bad_predicate("Invalid hook syntax: must be !hook(name)".to_string(), term_str));
+                    }
+                    let literal = &raw_alt[i+1];
+                    let s = string_cache_lookup(&mut string_cache, &mut string_cache_inv, &literal).0;
+                    matching_terms.push(MatchingTermE::Hook(s).to());
+                    i += 3;
+                    continue;
+                }
+                if matches!(&**term_str, "$BECOME" | "$become")
+                {
+                    matching_terms.push(MatchingTermE::Directive(MatchDirective::Become).to());
+                    continue;
+                }
+                if matches!(&**term_str, "$BECOME_AS" | "$become_as")
+                {
+                    matching_terms.push(MatchingTermE::Directive(MatchDirective::BecomeAs).to());
+                    continue;
+                }
+                if matches!(&**term_str, "$HOIST" | "$hoist")
+                {
+                    matching_terms.push(MatchingTermE::Directive(MatchDirective::Hoist).to());
+                    continue;
+                }
+                if matches!(&**term_str, "$HOIST_UNIT" | "$hoist_unit")
+                {
+                    matching_terms.push(MatchingTermE::Directive(MatchDirective::HoistIfUnit).to());
+                    continue;
+                }
+                if matches!(&**term_str, "$DROP" | "$drop")
+                {
+                    matching_terms.push(MatchingTermE::Directive(MatchDirective::Drop).to());
+                    continue;
+                }
+                if matches!(&**term_str, "$DROP_EMPTY" | "$drop_empty")
+                {
+                    matching_terms.push(MatchingTermE::Directive(MatchDirective::DropIfEmpty).to());
+                    continue;
+                }
+                if matches!(&**term_str, "$RENAME" | "$rename")
+                {
+                    matching_terms.push(MatchingTermE::Directive(MatchDirective::Rename).to());
+                    continue;
+                }
+                if matches!(&**term_str, "$ANY" | "$any")
+                {
+                    matching_terms.push(MatchingTermE::Directive(MatchDirective::Any).to());
+                    continue;
+                }
+                if matches!(&**term_str, "$PRUNED" | "$pruned")
+                {
+                    pruned = true;
+                    continue;
+                }
+                if matches!(&**term_str, "$INFER_PEEKS" | "$infer_peeks")
+                {
+                    if !infer.contains(&(index, true)) { infer.push((index, true)); }
+                    continue;
+                }
+                let id = by_name.get(term_str).ok_or_else(||
+                    GrammarError::new(GrammarErrorKind::UnknownRule, format!("Not a defined grammar rule: '{term_str}' (context: '{name}')")).in_rule(name).at(term_str))?;
+                matching_terms.push(MatchingTermE::Rule(*id).to());
+            }
+            if matching_terms.len() > 60000
+            {
+                Err(format!("More than 60k items in an alternation of {name}. Factor them out, dummy!"))?
+            }
+            if let Some(MatchingTermE::_AutoTemp) = matching_terms.get(0).map(|x| &x.t)
+            {
+                match matching_terms.get(1).map(|x| &x.t)
+                {
+                    Some(MatchingTermE::TermLit(s)) =>
+                    {
+                        matching_terms[0] = MatchingTermE::Peek(0, *s).to();
+                        matching_terms[1] = MatchingTermE::Directive(MatchDirective::Any).to();
+                    }
+                    Some(MatchingTermE::TermRegex(r)) =>
+                    {
+                        let r = r.clone();
+                        matching_terms[0] = MatchingTermE::PeekR(0, r).to();
+                        matching_terms[1] = MatchingTermE::Directive(MatchDirective::Any).to();
+                    }
+                    _ => Err(GrammarError::new(GrammarErrorKind::BadPredicate, format!("@auto must be followed by a string literal or regex literal (context: {name})")).in_rule(name).at("@auto"))?
+                }
+            }
+            check_placement(name, forms.len(), &matching_terms, pruned)?;
+            forms.push(Alternation { matching_terms, pruned });
+        }
+        if forms.len() > 60000
+        {
+            Err(format!("More than 60k alternations in {name}. Factor them out, dummy!"))?
+        }
+        if index > 4000000000
+        {
+            Err(format!("More than 4 billion grammar terms in grammar. What are you doing??? STOP!!!!! (╯°□°）╯︵ ┻━┻"))?
+        }
+        let (name, name_id) = string_cache_lookup(&mut string_cache, &mut string_cache_inv, &name);
+        points.push(GrammarPoint
+        {
+            name,
+            name_id,
+            id: index as u32,
+            forms,
+            recover,
+        });
+    }
+    
+    let mut literals = literals.into_iter().collect::<Vec<_>>();
+    literals.sort();
+    
+    let mut regexes = Vec::new();
+    for (r, r2) in lex_regexes
+    {
+        regexes.push((new_regex(&r).map_err(|e| GrammarError::new(GrammarErrorKind::InvalidRegex, format!("Invalid regex '{}': {}", r, e)))?, r2));
+    }
+    for name in &infer_names
+    {
+        let ids = match &**name
+        {
+            "*" => (0..points.len()).collect::<Vec<_>>(),
+            _ => vec!(*by_name.get(name).ok_or_else(|| GrammarError::new(GrammarErrorKind::UnknownRule, format!("Not a defined grammar rule: '{name}' (context: '__INFER_PEEKS')")).in_rule("__INFER_PEEKS").at(name))?),
+        };
+        infer.extend(ids.into_iter().filter(|id| !infer.iter().any(|(x, _)| x == id)).map(|id| (id, false)).collect::<Vec<_>>());
+    }
+    let mut g = Grammar { points, by_name, literals, regexes, string_cache, string_cache_inv, bracket_pairs, comments, comment_pairs, comment_regexes, reserved, reserved_words, comment_pairs_nested, used_guards : vec!(), used_hooks : vec!(), source : vec!() };
+    if !infer.is_empty()
+    {
+        infer.sort();
+        let conflicts = crate::analysis::infer_peeks(&mut g, &infer.iter().map(|(id, _)| *id).collect::<Vec<_>>());
+        if let Some(c) = conflicts.first()
+        {
+            let name = &g.points[c.rule].name;
+            let mut e = GrammarError::new(GrammarErrorKind::PeekConflict, format!("Can't infer peeks: {}", c.describe(&g))).in_rule(name);
+            if infer.contains(&(c.rule, true)) { e = e.at("$infer_peeks"); }
+            return Err(e);
+        }
+    }
+    g.find_guards_and_hooks();
+    Ok(g)
+}
+
+// Takes %include, %import, %append, %prepend, and %replace directives out of the source, leaving empty lines (or the rest of the rule) so that line numbers don't change.
+// Returns the stripped source, the (prefix, path) of each include or import, and the line of each rule that modifies an existing rule.
+fn bnf_strip_directives<'a>(source : &'a str) -> Result<(String, Vec<(Option<String>, String)>, HashMap<usize, &'a str>), GrammarError>
+{
+    let mut directives = vec!();
+    // line -> %append, %prepend, or %replace, for rules that modify an existing rule instead of defining a new one
+    let mut modes = HashMap::default();
+    let mut stripped = String::new();
+    for (linenum, line) in source.lines().enumerate()
+    {
+        let split = line.split_whitespace().collect::<Vec<_>>();
+        let err = |msg : String| { let mut e = GrammarError::new(GrammarErrorKind::Include, msg).at(line.trim()); e.line = linenum + 1; e.column = line.len() - line.trim_start().len() + 1; e };
+        let unquote = |s : Option<&&str>| s.filter(|s| s.len() >= 2 && s.starts_with('"') && s.ends_with('"')).map(|s| s[1..s.len() - 1].to_string());
+        match split.first().map(|x| &**x)
+        {
+            Some("%include") if split.len() == 2 && let Some(file) = unquote(split.get(1)) => directives.push((None, file)),
+            Some("%import") if split.len() == 3 && let Some(file) = unquote(split.get(2)) =>
+            {
+                if !split[1].chars().all(|c| c.is_alphanumeric() || c == '_')
+                {
+                    return Err(err(format!("Import prefixes must be plain names")));
+                }
+                directives.push((Some(split[1].to_string()), file));
+            }
+            Some("%include" | "%import") => return Err(err(format!("Malformed {}; expected %include \"path\" or %import prefix \"path\"", split[0]))),
+            Some(op @ ("%append" | "%prepend" | "%replace")) =>
+            {
+                if !split.contains(&"::=") { return Err(err(format!("Malformed {op}; expected {op} name ::= ..."))); }
+                modes.insert(linenum + 1, op);
+                // keep the rule itself, so that bnf_parse sees a normal rule starting on this line
+                let start = line.find(op).unwrap() + op.len();
+                stripped += &" ".repeat(start);
+                stripped += &line[start..];
+                stripped += "\n";
+                continue;
+            }
+            _ =>
+            {
+                stripped += line;
+                stripped += "\n";
+                continue;
+            }
+        }
+        stripped += "\n";
+    }
+    Ok((stripped, directives, modes))
+}
+
+struct Composer<'a> {
+    loader : &'a mut dyn FnMut(&str) -> Result<String, String>,
+    rules : Vec<(String, Vec<Vec<String>>)>,
+    // rule (or template) name -> where it was defined
+    origins : HashMap<String, String>,
+    // magic rule name -> index in rules
+    magic : HashMap<String, usize>,
+    // files currently being loaded, for cycle detection
+    stack : Vec<String>,
+    // (prefix, path) pairs that were already loaded
+    loaded : HashSet<(String, String)>,
+}
+
+impl<'a> Composer<'a> {
+    // Adds the rules of one grammar file, with every rule it defines renamed to `prefix.name` (if prefix isn't empty).
+    // Returns the unprefixed names of the rules it defined, including ones from its own %includes.
+    fn load(&mut self, path : Option<&str>, source : &str, prefix : &str) -> Result<HashSet<String>, GrammarError>
+    {
+        let location = |line : usize| match path { Some(path) => format!("line {line} of {path}"), None => format!("line {line}") };
+        let in_file = |mut e : GrammarError| { if e.file.is_none() { e.file = path.map(|p| p.to_string()); } e };
+        // error about the rule defined on the given line
+        let on_line = |e : GrammarError, line : usize|
+        {
+            let mut e = in_file(e);
+            let text = source.lines().nth(line - 1).unwrap_or_default();
+            e.line = line;
+            e.column = text.find(&*e.snippet).map(|pos| text[..pos].chars().count() + 1).unwrap_or(1);
+            e
+        };
+        // error about the directive that loads the given file
+        let on_directive = |e : GrammarError, file : &str|
+        {
+            match source.lines().position(|line| line.trim_start().starts_with('%') && line.contains(&format!("\"{file}\"")))
+            {
+                Some(i) => on_line(e, i + 1),
+                None => in_file(e),
+            }
+        };
+        
+        let (stripped, directives, modes) = bnf_strip_directives(source).map_err(in_file)?;
+        let rules = bnf_parse(&stripped).map_err(in_file)?;
+        
+        let mut local = HashSet::default();
+        let mut import_prefixes = HashSet::default();
+        for (import, file) in directives
+        {
+            let key = (import.as_ref().map(|p| join_prefix(prefix, p)).unwrap_or(prefix.to_string()), file.clone());
+            if let Some(p) = &import { import_prefixes.insert(p.clone()); }
+            if self.stack.contains(&file)
+            {
+                return Err(on_directive(GrammarError::new(GrammarErrorKind::Include, format!("Include cycle: {} -> {file}", self.stack.join(" -> "))).at(&file), &file));
+            }
+            if !self.loaded.insert(key.clone())
+            {
+                continue;
+            }
+            let source = (self.loader)(&file).map_err(|e| on_directive(GrammarError::new(GrammarErrorKind::Include, format!("Failed to load {file}: {e}")).at(&file), &file))?;
+            self.stack.push(file.clone());
+            let names = self.load(Some(&file), &source, &key.0)?;
+            self.stack.pop();
+            if import.is_none() { local.extend(names); }
+        }
+        for (name, line, _) in &rules
+        {
+            if !is_magic_rule(name) && !modes.contains_key(line) { local.insert(name.split('(').next().unwrap().to_string()); }
+        }
+        
+        let rename = |token : &str, params : &[&str]| -> Option<String>
+        {
+            if prefix.is_empty() || params.contains(&token) { return None; }
+            let base = token.split('(').next().unwrap();
+            let imported = base.split_once('.').is_some_and(|(p, _)| import_prefixes.contains(p));
+            (local.contains(base) || imported).then(|| join_prefix(prefix, token))
+        };
+        for (name, line, forms) in rules
+        {
+            let mode = modes.get(&line).copied();
+            if is_magic_rule(&name)
+            {
+                match self.magic.get(&name)
+                {
+                    Some(i) if mode == Some("%replace") => self.rules[*i].1 = forms,
+                    Some(i) =>
+                    {
+                        let merged = &mut self.rules[*i].1;
+                        let forms = forms.into_iter().filter(|alt| !merged.contains(alt)).collect::<Vec<_>>();
+                        if mode == Some("%prepend") { merged.splice(0..0, forms); } else { merged.extend(forms); }
+                    }
+                    None =>
+                    {
+                        self.magic.insert(name.clone(), self.rules.len());
+                        self.rules.push((name, forms));
+                    }
+                }
+                continue;
+            }
+            let params = name.split_once('(').map(|(_, p)| p.trim_end_matches(')').split(',').collect::<Vec<_>>()).unwrap_or_default();
+            let mut forms = forms;
+            for alt in forms.iter_mut()
+            {
+                let mut i = 0;
+                while i < alt.len()
+                {
+                    // guard and hook names aren't rule names
+                    if takes_arguments(&alt[i]) && !matches!(&*alt[i], "$sep_by" | "$SEP_BY")
+                    {
+                        while i < alt.len() && alt[i] != ")" { i += 1; }
+                    }
+                    else if let Some(renamed) = rename(&alt[i], &params)
+                    {
+                        alt[i] = renamed;
+                    }
+                    i += 1;
+                }
+            }
+            let name = rename(&name, &[]).unwrap_or(name.clone());
+            let base = name.split('(').next().unwrap().to_string();
+            if let Some(op) = mode
+            {
+                let i = self.rules.iter().position(|(n, _)| n.split('(').next() == Some(&*base))
+                    .ok_or_else(|| on_line(GrammarError::new(GrammarErrorKind::UnknownRule, format!("{op} of undefined rule {base}")).in_rule(&base).at(&base), line))?;
+                match op
+                {
+                    "%append" => self.rules[i].1.extend(forms),
+                    "%prepend" => { self.rules[i].1.splice(0..0, forms); }
+                    _ => self.rules[i] = (name, forms),
+                }
+                continue;
+            }
+            if let Some(other) = self.origins.get(&base)
+            {
+                let msg = format!("Duplicate rule {base} (at {other} and {}); use alternations (e.g. x ::= a | b), not additional definitions (like x ::= a [...] x ::= b)", location(line));
+                return Err(on_line(GrammarError::new(GrammarErrorKind::DuplicateRule, msg).in_rule(&base).at(&base), line));
+            }
+            self.origins.insert(base, location(line));
+            self.rules.push((name, forms));
+        }
+        Ok(local)
+    }
+}
+
+fn join_prefix(prefix : &str, name : &str) -> String
+{
+    if prefix.is_empty() { name.to_string() } else { format!("{prefix}.{name}") }
+}
+
+// Resolves %include, %import, %append, %prepend, and %replace directives, merging everything into one list of rules, on top of `base` (if any).
+pub (crate) fn bnf_compose(base : &[(String, Vec<Vec<String>>)], s : &str, loader : &mut dyn FnMut(&str) -> Result<String, String>) -> Result<Vec<(String, Vec<Vec<String>>)>, GrammarError>
+{
+    let mut c = Composer { loader, rules : base.to_vec(), origins : <_>::default(), magic : <_>::default(), stack : vec!(), loaded : <_>::default() };
+    for (i, (name, _)) in base.iter().enumerate()
+    {
+        if is_magic_rule(name) { c.magic.insert(name.clone(), i); }
+        else { c.origins.insert(name.split('(').next().unwrap().to_string(), "the base grammar".to_string()); }
+    }
+    c.load(None, s, "")?;
+    Ok(c.rules)
+}
+
+/// Turns a BNF string into a [`Grammar`]. See [the pred-recdec crate docs](https://docs.rs/pred-recdec) for syntax notes. The basic parts are standard BNF.
+///
+/// `%include` and `%import` directives aren't supported; use [`bnf_to_grammar_with_loader`] for those.
+///
+/// Only outright errors are reported. To find likely mistakes, like alternations that can never be chosen, use [`analyze`](crate::analysis::analyze).
+///
+/// Next step: [`tokenize`].
+pub fn bnf_to_grammar(s : &str) -> Result<Grammar, GrammarError>
+{
+    bnf_to_grammar_with_loader(s, |path| Err(format!("can't load {path}; use bnf_to_grammar_with_loader to support %include and %import")))
+}
+
+/// Like [`bnf_to_grammar`], but also supports `%include "path"` and `%import prefix "path"` lines, which pull in the rules of other grammars. `loader` is given each path exactly as written, and returns that grammar's source, so it can read files, look up in-memory strings, etc.
+///
+/// `%include` adds the other grammar's rules as-is. `%import` renames every rule defined in the other grammar to `prefix.name`, including references to it inside that grammar. Magic pseudo-rules like `__COMMENTS` are merged instead of renamed. Loading the same file with the same prefix twice only loads it once.
+///
+/// Rules that came from other grammars can be modified with `%append`, `%prepend`, and `%replace`; see [`Grammar::with_overlay`].
+///
+/// ```
+/// # use pred_recdec::bnf::*;
+/// let g = bnf_to_grammar_with_loader(r#"
+/// %import expr "expr.txt"
+/// S ::= expr.sum ";"
+/// "#, |path| match path
+/// {
+///     "expr.txt" => Ok(r#"sum ::= number $become sum_tail
+/// sum_tail ::= @auto "+" number $become sum_tail | #empty
+/// number ::= r`[0-9]+`r"#.to_string()),
+///     _ => Err("not found".to_string()),
+/// }).unwrap();
+/// assert!(g.by_name.contains_key("expr.sum_tail"));
+/// ```
+///
+/// Next step: [`tokenize`].
+pub fn bnf_to_grammar_with_loader(s : &str, mut loader : impl FnMut(&str) -> Result<String, String>) -> Result<Grammar, GrammarError>
+{
+    bnf_compose(&[], s, &mut loader).and_then(bnf_build).map_err(|e| e.locate(s))
+}
+
+#[doc(hidden)]
+pub fn bnf_build(source : Vec<(String, Vec<Vec<String>>)>) -> Result<Grammar, GrammarError>
+{
+    let expanded = bnf_expand_templates(source.clone()).map_err(|e| GrammarError::new(GrammarErrorKind::Template, e))?;
+    let desugared = bnf_desugar(expanded).map_err(|e| GrammarError::new(GrammarErrorKind::Syntax, e))?;
+    let mut g = grammar_convert(&desugared)?;
+    g.source = source;
+    Ok(g)
+}
+
+// Splits one line of BNF into terms (each with whether it directly follows the previous one, without whitespace) and its trailing comment, for bnf_format.
+fn bnf_split_line(line : &str) -> (Vec<(String, bool)>, Option<&str>)
+{
+    let mut terms : Vec<(String, bool)> = vec!();
+    let mut rest = line;
+    let mut attached = false;
+    while !rest.is_empty()
+    {
+        let c = get_char_at_byte(rest, 0);
+        let len = if c.is_whitespace()
+        {
+            rest = rest.trim_start();
+            attached = false;
+            continue;
+        }
+        else if c == '#'
+        {
+            return (terms, Some(rest));
+        }
+        else if c == '"'
+        {
+            bnf_literal_len(rest).unwrap_or(rest.len())
+        }
+        else if rest.starts_with("r`") || rest.starts_with("R`") || rest.starts_with("A`")
+        {
+            rest[2..].find("`r").map(|i| i + 4).unwrap_or(rest.len())
+        }
+        else if rest.starts_with("::=")
+        {
+            3
+        }
+        else if matches!(c, '(' | ')' | '[' | ']' | ',' | '|')
+            || (matches!(c, '*' | '+' | '?') && attached && matches!(terms.last().map(|t| &*t.0), Some(")" | "]")))
+        {
+            1
+        }
+        else
+        {
+            bnf_name_len(rest, true).max(1)
+        };
+        terms.push((rest[..len].to_string(), attached));
+        rest = &rest[len..];
+        attached = true;
+    }
+    (terms, None)
+}
+
+/// Reformats BNF source text in a consistent style, without changing what it means: rule names at the start of the line, alternations indented by four spaces, single spaces between terms, predicate and template arguments written like `@peek(0, "x")`, and groups written like `( a b )*`.
+///
+/// Unlike [`Grammar::to_bnf`], this works on the text itself, so comments, blank lines (at most one in a row), directives, EBNF sugar and templates are kept. Lines continued with a trailing `\` are left alone.
+pub fn bnf_format(source : &str) -> Result<String, String>
+{
+    let is_header = |line : &str|
+    {
+        let split = line.split_whitespace().collect::<Vec<_>>();
+        let pos = split.iter().position(|x| *x == "::=");
+        pos == Some(1) || pos.is_some_and(|p| p > 1 && split[0].contains('(') && !split[0].starts_with('(') && split[p - 1].ends_with(')'))
+    };
+    let mut out = vec!();
+    let mut continued = false;
+    for line in source.lines()
+    {
+        let trimmed = line.trim();
+        if continued || trimmed.ends_with("\\")
+        {
+            continued = trimmed.ends_with("\\");
+            out.push(line.trim_end().to_string());
+            continue;
+        }
+        if trimmed.is_empty()
+        {
+            if out.last().is_some_and(|l : &String| !l.is_empty()) { out.push(String::new()); }
+            continue;
+        }
+        if trimmed.starts_with("%include") || trimmed.starts_with("%import")
+        {
+            out.push(trimmed.split_whitespace().collect::<Vec<_>>().join(" "));
+            continue;
+        }
+        let (op, code) = match ["%append", "%prepend", "%replace"].iter().find(|op| trimmed.starts_with(*op))
+        {
+            Some(op) => (Some(*op), trimmed[op.len()..].trim_start()),
+            None => (None, trimmed),
+        };
+        let (terms, comment) = bnf_split_line(code);
+        
+        let mut text = String::new();
+        if let Some(op) = op { text += op; }
+        // for each open paren: whether it's an argument list (`@peek(...)`) rather than a group (`( ... )`)
+        let mut parens = vec!();
+        let mut prev : Option<&str> = None;
+        for (term, attached) in &terms
+        {
+            let args = parens.last().copied().unwrap_or(false);
+            let space = match (prev, &**term)
+            {
+                (None, _) => !text.is_empty(),
+                (_, "," | "*" | "+" | "?") => false,
+                (Some("("), _) if args => false,
+                (_, ")") if args => false,
+                (Some(p), "(") if *attached && !matches!(p, "(" | ")" | "[" | "]" | "|" | "," | "::=") => false,
+                _ => true,
+            };
+            if term == "("
+            {
+                parens.push(!space && prev.is_some());
+            }
+            if term == ")" { parens.pop(); }
+            if space { text += " "; }
+            text += term;
+            prev = Some(term);
+        }
+        if let Some(comment) = comment
+        {
+            if !text.is_empty() { text += " "; }
+            text += comment.trim_end();
+        }
+        
+        let indent = if is_header(code) || (terms.is_empty() && !line.starts_with(char::is_whitespace)) { "" } else { "    " };
+        out.push(format!("{indent}{text}"));
+    }
+    while out.last().is_some_and(|l| l.is_empty()) { out.pop(); }
+    let formatted = out.join("\n") + "\n";
+    
+    // make sure nothing but whitespace changed
+    let rules = |s : &str| -> Result<_, String>
+    {
+        let (stripped, directives, modes) = bnf_strip_directives(s)?;
+        let rules = bnf_parse(&stripped)?.into_iter().map(|(name, _, forms)| (name, forms)).collect::<Vec<_>>();
+        let mut modes = modes.into_values().map(|x| x.to_string()).collect::<Vec<_>>();
+        modes.sort();
+        Ok((rules, directives, modes))
+    };
+    if rules(source)? != rules(&formatted)?
+    {
+        return Err("Formatting would change the meaning of the grammar; this is a bug in bnf_format".to_string());
+    }
+    Ok(formatted)
+}
+
+#[doc(hidden)]
+pub fn bnf_quote(text : &str) -> String
+{
+    format!("\"{}\"", text.replace("\\", "\\\\").replace("\"", "\\\"").replace("\n", "\\n"))
+}
+
+impl Grammar {
+    /// Turns the grammar back into BNF text, in a canonical format: magic pseudo-rules first, then one rule per grammar point, in order, with one alternation per line.
+    ///
+    /// Passing the result to [`bnf_to_grammar`] produces an equivalent grammar. EBNF sugar, `$sep_by`, templates, and `%include`s are already expanded by the time a grammar is built, so they show up as the rules they were expanded into; comments are gone.
+    pub fn to_bnf(&self) -> String
+    {
+        let lexed = self.regexes.iter().map(|(_, r)| r.p.clone()).collect::<HashSet<_>>();
+        // (kind, pattern), from a regex's full pattern
+        let regex = |r : &RegexCacher, lexer_kind : &str|
+        {
+            let p = r.p.strip_prefix("\\A(?:").unwrap_or(&r.p);
+            match p.strip_suffix(")\\z")
+            {
+                Some(p) => format!("{}`{p}`r", if lexed.contains(&r.p) { lexer_kind } else { "R" }),
+                None => format!("A`{}`r", p.strip_suffix(")").unwrap_or(p)),
+            }
+        };
+        let string = |id : u32| bnf_quote(&self.string_cache_inv[id as usize]);
+        
+        let mut out = String::new();
+        let mut magic = |name : &str, forms : Vec<String>|
+        {
+            if forms.is_empty() { return; }
+            out += &format!("{name} ::= {}\n", forms.join(" | "));
+        };
+        magic("__COMMENTS", self.comments.iter().map(|c| bnf_quote(c)).collect());
+        magic("__COMMENT_PAIRS", self.comment_pairs.iter().map(|(l, r)| format!("{l} {r}")).collect());
+        magic("__COMMENT_PAIRS_NESTED", self.comment_pairs_nested.iter().map(|(l, r)| format!("{l} {r}")).collect());
+        magic("__COMMENT_REGEXES", self.comment_regexes.iter().map(|r| format!("r`{}`r", r.as_str().strip_prefix("\\A").unwrap_or(r.as_str()))).collect());
+        magic("__BRACKET_PAIRS", self.bracket_pairs.iter().map(|(l, r)| format!("{l} {r}")).collect());
+        if !self.reserved_words.is_empty() { magic("__RESERVED_WORDS", vec!(self.reserved_words.join(" "))); }
+        if !out.is_empty() { out += "\n"; }
+        
+        for point in &self.points
+        {
+            out += &format!("{} ::=\n", point.name);
+            for (i, alt) in point.forms.iter().enumerate()
+            {
+                let mut terms = vec!();
+                let mut j = 0;
+                while j < alt.matching_terms.len()
+                {
+                    let next_is_any = matches!(alt.matching_terms.get(j + 1).map(|t| &t.t), Some(MatchingTermE::Directive(MatchDirective::Any)));
+                    terms.push(match &alt.matching_terms[j].t
+                    {
+                        // `@auto x` is stored as `@peek(0, x) $any`, but also registers x with the tokenizer
+                        MatchingTermE::Peek(0, s) if j == 0 && next_is_any && self.literals.contains(&self.string_cache_inv[*s as usize]) =>
+                        {
+                            j += 1;
+                            format!("@auto {}", string(*s))
+                        }
+                        MatchingTermE::PeekR(0, r) if j == 0 && next_is_any && lexed.contains(&r.p) =>
+                        {
+                            j += 1;
+                            format!("@auto {}", regex(r, "r"))
+                        }
+                        MatchingTermE::Rule(id) => self.points[*id].name.to_string(),
+                        MatchingTermE::TermLit(s) => string(*s),
+                        MatchingTermE::TermRegex(r) => regex(r, "r"),
+                        MatchingTermE::Directive(d) => d.as_str().to_string(),
+                        MatchingTermE::Hook(name) => format!("!hook({name})"),
+                        MatchingTermE::_AutoTemp => "@auto".to_string(),
+                        MatchingTermE::Eof => "@eof".to_string(),
+                        MatchingTermE::Peek(n, s) => format!("@peek({n}, {})", string(*s)),
+                        // peeks never register with the tokenizer
+                        MatchingTermE::PeekR(n, r) => format!("@peekr({n}, {})", regex(r, "R")),
+                        MatchingTermE::PeekRes(n, r) => format!("@peekres({n}, {})", regex(r, "R")),
+                        MatchingTermE::Guard(name) => format!("@guard({name})"),
+                    });
+                    j += 1;
+                }
+                if alt.pruned { terms.push("$pruned".to_string()); }
+                if i == 0 && let Some((r, seek_past)) = &point.recover
+                {
+                    terms.push(format!("{} {}", if *seek_past { "@recover" } else { "@recover_before" }, regex(r, "R")));
+                }
+                if terms.is_empty() { terms.push("#empty".to_string()); }
+                out += &format!("    {}{}\n", if i == 0 { "" } else { "| " }, terms.join(" "));
+            }
+        }
+        out
+    }
+    /// Builds a new grammar (a "dialect") from this one's BNF plus an overlay. The overlay is BNF that can also modify existing rules:
+    ///
+    /// - `%append name ::= ...` adds alternations to the end of an existing rule.
+    /// - `%prepend name ::= ...` adds alternations to the start of an existing rule, i.e. they're tried first.
+    /// - `%replace name ::= ...` replaces all of an existing rule's alternations.
+    ///
+    /// New rules are added as normal, and redefining an existing rule without one of the above is an error. Magic pseudo-rules are merged, so e.g. `__RESERVED_WORDS ::= typeof` adds a reserved word. The same directives also work in normal grammars, for modifying rules from `%include`d or `%import`ed grammars.
+    ///
+    /// This grammar isn't modified, and can be used as the base of any number of dialects.
+    ///
+    /// ```
+    /// # use pred_recdec::bnf::*;
+    /// let c99 = bnf_to_grammar(r#"
+    /// type_specifier ::= @auto "int" | "char"
+    /// "#).unwrap();
+    /// let gnu = c99.with_overlay(r#"
+    /// %prepend type_specifier ::= @auto "__int128"
+    /// "#).unwrap();
+    /// assert_eq!(gnu.points[gnu.by_name["type_specifier"]].forms.len(), 3);
+    /// ```
+    pub fn with_overlay(&self, overlay : &str) -> Result<Grammar, GrammarError>
+    {
+        self.with_overlay_and_loader(overlay, |path| Err(format!("can't load {path}; use with_overlay_and_loader to support %include and %import")))
+    }
+    /// Like [`Grammar::with_overlay`], but the overlay can also use `%include` and `%import`. See [`bnf_to_grammar_with_loader`].
+    pub fn with_overlay_and_loader(&self, overlay : &str, mut loader : impl FnMut(&str) -> Result<String, String>) -> Result<Grammar, GrammarError>
+    {
+        bnf_compose(&self.source, overlay, &mut loader).and_then(bnf_build).map_err(|e| e.locate(overlay))
+    }
+    // Every `@guard` (false) and `!hook` (true) in the grammar, with the grammar point ID, alternation index and term index where it's used.
+    #[doc(hidden)]
+    pub fn guard_and_hook_uses(&self) -> impl Iterator<Item = (bool, &Rc<String>, usize, usize, usize)>
+    {
+        self.points.iter().flat_map(|p| p.forms.iter().enumerate().flat_map(move |(j, f)| f.matching_terms.iter().enumerate().filter_map(move |(k, t)| match &t.t
+        {
+            MatchingTermE::Guard(name) => Some((false, name, p.id as usize, j, k)),
+            MatchingTermE::Hook(name) => Some((true, name, p.id as usize, j, k)),
+            _ => None,
+        })))
+    }
+    // Fills in used_guards and used_hooks; called once the grammar's points are final.
+    pub (crate) fn find_guards_and_hooks(&mut self)
+    {
+        let names = |hooks : bool|
+        {
+            let mut ret = self.guard_and_hook_uses().filter(|x| x.0 == hooks).map(|x| x.1.to_string()).collect::<Vec<_>>();
+            ret.sort();
+            ret.dedup();
+            ret
+        };
+        (self.used_guards, self.used_hooks) = (names(false), names(true));
+    }
+    /// Names of the guards that the grammar uses (with `@guard(name)`), sorted. The guards map given to `pred_recdec::ast::parse` must have all of them.
+    pub fn required_guards(&self) -> Vec<String>
+    {
+        self.used_guards.clone()
+    }
+    /// Names of the hooks that the grammar uses (with `!hook(name)`), sorted. The hooks map given to `pred_recdec::ast::parse` must have all of them. The `init` hook is optional, and only listed if the grammar also uses it with `!hook(init)`.
+    pub fn required_hooks(&self) -> Vec<String>
+    {
+        self.used_hooks.clone()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Produced by [`tokenize`].
+///
+/// Next step: `pred_recdec::ast::parse`.
+pub struct Token {
+    /// Interned string ID, see [`Grammar::string_cache_inv`]
+    pub text : u32,
+    /// What line did it come from? **Note:** if you use regex comments, this will be inaccurate.
+    pub line : u32,
+    /// For bracket pairs: how far away, in which direction, is the paired bracket? In terms of tokens.
+    pub pair : isize,
+}
+
+// Sort literals from grammar by length and combine them into a single match-longest regex.
+pub (crate) fn build_literal_regex(literals : &Vec<String>, terminated : bool) -> Regex
+{
+    let mut text_token_regex_s = "\\A(?:".to_string();
+    
+    let mut lits = literals.clone();
+    lits.sort_by(|a, b| b.len().cmp(&a.len()));
+    for text in lits.iter()
+    {
+        let s2 = regex::escape(&text);
+        text_token_regex_s += &s2;
+        text_token_regex_s += "|";
+    }
+    if lits.len() > 0 { text_token_regex_s.pop(); }
+    text_token_regex_s += ")";
+    if terminated { text_token_regex_s += "\\z"; }
+    let text_token_regex = new_regex(&text_token_regex_s).unwrap();
+    text_token_regex
+}
+
+/// Tokenizer error state.
+#[derive(Clone, Debug)]
+pub struct TokError {
+    /// Human-readable error message. Format is not guaranteed and may change arbitrarily.
+    #[allow(unused)] pub err_message : String,
+    /// Number of tokens produced so far.
+    #[allow(unused)] pub produced : usize,
+    /// At what byte index in the input string did tokenization fail to continue?
+    #[allow(unused)] pub location : usize,
+    /// Is `Some` if tokenization failed because of failing to pair brackets:
+    ///
+    /// - `String`: responsible token text
+    /// - `bool`: openness
+    ///
+    /// If the bool is true, then the failed pairing is "open", like the string `()(`. Otherwise, it's "closed", like `())`.
+    ///
+    /// The responsible token is the token where the error was noticed, not the token that's "missing". E.g. for `())` it's `")"`.
+    ///
+    /// It is currently unspecified whether non-nested overlaps like `( { ) }` trigger an error.
+    ///
+    /// KNOWN BUG: "open" failed pairings like `()(` do not currently produce errors. They will become errors in a future version.
+    #[allow(unused)] pub pairing_error : Option<(String, bool)>,
+}
+
+/// Scans the given string and produces a stream of [`Token`]s.
+///
+/// Next step: `pred_recdec::ast::parse`.
+///
+/// The scanner performs maximal munch between all string literals and r``r terminals in the grammar. It also skips whitespace, and comments (as defined in the grammar). The produced tokens use string interning and can be bracket-paired. See [the pred-recdec crate docs](https://docs.rs/pred-recdec) for more details.
+pub fn tokenize(
+    g : &mut Grammar,
+    mut s : &str
+) -> Result<Vec<Token>, TokError>
+{
+    let s_orig = s;
+    let mut tokens = Vec::<Token>::new();
+    tokens.reserve(s.len()/16 + 1);
+    
+    let mut lit_filtered = Vec::new();
+    for l in g.literals.iter()
+    {
+        let mut covered = false;
+        for r in &g.regexes
+        {
+            if let Some(loc) = regex_find(&r.0, l).map(|x| rem_end(&x) - rem_start(&x))
+            {
+                if loc == l.len()
+                {
+                    covered = true;
+                    break;
+                }
+            }
+        }
+        if !covered
+        {
+            lit_filtered.push(l.clone());
+        }
+    }
+    let all_literals_regex = if lit_filtered.len() > 0
+    {
+        Some(build_literal_regex(&lit_filtered, false))
+    }
+    else
+    {
+        None
+    };
+    //println!("{}", all_literals_regex);
+    
+    for text in g.literals.iter()
+    {
+        string_cache_lookup_id(&mut g.string_cache, &mut g.string_cache_inv, text);
+    }
+    for point in g.points.iter()
+    {
+        string_cache_lookup_id(&mut g.string_cache, &mut g.string_cache_inv, &point.name);
+    }
+    
+    let mut openers = HashSet::default();
+    let mut closers = HashMap::default();
+    let mut stacks = HashMap::default();
+    let mut any_paired = false;
+    for (l, r) in &g.bracket_pairs
+    {
+        let lsc = string_cache_lookup_id(&mut g.string_cache, &mut g.string_cache_inv, &l);
+        let rsc = string_cache_lookup_id(&mut g.string_cache, &mut g.string_cache_inv, &r);
+        openers.insert(lsc);
+        closers.insert(rsc, lsc);
+        stacks.insert(lsc, Vec::<usize>::new());
+        any_paired = true;
+    }
+    
+    /*
+    println!("num regexes to check: {}", g.regexes.len());
+    for r in &g.regexes
+    {
+        println!("{:?}", r.as_str());
+    }
+    */
+    
+    let mut line = 1;
+    'top: while !s.is_empty()
+    {
+        if get_char_at_byte(s, 0) as u32 <= 0x20
+        {
+            if matches!(get_char_at_byte(s, 0), ' ' | '\r' | '\n' | '\t')
+            {
+                while !s.is_empty() && matches!(get_char_at_byte(s, 0), ' ' | '\r' | '\n' | '\t')
+                {
+                    if matches!(get_char_at_byte(s, 0), '\n')
+                    {
+                        line += 1;
+                    }
+                    s = &s[1..]; // ascii whitespace is always 1 byte long
+                }
+                if s.is_empty() { break; }
+                continue 'top;
+            }
+        }
+        
+        // Pure regex comments
+        for re in &g.comment_regexes
+        {
+            if let Some(x) = regex_find(re, s)
+            {
+                //s = &s[x.len()..];
+                s = &s[rem_end(&x) - rem_start(&x)..];
+                continue 'top;
+            }
+        }
+        // Comments with escapable newline handling
+        for c in &g.comments
+        {
+            if s.starts_with(c)
+            {
+                s = &s[c.len()..];
+                while s.len() > 0 && get_char_at_byte(s, 0) != '\n'
+                {
+                    if get_char_at_byte(s, 0) == '\\'
+                    {
+                        s = &s[get_char_at_byte(s, 0).len_utf8()..]; // extra skip
+                    }
+                    if s.len() > 0
+                    {
+                        s = &s[get_char_at_byte(s, 0).len_utf8()..];
+                    }
+                }
+                continue 'top;
+            }
+        }
+        // Pair comments with nesting
+        for (l, r) in &g.comment_pairs_nested
+        {
+            if s.starts_with(l)
+            {
+                s = &s[l.len()..];
+                let mut nest = 1;
+                while s.len() > 0 && nest > 0
+                {
+                    if matches!(get_char_at_byte(s, 0), '\n')
+                    {
+                        line += 1;
+                    }
+                    if s.starts_with(l) { nest += 1; }
+                    s = &s[get_char_at_byte(s, 0).len_utf8()..];
+                    if s.starts_with(r) { nest -= 1; }
+                }
+                if s.starts_with(r) { s = &s[r.len()..]; }
+                continue 'top;
+            }
+        }
+        // Pair comments without nesting
+        for (l, r) in &g.comment_pairs
+        {
+            if s.starts_with(l)
+            {
+                s = &s[l.len()..];
+                while s.len() > 0 && !s.starts_with(r)
+                {
+                    if matches!(get_char_at_byte(s, 0), '\n')
+                    {
+                        line += 1;
+                    }
+                    s = &s[get_char_at_byte(s, 0).len_utf8()..];
+                }
+                s = &s[r.len()..];
+                continue 'top;
+            }
+        }
+        // Maximal munch: Regex pass
+        let mut longest = 0;
+        //let mut found_regex = None;
+        for r in &g.regexes
+        {
+            if let Some(loc) = regex_find(&r.0, s)
+            {
+                let len = rem_end(&loc) - rem_start(&loc);
+                //found_regex = Some(&r.1);
+                longest = longest.max(len);
+            }
+        }
+        // Literals pass.
+        if let Some(all_literals_regex) = &all_literals_regex && let Some(loc) = regex_find(&all_literals_regex, s)
+        {
+            let len = rem_end(&loc) - rem_start(&loc);
+            //found_regex = None;
+            longest = longest.max(len);
+        }
+        if longest == 0
+        {
+            let sn : String = s.chars().take(5).collect();
+            return Err(TokError {
+                err_message: format!("Failed to tokenize at index {}:{}[...]", s_orig.len()-s.len(), sn),
+                produced : tokens.len(),
+                location : s_orig.len() - s.len(),
+                pairing_error : None,
+            });
+        }
+        
+        //let text_info = string_cache_lookup(&mut g.string_cache, &mut g.string_cache_inv, &s[..longest]);
+        //let text = text_info.1;
+        let text = string_cache_lookup_id(&mut g.string_cache, &mut g.string_cache_inv, &s[..longest]);
+        let mut token = Token { text, line, pair : 0 };
+        
+        /*
+        if let Some(r2) = found_regex
+        {
+            r2.cache2.borrow_mut().insert(text, true);
+            r2.cache.borrow_mut().insert(text_info.0, true);
+        }
+        */
+        
+        if any_paired
+        {
+            if openers.contains(&text) && let Some(s) = stacks.get_mut(&text)
+            {
+                s.push(tokens.len());
+            }
+            if let Some(l) = closers.get(&text) && let Some(stack) = stacks.get_mut(l)
+            {
+                let n = match stack.pop() {
+                    Some(n) => n,
+                    None => Err(TokError {
+                        err_message: format!("Unmatched delimiter at {}: {}", s_orig.len() - s.len(), g.string_cache_inv.get(text as usize).unwrap()),
+                        produced : tokens.len(),
+                        location : s_orig.len() - s.len(),
+                        pairing_error : Some((s[..longest].to_string(), false)),
+                    })?,
+                };
+                //.ok_or_else(|| )?;
+                
+                let me = tokens.len();
+                let diff = match me.checked_signed_diff(n) {
+                    Some(n) => n,
+                    None => Err(TokError {
+                        err_message: format!("Input too long"),
+                        produced : tokens.len(),
+                        location : s_orig.len() - s.len(),
+                        pairing_error : None,
+                    })?,
+                };
+                token.pair = -diff;
+                tokens[n].pair = diff;
+            }
+        }
+        
+        s = &s[longest..];
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
@@ -0,0 +1,543 @@
+// Native parser generation
+
+use crate::bnf::*;
+
+type HashMap<K, V> = std::collections::HashMap::<K, V, crate::HashBuilder>;
+
+/// Every regex in the grammar, in the order that parsers made by [`generate_rust`] index them. Used by generated parsers.
+pub fn regex_table(g : &Grammar) -> Vec<&RegexCacher>
+{
+    let mut ret = vec!();
+    for point in &g.points
+    {
+        for alt in &point.forms
+        {
+            for term in &alt.matching_terms
+            {
+                match &term.t
+                {
+                    MatchingTermE::TermRegex(r) | MatchingTermE::PeekR(_, r) | MatchingTermE::PeekRes(_, r) => ret.push(r),
+                    _ => {}
+                }
+            }
+        }
+        if let Some((r, _)) = &point.recover { ret.push(r); }
+    }
+    ret
+}
+
+// Everything in a generated parser except the rule functions and tables.
+const PRELUDE : &str = r#"
+    use std::rc::Rc;
+    use ::pred_recdec::ast::*;
+    use ::pred_recdec::bnf::*;
+    type HashMap<K, V> = std::collections::HashMap::<K, V, ::pred_recdec::HashBuilder>;
+    
+    struct Parser<'a, 'b>
+    {
+        global : PrdGlobal<'a>,
+        tokens : &'b [Token],
+        re : Vec<&'a RegexCacher>,
+    }
+    
+    struct WorkState
+    {
+        chosen : u32,
+        children : Vec<ASTNode>,
+        i : usize,
+        start : usize,
+        poisoned : bool,
+        // where to pick the rule back up once the child it's waiting on is done
+        at : usize,
+    }
+    
+    impl WorkState
+    {
+        fn new(id : usize, i : usize) -> Self
+        {
+            WorkState { chosen : NAME_IDS[id], children : vec!(), i, start : i, poisoned : false, at : 0 }
+        }
+    }
+    
+    enum Step { Done(ASTNode), Become(usize), Call(usize) }
+    
+    /// Parses the token stream with the grammar this parser was generated from. Same as [`pred_recdec::ast::parse`], but with the grammar compiled to native code.
+    ///
+    /// Panics if given a different grammar.
+    pub fn parse(
+        g : &Grammar, root_rule_name : &str, tokens : &[Token],
+        guards : Rc<HashMap<String, Guard>>,
+        hooks : Rc<HashMap<String, Hook>>,
+    ) -> Result<ASTNode, Box<PrdError>>
+    {
+        let matches = g.points.len() == RULE_NAMES.len() && g.points.iter().zip(RULE_NAMES).all(|(p, n)| *p.name == **n)
+            && STRINGS.iter().all(|(id, s)| g.string_cache_inv.get(*id as usize).is_some_and(|x| **x == **s));
+        assert!(matches, "This parser was generated from a different grammar");
+        let id = *g.by_name.get(root_rule_name).unwrap();
+        ::pred_recdec::ast::check_guards_and_hooks(g, &guards, &hooks)?;
+        let mut p = Parser { global : PrdGlobal::new(g, guards, hooks, tokens), tokens, re : ::pred_recdec::codegen::regex_table(g) };
+        run(&mut p, id)
+    }
+    
+    // Runs rules off of a stack instead of recursing, so there's no depth limit, same as pred_recdec::ast::parse.
+    fn run(p : &mut Parser, id : usize) -> Result<ASTNode, Box<PrdError>>
+    {
+        let mut stack : Vec<(usize, WorkState)> = vec!();
+        let mut id = id;
+        let mut ws = WorkState::new(id, 0);
+        loop
+        {
+            let mut result = match rule(p, id, &mut ws)
+            {
+                Ok(Step::Call(child)) =>
+                {
+                    let next = WorkState::new(child, ws.i);
+                    stack.push((id, std::mem::replace(&mut ws, next)));
+                    id = child;
+                    continue;
+                }
+                Ok(Step::Become(next)) =>
+                {
+                    id = next;
+                    ws.at = 0;
+                    continue;
+                }
+                Ok(Step::Done(node)) => Ok(node),
+                Err(e) => Err(e),
+            };
+            // hand the result to the rule waiting on it; if it failed, that rule fails too, unless it can recover
+            loop
+            {
+                let Some((parent, parent_ws)) = stack.pop() else { return result };
+                let child = id;
+                (id, ws) = (parent, parent_ws);
+                if result.is_err() && let Some((re, after)) = RECOVER[child] && let Some(node) = recover(p, &ws, child, re, after)
+                {
+                    result = Ok(node);
+                }
+                match result
+                {
+                    Ok(node) =>
+                    {
+                        push_child(&mut ws, node);
+                        break;
+                    }
+                    Err(mut e) =>
+                    {
+                        // same as pred_recdec::ast::parse with the deep_errors feature
+                        if ::pred_recdec::ast::DEEP_ERRORS { e.err_message = format!("In {}: {}", RULE_NAMES[id], e.err_message); }
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+    }
+    
+    #[cold]
+    #[inline(never)]
+    fn err(msg : String, i : usize, rule : u32, behalf : u32, alt : usize, prog : Option<u16>) -> Box<PrdError>
+    {
+        Box::new(PrdError { err_message : msg, token_index : i, rule, on_behalf_of_rule : behalf, in_alt : alt.wrapping_sub(1) as u16, alt_progress : prog })
+    }
+    
+    #[cold]
+    #[inline(never)]
+    fn fail(p : &Parser, ws : &WorkState, rule : u32, alt : usize, term : u16) -> Box<PrdError>
+    {
+        let token = p.tokens.get(ws.i).map(|t| p.global.g.string_cache_inv[t.text as usize].to_string()).unwrap_or("<no token>".to_string());
+        let msg = format!("Failed to match token at {} in rule {} alt {}. Token is `{}`.", ws.i, p.global.g.string_cache_inv[rule as usize], alt, token);
+        err(msg, ws.i, rule, ws.chosen, alt, Some(term))
+    }
+    
+    #[inline(always)]
+    fn peek(p : &Parser, ws : &WorkState, n : isize) -> Option<u32>
+    {
+        p.tokens.get((ws.i as isize + n) as usize).map(|t| t.text)
+    }
+    
+    #[inline(always)]
+    fn peek_regex(p : &Parser, ws : &WorkState, n : isize, re : usize) -> bool
+    {
+        peek(p, ws, n).is_some_and(|t| p.re[re].is_match_interned(t, &p.global.g.string_cache_inv))
+    }
+    
+    #[inline(always)]
+    fn token_regex(p : &Parser, ws : &WorkState, re : usize) -> bool
+    {
+        ws.i < p.tokens.len() && p.re[re].is_match_interned(p.tokens[ws.i].text, &p.global.g.string_cache_inv)
+    }
+    
+    #[inline(always)]
+    fn leaf(p : &Parser, ws : &mut WorkState)
+    {
+        ws.children.push(ASTNode::new(None, 1, p.tokens[ws.i].text));
+    }
+    
+    fn guard(p : &mut Parser, ws : &WorkState, name : &str, rule : u32, alt : usize) -> Result<bool, Box<PrdError>>
+    {
+        match p.global.guard(name)
+        {
+            Some(f) => match f(&mut p.global, p.tokens, ws.i)
+            {
+                GuardResult::Accept => Ok(true),
+                GuardResult::HardError(e) => Err(err(e, ws.i, rule, ws.chosen, alt, None)),
+                _ => Ok(false),
+            }
+            None => Err(err(format!("Unknown guard {name}"), ws.i, rule, ws.chosen, alt, None)),
+        }
+    }
+    
+    fn hook(p : &mut Parser, ws : &mut WorkState, name : &str, rule : u32, alt : usize, term : u16) -> Result<(), Box<PrdError>>
+    {
+        match p.global.hook(name)
+        {
+            Some(f) => match f(&mut p.global, p.tokens, ws.i, &mut ws.children)
+            {
+                Ok(consumed) => { ws.i += consumed; Ok(()) }
+                Err(e) => Err(err(e, ws.i, rule, ws.chosen, alt, Some(term))),
+            }
+            None =>
+            {
+                let msg = format!("Unknown custom hook {:?} inside of {}", name, p.global.g.string_cache_inv[ws.chosen as usize]);
+                Err(err(msg, ws.i, rule, ws.chosen, alt, Some(term)))
+            }
+        }
+    }
+    
+    #[inline(always)]
+    fn push_child(ws : &mut WorkState, child : ASTNode)
+    {
+        if child.is_poisoned()
+        {
+            ws.poisoned = true;
+        }
+        ws.i += child.get_real_token_count() as usize;
+        ws.children.push(child);
+    }
+    
+    // For rules with @recover or @recover_before that failed: skips ahead to the recovery point, if there is one.
+    fn recover(p : &Parser, ws : &WorkState, id : usize, re : usize, after : bool) -> Option<ASTNode>
+    {
+        let mut j = ws.i + 1;
+        while j < p.tokens.len() && !p.re[re].is_match(&p.global.g.string_cache_inv[p.tokens[j].text as usize])
+        {
+            j += 1;
+        }
+        if j >= p.tokens.len() { return None; }
+        if after { j += 1; }
+        Some(ASTNode::new(Some(vec!()), (j - ws.i) as u32 ^ !0u32, NAME_IDS[id]))
+    }
+    
+    #[inline(always)]
+    fn done(ws : &mut WorkState) -> Step
+    {
+        let mut token_count = (ws.i - ws.start) as u32;
+        if ws.poisoned
+        {
+            token_count = token_count ^ !0u32;
+        }
+        Step::Done(ASTNode::new(Some(std::mem::take(&mut ws.children)), token_count, ws.chosen))
+    }
+    
+    #[inline(always)]
+    fn done_empty(ws : &mut WorkState) -> Step
+    {
+        Step::Done(ASTNode::new(Some(std::mem::take(&mut ws.children)), (ws.i - ws.start) as u32, ws.chosen))
+    }
+"#;
+
+// Writes Rust code for one rule's alternations.
+struct RuleGen<'a> {
+    g : &'a Grammar,
+    regexes : &'a HashMap<*const RegexCacher, usize>,
+    strings : &'a mut Vec<u32>,
+    out : String,
+    indent : usize,
+    // code for picking the rule back up after each child it waits on, in order
+    resumes : Vec<String>,
+    // output and indentation set aside while writing a resume point's code, and which resume point it is
+    suspended : Vec<(String, usize, usize)>,
+}
+
+impl<'a> RuleGen<'a> {
+    fn line(&mut self, s : &str)
+    {
+        self.out += &" ".repeat(self.indent * 4);
+        self.out += s;
+        self.out += "\n";
+    }
+    fn re(&self, r : &RegexCacher) -> usize { self.regexes[&(r as *const RegexCacher)] }
+    fn string(&mut self, id : u32) -> u32
+    {
+        self.strings.push(id);
+        id
+    }
+    // Condition for taking an alternation, if it starts with a guard.
+    fn condition(&mut self, term : &MatchingTermE, rule : u32, alt : usize) -> Option<String>
+    {
+        Some(match term
+        {
+            MatchingTermE::Guard(name) => format!("guard(p, ws, {:?}, {rule}, {alt})?", name.as_str()),
+            MatchingTermE::Peek(n, s) => format!("peek(p, ws, {n}) == Some({})", self.string(*s)),
+            MatchingTermE::PeekR(n, r) => format!("peek_regex(p, ws, {n}, {})", self.re(r)),
+            MatchingTermE::PeekRes(n, r) => format!("peek_regex(p, ws, {n}, {}) && !::pred_recdec::bnf::is_reserved(p.global.g, peek(p, ws, {n}).unwrap())", self.re(r)),
+            MatchingTermE::Eof => "ws.i == p.tokens.len()".to_string(),
+            _ => return None,
+        })
+    }
+    // Code for the terms of an alternation, after its guard (if any). Ends by returning from the rule function or continuing its loop.
+    // Waiting on a child also returns, so everything after that goes in a new resume point.
+    fn terms(&mut self, point : &GrammarPoint, alt : usize, start : usize)
+    {
+        let base = self.suspended.len();
+        self.terms_inner(point, alt, start);
+        while self.suspended.len() > base
+        {
+            let (out, indent, k) = self.suspended.pop().unwrap();
+            self.resumes[k - 1] = std::mem::replace(&mut self.out, out);
+            self.indent = indent;
+        }
+    }
+    fn terms_inner(&mut self, point : &GrammarPoint, alt : usize, start : usize)
+    {
+        let rule = point.name_id;
+        let form = &point.forms[alt];
+        let terms = &form.matching_terms;
+        if terms.is_empty()
+        {
+            self.line("return Ok(done_empty(ws));");
+            return;
+        }
+        self.line(&format!("if ws.children.capacity() == 0 {{ ws.children.reserve_exact({}); }}", terms.len()));
+        let fail = |t : usize| format!("return Err(fail(p, ws, {rule}, {alt}, {t}));");
+        let mut t = start;
+        while t < terms.len()
+        {
+            let next_rule = match terms.get(t + 1).map(|x| &x.t) { Some(MatchingTermE::Rule(id)) => Some(*id), _ => None };
+            match &terms[t].t
+            {
+                MatchingTermE::Rule(id) =>
+                {
+                    self.resumes.push(String::new());
+                    let k = self.resumes.len();
+                    self.line(&format!("ws.at = {k};"));
+                    self.line(&format!("return Ok(Step::Call({id}));"));
+                    self.suspended.push((std::mem::take(&mut self.out), self.indent, k));
+                    self.indent = 0;
+                }
+                MatchingTermE::TermLit(s) =>
+                {
+                    let s = self.string(*s);
+                    self.line(&format!("if ws.i >= p.tokens.len() || p.tokens[ws.i].text != {s} {{ {} }}", fail(t)));
+                    if !form.pruned { self.line("leaf(p, ws);"); }
+                    self.line("ws.i += 1;");
+                }
+                MatchingTermE::TermRegex(r) =>
+                {
+                    self.line(&format!("if !token_regex(p, ws, {}) {{ {} }}", self.re(r), fail(t)));
+                    if !form.pruned { self.line("leaf(p, ws);"); }
+                    self.line("ws.i += 1;");
+                }
+                MatchingTermE::Directive(d @ (MatchDirective::Become | MatchDirective::BecomeAs)) =>
+                {
+                    match next_rule
+                    {
+                        Some(id) =>
+                        {
+                            if matches!(d, MatchDirective::BecomeAs) { self.line(&format!("ws.chosen = {};", self.g.points[id].name_id)); }
+                            if id == point.id as usize
+                            {
+                                if !self.suspended.is_empty() { self.line("ws.at = 0;"); }
+                                self.line("continue 'top;");
+                            }
+                            else { self.line(&format!("return Ok(Step::Become({id}));")); }
+                        }
+                        None => self.line(&fail(t)),
+                    }
+                    return;
+                }
+                MatchingTermE::Directive(MatchDirective::Rename) =>
+                {
+                    match next_rule
+                    {
+                        // (sets the rule's ID, not its name's ID, same as the interpreter)
+                        Some(id) => self.line(&format!("ws.chosen = {id};")),
+                        None => { self.line(&fail(t)); return; }
+                    }
+                    t += 1;
+                }
+                MatchingTermE::Directive(MatchDirective::Drop) =>
+                {
+                    self.line(&format!("if ws.children.pop().is_none() {{ {} }}", fail(t)));
+                }
+                MatchingTermE::Directive(MatchDirective::DropIfEmpty) =>
+                {
+                    self.line(&format!("match ws.children.last() {{ Some(c) => {{ if c.children.is_some() {{ ws.children.pop(); }} }} None => {{ {} }} }}", fail(t)));
+                }
+                MatchingTermE::Directive(MatchDirective::Hoist) =>
+                {
+                    self.line(&format!("match ws.children.pop() {{ Some(mut x) => {{ if let Some(mut c) = x.children.take() {{ ws.children.append(&mut c); }} }} None => {{ {} }} }}", fail(t)));
+                }
+                MatchingTermE::Directive(MatchDirective::HoistIfUnit) =>
+                {
+                    self.line(&format!("match ws.children.pop() {{ Some(mut x) => {{ if let Some(mut c) = x.children.take() {{ if c.len() == 1 {{ ws.children.append(&mut c); }} }} }} None => {{ {} }} }}", fail(t)));
+                }
+                MatchingTermE::Directive(MatchDirective::Any) =>
+                {
+                    self.line(&format!("if ws.i >= p.tokens.len() {{ {} }}", fail(t)));
+                    self.line("leaf(p, ws);");
+                    self.line("ws.i += 1;");
+                }
+                MatchingTermE::Hook(name) => self.line(&format!("hook(p, ws, {:?}, {rule}, {alt}, {t})?;", name.as_str())),
+                // grammars with guards anywhere but first are rejected when they're loaded
+                term => unreachable!("{term:?} can't come after the start of an alternation"),
+            }
+            t += 1;
+        }
+        self.line("return Ok(done(ws));");
+    }
+    fn block(&mut self, code : &str)
+    {
+        for l in code.lines() { self.line(l); }
+    }
+    fn rule(&mut self, point : &GrammarPoint)
+    {
+        let self_become = point.forms.iter().any(|alt| alt.matching_terms.windows(2).any(|w|
+            matches!(w[0].t, MatchingTermE::Directive(MatchDirective::Become | MatchDirective::BecomeAs))
+            && matches!(w[1].t, MatchingTermE::Rule(id) if id == point.id as usize)));
+        
+        // choosing an alternation and running it up to its first child, then each resume point
+        let (out, indent) = (std::mem::take(&mut self.out), self.indent);
+        self.indent = 0;
+        let mut exhaustive = false;
+        for (k, alt) in point.forms.iter().enumerate()
+        {
+            let cond = alt.matching_terms.first().and_then(|t| self.condition(&t.t, point.name_id, k));
+            match cond
+            {
+                Some(cond) =>
+                {
+                    self.line(&format!("if {cond}"));
+                    self.line("{");
+                    self.indent += 1;
+                    self.terms(point, k, 1);
+                    self.indent -= 1;
+                    self.line("}");
+                }
+                // an alternation without a guard is always taken, so the rest are unreachable
+                None =>
+                {
+                    self.terms(point, k, 0);
+                    exhaustive = true;
+                    break;
+                }
+            }
+        }
+        if !exhaustive
+        {
+            let msg = format!("Failed to match rule {} at token position {{}}", point.name);
+            self.line(&format!("let msg = format!({msg:?}, ws.start);"));
+            self.line(&format!("return Err(err(msg, ws.i, {}, ws.chosen, {}, Some({})));", point.name_id, point.forms.len(), point.forms.len()));
+        }
+        let arms = std::iter::once(std::mem::replace(&mut self.out, out)).chain(std::mem::take(&mut self.resumes)).collect::<Vec<_>>();
+        self.indent = indent;
+        
+        self.line(&format!("// {}", point.name));
+        self.line(&format!("fn rule_{}(p : &mut Parser, ws : &mut WorkState) -> Result<Step, Box<PrdError>>", point.id));
+        self.line("{");
+        self.indent += 1;
+        if self_become
+        {
+            self.line("'top: loop");
+            self.line("{");
+            self.indent += 1;
+        }
+        if arms.len() == 1
+        {
+            self.block(&arms[0]);
+        }
+        else
+        {
+            self.line("match ws.at");
+            self.line("{");
+            self.indent += 1;
+            for (k, arm) in arms.iter().enumerate()
+            {
+                self.line(&format!("{k} =>"));
+                self.line("{");
+                self.indent += 1;
+                self.block(arm);
+                self.indent -= 1;
+                self.line("}");
+            }
+            self.line("_ => unreachable!(),");
+            self.indent -= 1;
+            self.line("}");
+        }
+        if self_become
+        {
+            self.indent -= 1;
+            self.line("}");
+        }
+        self.indent -= 1;
+        self.line("}");
+    }
+}
+
+/// Generates the source code of a native Rust parser for the grammar. Each rule becomes a function, peeks become direct comparisons of interned string IDs, and `$become` becomes a loop. This avoids the overhead of interpreting the grammar.
+///
+/// The generated code defines a `parse` function with the same signature as `pred_recdec::ast::parse`, which produces the same ASTs and errors, and calls guards and hooks the same way. Like `parse`, it keeps its own stack instead of recursing, so there's no depth limit. That includes the rules that errors came up through, with the `deep_errors` feature. It must be given the same grammar it was generated from (e.g. loaded with [`bnf_to_grammar`] from the same source, or with [`Grammar::from_bytes`]), and panics otherwise. It doesn't support observers.
+///
+/// The output is meant to be written to a file by a build script (or by `prd codegen`) and used with `include!` inside a module, e.g. `mod c_parser { include!(concat!(env!("OUT_DIR"), "/c_parser.rs")); }`. The `grammar!` macro can also generate it, with its `native` option.
+pub fn generate_rust(g : &Grammar) -> String
+{
+    let regexes = regex_table(g).into_iter().enumerate().map(|(i, r)| (r as *const RegexCacher, i)).collect::<HashMap<_, _>>();
+    let mut strings = vec!();
+    let mut rules = String::new();
+    for point in &g.points
+    {
+        let mut rg = RuleGen { g, regexes : &regexes, strings : &mut strings, out : String::new(), indent : 1, resumes : vec!(), suspended : vec!() };
+        rg.rule(point);
+        rules += "    \n";
+        rules += &rg.out;
+    }
+    strings.sort();
+    strings.dedup();
+    
+    let mut out = String::new();
+    out += "// Generated by pred_recdec::codegen::generate_rust. Don't edit this file; regenerate it from the grammar instead.\n";
+    out += "\n";
+    out += "pub use self::prd_generated::parse;\n";
+    out += "\n";
+    out += "#[allow(unused, non_snake_case, clippy::all)]\n";
+    out += "mod prd_generated\n";
+    out += "{";
+    out += PRELUDE;
+    out += "    \n";
+    out += "    // the rules and the interned strings that this parser was generated for, to check that it's given the same grammar\n";
+    out += &format!("    const RULE_NAMES : &[&str] = &[{}];\n", g.points.iter().map(|p| format!("{:?}", p.name.as_str())).collect::<Vec<_>>().join(", "));
+    out += &format!("    const STRINGS : &[(u32, &str)] = &[{}];\n", strings.iter().map(|s| format!("({s}, {:?})", g.string_cache_inv[*s as usize].as_str())).collect::<Vec<_>>().join(", "));
+    out += &format!("    const NAME_IDS : &[u32] = &[{}];\n", g.points.iter().map(|p| p.name_id.to_string()).collect::<Vec<_>>().join(", "));
+    out += "    \n";
+    let recover = g.points.iter().map(|p| match &p.recover
+    {
+        Some((r, after)) => format!("Some(({}, {after}))", regexes[&(r as *const RegexCacher)]),
+        None => "None".to_string(),
+    });
+    out += &format!("    const RECOVER : &[Option<(usize, bool)>] = &[{}];\n", recover.collect::<Vec<_>>().join(", "));
+    out += "    \n";
+    out += "    fn rule(p : &mut Parser, id : usize, ws : &mut WorkState) -> Result<Step, Box<PrdError>>\n";
+    out += "    {\n";
+    out += "        match id\n";
+    out += "        {\n";
+    for point in &g.points
+    {
+        out += &format!("            {} => rule_{}(p, ws),\n", point.id, point.id);
+    }
+    out += "            _ => unreachable!(),\n";
+    out += "        }\n";
+    out += "    }\n";
+    out += &rules;
+    out += "}\n";
+    out
+}
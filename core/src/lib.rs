@@ -0,0 +1,13 @@
+//! Grammar building, analysis, serialization and code generation for [pred-recdec](https://docs.rs/pred-recdec), shared by pred-recdec and its `grammar!` macro. Use it through pred-recdec, which re-exports these modules, rather than depending on this crate directly. Items hidden from the docs are only public for pred-recdec's own use, and aren't part of the API.
+
+/// Module for BNF and Grammar-related stuff.
+pub mod bnf;
+/// Module for saving compiled grammars and loading them back.
+pub mod serialize;
+/// Module for generating native Rust parsers from grammars.
+pub mod codegen;
+/// Module for finding mistakes in grammars without parsing anything.
+pub mod analysis;
+
+/// Hasher used by this crate's hash maps, e.g. the ones that hold guards and hooks.
+pub use rustc_hash::FxBuildHasher as HashBuilder;
@@ -0,0 +1,292 @@
+// Precompiled grammars
+
+use crate::bnf::*;
+use std::rc::Rc;
+
+const MAGIC : &[u8; 4] = b"PRDG";
+
+// Terms are stored as a tag byte followed by their data.
+const TAG_RULE : u8 = 0;
+const TAG_LIT : u8 = 1;
+const TAG_REGEX : u8 = 2;
+const TAG_DIRECTIVE : u8 = 3;
+const TAG_HOOK : u8 = 4;
+const TAG_AUTO : u8 = 5;
+const TAG_EOF : u8 = 6;
+const TAG_PEEK : u8 = 7;
+const TAG_PEEKR : u8 = 8;
+const TAG_PEEKRES : u8 = 9;
+const TAG_GUARD : u8 = 10;
+
+const DIRECTIVES : [MatchDirective; 8] = [
+    MatchDirective::Any, MatchDirective::Become, MatchDirective::BecomeAs, MatchDirective::Hoist,
+    MatchDirective::HoistIfUnit, MatchDirective::Drop, MatchDirective::DropIfEmpty, MatchDirective::Rename,
+];
+
+#[derive(Default)]
+struct Writer {
+    out : Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, n : u8) { self.out.push(n); }
+    fn u32(&mut self, n : u32) { self.out.extend_from_slice(&n.to_le_bytes()); }
+    fn i64(&mut self, n : i64) { self.out.extend_from_slice(&n.to_le_bytes()); }
+    fn len(&mut self, n : usize) { self.u32(n.try_into().unwrap()); }
+    fn str(&mut self, s : &str)
+    {
+        self.len(s.len());
+        self.out.extend_from_slice(s.as_bytes());
+    }
+    fn strs(&mut self, list : &[String])
+    {
+        self.len(list.len());
+        for s in list { self.str(s); }
+    }
+    fn pairs(&mut self, list : &[(String, String)])
+    {
+        self.len(list.len());
+        for (l, r) in list
+        {
+            self.str(l);
+            self.str(r);
+        }
+    }
+    fn regex(&mut self, r : &RegexCacher) { self.str(r.pattern()); }
+    // Hooks and guards hold their name as an interned string, so store its ID instead of the text
+    fn interned(&mut self, g : &Grammar, s : &Rc<String>) { self.u32(g.string_cache[&**s]); }
+}
+
+struct Reader<'a> {
+    data : &'a [u8],
+    pos : usize,
+    // regexes with the same pattern share their match cache, like in a freshly-built grammar
+    cache_pool : RegexCachePool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n : usize) -> Result<&'a [u8], String>
+    {
+        let ret = self.data.get(self.pos..self.pos.saturating_add(n)).ok_or_else(|| "Truncated grammar data".to_string())?;
+        self.pos += n;
+        Ok(ret)
+    }
+    fn u8(&mut self) -> Result<u8, String> { Ok(self.bytes(1)?[0]) }
+    fn u32(&mut self) -> Result<u32, String> { Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap())) }
+    fn i64(&mut self) -> Result<i64, String> { Ok(i64::from_le_bytes(self.bytes(8)?.try_into().unwrap())) }
+    fn len(&mut self) -> Result<usize, String> { Ok(self.u32()? as usize) }
+    fn bool(&mut self) -> Result<bool, String> { Ok(self.u8()? != 0) }
+    fn str(&mut self) -> Result<String, String>
+    {
+        let n = self.len()?;
+        String::from_utf8(self.bytes(n)?.to_vec()).map_err(|_| "Invalid UTF-8 in grammar data".to_string())
+    }
+    fn strs(&mut self) -> Result<Vec<String>, String>
+    {
+        (0..self.len()?).map(|_| self.str()).collect()
+    }
+    fn pairs(&mut self) -> Result<Vec<(String, String)>, String>
+    {
+        (0..self.len()?).map(|_| Ok((self.str()?, self.str()?))).collect()
+    }
+    fn regex(&mut self) -> Result<RegexCacher, String>
+    {
+        let pattern = self.str()?;
+        let re = new_regex(&pattern).map_err(|e| format!("Invalid regex '{}': {}", pattern, e))?;
+        Ok(RegexCacher::new_with_pool(pattern, re, &mut self.cache_pool))
+    }
+    fn string_id(&mut self, g : &Grammar) -> Result<u32, String>
+    {
+        let id = self.u32()?;
+        if id as usize >= g.string_cache_inv.len() { return Err(format!("Invalid string ID {id} in grammar data")); }
+        Ok(id)
+    }
+    fn interned(&mut self, g : &Grammar) -> Result<Rc<String>, String>
+    {
+        let id = self.string_id(g)?;
+        Ok(Rc::clone(&g.string_cache_inv[id as usize]))
+    }
+}
+
+impl Grammar {
+    /// Serializes the grammar to a compact binary format, which [`Grammar::from_bytes`] can load back much faster than [`bnf_to_grammar`] can build it from BNF.
+    ///
+    /// The data is only meant to be loaded by the same version of this crate that saved it. The interned strings are saved as they currently are, so a grammar that has already been used for tokenizing also saves the strings it has seen.
+    ///
+    /// ```
+    /// # use pred_recdec::bnf::*;
+    /// let g = bnf_to_grammar(r#"S ::= "a" r`[0-9]+`r"#).unwrap();
+    /// let bytes = g.to_bytes();
+    /// let g2 = Grammar::from_bytes(&bytes).unwrap();
+    /// assert_eq!(g.to_bnf(), g2.to_bnf());
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let mut w = Writer::default();
+        w.out.extend_from_slice(MAGIC);
+        w.str(env!("CARGO_PKG_VERSION"));
+        
+        w.len(self.string_cache_inv.len());
+        for s in &self.string_cache_inv { w.str(s); }
+        w.strs(&self.literals);
+        w.len(self.regexes.len());
+        for (r, r2) in &self.regexes
+        {
+            w.str(r.as_str());
+            w.regex(r2);
+        }
+        
+        w.pairs(&self.bracket_pairs);
+        w.strs(&self.comments);
+        w.pairs(&self.comment_pairs);
+        w.pairs(&self.comment_pairs_nested);
+        w.len(self.comment_regexes.len());
+        for r in &self.comment_regexes { w.str(r.as_str()); }
+        w.u8(self.reserved.is_some() as u8);
+        w.strs(&self.reserved_words);
+        
+        w.len(self.source.len());
+        for (name, forms) in &self.source
+        {
+            w.str(name);
+            w.len(forms.len());
+            for alt in forms { w.strs(alt); }
+        }
+        
+        w.len(self.points.len());
+        for point in &self.points
+        {
+            w.u32(point.name_id);
+            w.len(point.forms.len());
+            for alt in &point.forms
+            {
+                w.u8(alt.pruned as u8);
+                w.len(alt.matching_terms.len());
+                for term in &alt.matching_terms
+                {
+                    match &term.t
+                    {
+                        MatchingTermE::Rule(id) => { w.u8(TAG_RULE); w.len(*id); }
+                        MatchingTermE::TermLit(s) => { w.u8(TAG_LIT); w.u32(*s); }
+                        MatchingTermE::TermRegex(r) => { w.u8(TAG_REGEX); w.regex(r); }
+                        MatchingTermE::Directive(d) =>
+                        {
+                            w.u8(TAG_DIRECTIVE);
+                            w.u8(DIRECTIVES.iter().position(|x| x.as_str() == d.as_str()).unwrap() as u8);
+                        }
+                        MatchingTermE::Hook(name) => { w.u8(TAG_HOOK); w.interned(self, name); }
+                        MatchingTermE::_AutoTemp => w.u8(TAG_AUTO),
+                        MatchingTermE::Eof => w.u8(TAG_EOF),
+                        MatchingTermE::Peek(n, s) => { w.u8(TAG_PEEK); w.i64(*n as i64); w.u32(*s); }
+                        MatchingTermE::PeekR(n, r) => { w.u8(TAG_PEEKR); w.i64(*n as i64); w.regex(r); }
+                        MatchingTermE::PeekRes(n, r) => { w.u8(TAG_PEEKRES); w.i64(*n as i64); w.regex(r); }
+                        MatchingTermE::Guard(name) => { w.u8(TAG_GUARD); w.interned(self, name); }
+                    }
+                }
+            }
+            w.u8(point.recover.is_some() as u8);
+            if let Some((r, seek_past)) = &point.recover
+            {
+                w.regex(r);
+                w.u8(*seek_past as u8);
+            }
+        }
+        w.out
+    }
+    /// Loads a grammar saved with [`Grammar::to_bytes`].
+    ///
+    /// This skips parsing and validating BNF, but regexes still have to be compiled. Data from a different version of this crate is rejected, and corrupt data returns an error instead of producing a broken grammar.
+    ///
+    /// Next step: [`tokenize`].
+    pub fn from_bytes(data : &[u8]) -> Result<Grammar, String>
+    {
+        let mut r = Reader { data, pos : 0, cache_pool : <_>::default() };
+        if r.bytes(4).ok() != Some(&MAGIC[..]) { return Err("Not a precompiled grammar".to_string()); }
+        let version = r.str()?;
+        if version != env!("CARGO_PKG_VERSION")
+        {
+            return Err(format!("Grammar was saved by version {version} of this crate, but this is version {}; rebuild it from BNF", env!("CARGO_PKG_VERSION")));
+        }
+        
+        let mut g = Grammar::default();
+        for _ in 0..r.len()?
+        {
+            let s = r.str()?;
+            string_cache_lookup(&mut g.string_cache, &mut g.string_cache_inv, &s);
+        }
+        g.literals = r.strs()?;
+        for _ in 0..r.len()?
+        {
+            let pattern = r.str()?;
+            let re = new_regex(&pattern).map_err(|e| format!("Invalid regex '{}': {}", pattern, e))?;
+            g.regexes.push((re, r.regex()?));
+        }
+        
+        g.bracket_pairs = r.pairs()?;
+        g.comments = r.strs()?;
+        g.comment_pairs = r.pairs()?;
+        g.comment_pairs_nested = r.pairs()?;
+        for pattern in r.strs()?
+        {
+            g.comment_regexes.push(new_regex(&pattern).map_err(|e| format!("Invalid regex '{}': {}", pattern, e))?);
+        }
+        let has_reserved = r.bool()?;
+        g.reserved_words = r.strs()?;
+        if has_reserved { g.reserved = Some(build_literal_regex(&g.reserved_words, true)); }
+        
+        for _ in 0..r.len()?
+        {
+            let name = r.str()?;
+            let forms = (0..r.len()?).map(|_| r.strs()).collect::<Result<_, _>>()?;
+            g.source.push((name, forms));
+        }
+        
+        let num_points = r.len()?;
+        for id in 0..num_points
+        {
+            let name_id = r.string_id(&g)?;
+            let name = Rc::clone(&g.string_cache_inv[name_id as usize]);
+            let mut forms = vec!();
+            for _ in 0..r.len()?
+            {
+                let pruned = r.bool()?;
+                let mut matching_terms = vec!();
+                for _ in 0..r.len()?
+                {
+                    let term = match r.u8()?
+                    {
+                        TAG_RULE =>
+                        {
+                            let id = r.len()?;
+                            if id >= num_points { return Err(format!("Invalid rule ID {id} in grammar data")); }
+                            MatchingTermE::Rule(id)
+                        }
+                        TAG_LIT => MatchingTermE::TermLit(r.string_id(&g)?),
+                        TAG_REGEX => MatchingTermE::TermRegex(r.regex()?),
+                        TAG_DIRECTIVE =>
+                        {
+                            let d = r.u8()?;
+                            MatchingTermE::Directive(DIRECTIVES.get(d as usize).ok_or_else(|| format!("Invalid directive {d} in grammar data"))?.clone())
+                        }
+                        TAG_HOOK => MatchingTermE::Hook(r.interned(&g)?),
+                        TAG_AUTO => MatchingTermE::_AutoTemp,
+                        TAG_EOF => MatchingTermE::Eof,
+                        TAG_PEEK => MatchingTermE::Peek(r.i64()? as isize, r.string_id(&g)?),
+                        TAG_PEEKR => MatchingTermE::PeekR(r.i64()? as isize, r.regex()?),
+                        TAG_PEEKRES => MatchingTermE::PeekRes(r.i64()? as isize, r.regex()?),
+                        TAG_GUARD => MatchingTermE::Guard(r.interned(&g)?),
+                        tag => return Err(format!("Invalid term tag {tag} in grammar data")),
+                    };
+                    matching_terms.push(term.to());
+                }
+                forms.push(Alternation { matching_terms, pruned });
+            }
+            let recover = if r.bool()? { Some((r.regex()?, r.bool()?)) } else { None };
+            if g.by_name.insert(name.to_string(), id).is_some() { return Err(format!("Duplicate rule {name} in grammar data")); }
+            g.points.push(GrammarPoint { name, name_id, id : id as u32, forms, recover });
+        }
+        if r.pos != data.len() { return Err("Trailing bytes after grammar data".to_string()); }
+        g.find_guards_and_hooks();
+        Ok(g)
+    }
+}
@@ -0,0 +1,172 @@
+//! Compile-time grammar embedding for [pred-recdec](https://docs.rs/pred-recdec). Use it through `pred_recdec::grammar!` (with the `macros` feature) rather than depending on this crate directly.
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{LitStr, Token};
+
+use pred_recdec_core::{bnf::*, codegen};
+
+struct Args {
+    path : LitStr,
+    intern : Vec<LitStr>,
+    native : bool,
+}
+
+impl Parse for Args {
+    fn parse(input : ParseStream) -> syn::Result<Self>
+    {
+        let path = input.parse()?;
+        let mut intern = vec!();
+        let mut native = false;
+        while input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty()
+        {
+            let key : syn::Ident = input.parse()?;
+            if key == "native"
+            {
+                native = true;
+                continue;
+            }
+            if key != "intern" { return Err(syn::Error::new(key.span(), "expected `intern = [\"...\", ...]` or `native`")); }
+            input.parse::<Token![=]>()?;
+            let content;
+            syn::bracketed!(content in input);
+            intern = Punctuated::<LitStr, Token![,]>::parse_terminated(&content)?.into_iter().collect();
+        }
+        Ok(Args { path, intern, native })
+    }
+}
+
+// Turns a rule name or literal into an UPPER_SNAKE_CASE constant name, if it has a sensible one.
+fn const_name(s : &str) -> Option<String>
+{
+    // separators in rule names, e.g. from %import or templates, don't get spelled out
+    let name_like = s.starts_with(|c : char| c.is_alphanumeric() || c == '_');
+    let mut parts = vec!();
+    let mut word = String::new();
+    for c in s.chars()
+    {
+        if c.is_ascii_alphanumeric() || c == '_'
+        {
+            word.push(c.to_ascii_uppercase());
+            continue;
+        }
+        if !word.is_empty() { parts.push(std::mem::take(&mut word)); }
+        parts.push(match c
+        {
+            '.' | '<' | '>' | ',' | '"' if name_like => continue,
+            '*' => "STAR", '/' => "SLASH", '%' => "PERCENT", '+' => "PLUS", '-' => "MINUS",
+            '<' => "LT", '>' => "GT", '=' => "EQ", '!' => "BANG", '&' => "AMP", '|' => "PIPE",
+            '^' => "CARET", '~' => "TILDE", '(' => "LPAREN", ')' => "RPAREN", '[' => "LBRACKET",
+            ']' => "RBRACKET", '{' => "LBRACE", '}' => "RBRACE", ',' => "COMMA", ';' => "SEMI",
+            ':' => "COLON", '.' => "DOT", '?' => "QUESTION", '#' => "HASH", '"' => "QUOTE",
+            '\'' => "APOS", '\\' => "BACKSLASH", '@' => "AT", '$' => "DOLLAR",
+            _ => return None,
+        }.to_string());
+    }
+    if !word.is_empty() { parts.push(word); }
+    let name = parts.join("_");
+    if name.is_empty() || name == "_" || name.starts_with(|c : char| c.is_ascii_digit()) { return None; }
+    Some(name)
+}
+
+/// Builds a grammar at compile time, and generates items for using it:
+///
+/// - `fn grammar() -> Grammar`, which loads the precompiled grammar (see `Grammar::from_bytes`).
+/// - `mod rules`, with the ID (index in `Grammar::points`) of each rule, e.g. `rules::TRANSLATION_UNIT`.
+/// - `mod rule_names`, with the interned string ID of each rule's name, which is what `ASTNode::text` holds for that rule's nodes.
+/// - `mod literals`, with the interned string ID of each literal, e.g. `literals::STAR` for `"*"`, plus any strings given in `intern = [...]`. Strings that only regexes match (e.g. operators matched by ```r`[+\-]`r```) aren't interned until they're tokenized, so list them there to get a constant for them.
+/// - With the `native` option, `fn parse`, a native parser for the grammar (see `pred_recdec::codegen::generate_rust`), which is a faster drop-in replacement for `pred_recdec::ast::parse`.
+///
+/// Names that don't turn into a unique constant name are skipped. The path is relative to the crate's `Cargo.toml`, like `include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/..."))`. `%include` and `%import` paths are relative to the grammar file. Invalid grammars are compile errors.
+///
+/// ```ignore
+/// mod c_grammar { pred_recdec::grammar!("src/grammar_c.txt", intern = ["+", "-"], native); }
+/// let mut g = c_grammar::grammar();
+/// ```
+#[proc_macro]
+pub fn grammar(input : TokenStream) -> TokenStream
+{
+    let args = syn::parse_macro_input!(input as Args);
+    let span = args.path.span();
+    let error = |e : String| TokenStream::from(syn::Error::new(span, e).to_compile_error());
+    
+    let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let path = std::path::Path::new(&dir).join(args.path.value());
+    let source = match std::fs::read_to_string(&path)
+    {
+        Ok(x) => x,
+        Err(e) => return error(format!("Failed to read {}: {e}", path.display())),
+    };
+    // every file the grammar is built from, so that cargo rebuilds when one changes
+    let mut files = vec!(path.display().to_string());
+    let grammar_dir = path.parent().map(|d| d.to_path_buf()).unwrap_or_default();
+    let loader = |p : &str|
+    {
+        let p = grammar_dir.join(p);
+        files.push(p.display().to_string());
+        std::fs::read_to_string(&p).map_err(|e| format!("Failed to read {}: {e}", p.display()))
+    };
+    let mut g = match bnf_to_grammar_with_loader(&source, loader)
+    {
+        Ok(g) => g,
+        Err(e) => return error(format!("Invalid grammar {}: {e}", args.path.value())),
+    };
+    
+    let mut literal_ids = vec!();
+    for s in g.literals.clone().iter().chain(args.intern.iter().map(|s| s.value()).collect::<Vec<_>>().iter())
+    {
+        literal_ids.push((s.clone(), string_cache_lookup(&mut g.string_cache, &mut g.string_cache_inv, s).1));
+    }
+    
+    let mut seen = std::collections::HashSet::new();
+    let mut rules = vec!();
+    let mut rule_names = vec!();
+    for point in &g.points
+    {
+        let Some(name) = const_name(&point.name) else { continue };
+        if !seen.insert(name.clone()) { continue; }
+        let (doc, ident, id, name_id) = (format!("`{}`", point.name), format_ident!("{name}"), point.id as usize, point.name_id);
+        rules.push(quote! { #[doc = #doc] pub const #ident : usize = #id; });
+        rule_names.push(quote! { #[doc = #doc] pub const #ident : u32 = #name_id; });
+    }
+    let mut seen = std::collections::HashSet::new();
+    let mut literals = vec!();
+    for (s, id) in literal_ids
+    {
+        let Some(name) = const_name(&s) else { continue };
+        if !seen.insert(name.clone()) { continue; }
+        let (doc, ident) = (format!("`{s:?}`"), format_ident!("{name}"));
+        literals.push(quote! { #[doc = #doc] pub const #ident : u32 = #id; });
+    }
+    
+    let bytes = proc_macro2::Literal::byte_string(&g.to_bytes());
+    let native = match args.native
+    {
+        true => match codegen::generate_rust(&g).parse::<proc_macro2::TokenStream>()
+        {
+            Ok(x) => x,
+            Err(e) => return error(format!("Failed to generate a native parser: {e}")),
+        }
+        false => quote! {},
+    };
+    quote! {
+        const _ : () = { #( let _ = include_bytes!(#files); )* };
+        /// Loads the grammar that was built at compile time.
+        pub fn grammar() -> ::pred_recdec::bnf::Grammar
+        {
+            ::pred_recdec::bnf::Grammar::from_bytes(#bytes).unwrap()
+        }
+        /// Rule IDs (indexes in `Grammar::points`).
+        #[allow(dead_code)]
+        pub mod rules { #(#rules)* }
+        /// Interned string IDs of rule names, as found in `ASTNode::text`.
+        #[allow(dead_code)]
+        pub mod rule_names { #(#rule_names)* }
+        /// Interned string IDs of literals.
+        #[allow(dead_code)]
+        pub mod literals { #(#literals)* }
+        #native
+    }.into()
+}
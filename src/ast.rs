@@ -5,6 +5,7 @@ use std::rc::Rc;
 type HashMap<K, V> = std::collections::HashMap::<K, V, crate::HashBuilder>;
 
 use crate::bnf::*;
+use crate::trace::*;
 
 #[derive(Clone, Debug, Default)]
 /// AST node. Grammar rules have children, tokens do not.
@@ -117,6 +118,24 @@ pub struct PrdGlobal<'a> {
     #[allow(unused)] pub g : &'a Grammar,
 }
 
+impl<'a> PrdGlobal<'a> {
+    /// Sets up the state for parsing `tokens`, and calls the `init` hook if there is one. The parse functions in this module do this themselves; it's for parsers made with [`codegen`](super::codegen).
+    pub fn new(g : &'a Grammar, guards : Rc<HashMap<String, Guard>>, hooks : Rc<HashMap<String, Hook>>, tokens : &[Token]) -> Self
+    {
+        let mut global = PrdGlobal { guards, hooks, udata : <_>::default(), udata_r : <_>::default(), g };
+        if let Some(f) = global.hooks.get("init")
+        {
+            let f = Rc::clone(&f);
+            let _ = f(&mut global, tokens, 0, &mut vec!());
+        }
+        global
+    }
+    /// Look up one of the guards passed into the parse function.
+    pub fn guard(&self, name : &str) -> Option<Guard> { self.guards.get(name).cloned() }
+    /// Look up one of the hooks passed into the parse function.
+    pub fn hook(&self, name : &str) -> Option<Hook> { self.hooks.get(name).cloned() }
+}
+
 /// Parser error state.
 #[derive(Clone, Debug)]
 pub struct PrdError {
@@ -140,7 +159,7 @@ pub struct PrdError {
     #[allow(unused)] pub on_behalf_of_rule : u32,
 }
 
-struct WorkState<'a> { 
+pub (crate) struct WorkState<'a> { 
     pub (crate) g_item : &'a GrammarPoint,
     pub (crate) chosen_name_id : u32,
     pub (crate) children : Vec<ASTNode>,
@@ -188,8 +207,8 @@ fn token_name(cache : &Vec<Rc<String>>, t : Option<&Token>) -> String
 }
 
 #[inline(always)]
-fn handle_matchterm(
-    global : &mut PrdGlobal, tokens : &[Token],
+fn handle_matchterm<O : ParseObserver + ?Sized>(
+    global : &mut PrdGlobal, obs : &mut O, tokens : &[Token],
     ws : &mut WorkState, term: &MatchingTermE, matched : &mut bool)
     -> Result<(), Box<PrdError>>
 {
@@ -276,7 +295,11 @@ fn handle_matchterm(
             if let Some(f) = global.hooks.get(&**name)
             {
                 let f = Rc::clone(&f);
-                match f(global, tokens, ws.i, &mut ws.children)
+                let start = if obs.wants_timing() { Some(std::time::Instant::now()) } else { None };
+                let result = f(global, tokens, ws.i, &mut ws.children);
+                if let Some(start) = start { obs.hook_timed(ws.g_item.id as usize, name, start.elapsed()); }
+                obs.hook_called(ws.g_item.id as usize, name, ws.i, &result);
+                match result
                 {
                     Ok(consumed) => { ws.i += consumed; }
                     Err(e) => build_err!(Some(ws.term_idx as u16), ws, "{}", e)?
@@ -375,8 +398,8 @@ fn handle_acceptance(
 }
 
 #[inline(always)]
-fn check_recovery(
-    global : &mut PrdGlobal, tokens : &[Token],
+fn check_recovery<O : ParseObserver + ?Sized>(
+    global : &mut PrdGlobal, obs : &mut O, tokens : &[Token],
     ws : &mut WorkState, child : &mut Result<ASTNode, Box<PrdError>>, id : usize)
     -> Result<(), Box<PrdError>>
 {
@@ -392,6 +415,7 @@ fn check_recovery(
             if j < tokens.len()
             {
                 if *after { j += 1; }
+                obs.recovered(id, ws.i, j);
                 *child = Ok(ASTNode::new(Some(vec!()), (j - ws.i) as u32 ^ !0u32, global.g.points[id].name_id));
             }
         }
@@ -399,6 +423,16 @@ fn check_recovery(
     Ok(())
 }
 
+/// Whether this crate was built with the `deep_errors` feature. Used by generated parsers, which are compiled as part of other crates.
+pub const DEEP_ERRORS : bool = cfg!(feature = "deep_errors");
+
+// Token count for the node of a rule that just finished, flagged if anything in it was recovered from.
+fn finished_token_count(ws : &WorkState) -> u32
+{
+    let token_count = (ws.i - ws.token_start) as u32;
+    if ws.poisoned { token_count ^ !0u32 } else { token_count }
+}
+
 fn make_workstate<'a>(
     g_item : &'a GrammarPoint, token_start : usize,
 ) -> WorkState<'a>
@@ -411,23 +445,33 @@ fn make_workstate<'a>(
 }
 
 #[inline(never)]
-pub (crate) fn pred_recdec_parse_impl_recursive(
-    global : &mut PrdGlobal,
+pub (crate) fn pred_recdec_parse_impl_recursive<O : ParseObserver + ?Sized>(
+    global : &mut PrdGlobal, obs : &mut O,
     gp_id : usize, tokens : &[Token], _token_start : usize,
     depth : usize
 ) -> Result<ASTNode, Box<PrdError>>
+{
+    let mut ws = make_workstate(&global.g.points[gp_id], _token_start);
+    obs.rule_entered(gp_id, ws.i);
+    let ret = pred_recdec_parse_impl_recursive_inner(global, obs, &mut ws, tokens, depth);
+    obs.rule_exited(ws.g_item.id as usize, ws.token_start, ws.i, ret.is_ok());
+    ret
+}
+
+#[inline(always)]
+fn pred_recdec_parse_impl_recursive_inner<'a, O : ParseObserver + ?Sized>(
+    global : &mut PrdGlobal<'a>, obs : &mut O,
+    ws : &mut WorkState<'a>, tokens : &[Token],
+    depth : usize
+) -> Result<ASTNode, Box<PrdError>>
 {
     // BE WARNED: There are a bunch of very strange patterns in this function that look like they have no purpose,
     //   but exist to trick LLVM into spilling less data onto the stack.
     // In the future, I'll write a non-recursive implementation, but it'll likely be a bit slower.
     const DEPTH_LIMIT : usize = if cfg!(debug_assertions) { 300 } else { 1500 };
     
-    let mut ws = make_workstate(&global.g.points[gp_id], _token_start);
-    
     if depth > DEPTH_LIMIT { return build_err!(None, ws, "Exceeded recursion depth limit of {DEPTH_LIMIT}."); }
     
-    #[cfg(feature = "parse_trace")] { println!("entered {} at {}, depth {depth}", global.g.string_cache_inv[ws.chosen_name_id as usize], ws.i); }
-    
     // Structured this way for the sake of $become
     // 1) We can't use an iterator because then we can't go back to alternation 0.
     // 2) We can't have a "find alt" loop followed by a non-loop process block because then we can't go back to the "find alt" loop during $BECOME.
@@ -438,21 +482,28 @@ pub (crate) fn pred_recdec_parse_impl_recursive(
         
         if alt.matching_terms.len() == 0
         {
-            return Ok(ASTNode::new(Some(ws.children), (ws.i - ws.token_start) as u32, ws.chosen_name_id));
+            obs.alt_chosen(ws.g_item.id as usize, ws.alt_id, ws.i);
+            return Ok(ASTNode::new(Some(std::mem::take(&mut ws.children)), (ws.i - ws.token_start) as u32, ws.chosen_name_id));
         }
         else
         {
             let mut accepted = true;
+            let start = if obs.wants_timing() { Some(std::time::Instant::now()) } else { None };
             let a = handle_acceptance(
                 global, tokens,
                 &ws.g_item.forms[ws.alt_id as usize],
-                &mut ws, &mut accepted
+                ws, &mut accepted
             )?;
+            if a != 0
+            {
+                if let Some(start) = start { obs.guard_timed(ws.g_item.id as usize, ws.alt_id, start.elapsed()); }
+                obs.guard_checked(ws.g_item.id as usize, ws.alt_id, ws.i, accepted);
+            }
             if !accepted { ws.alt_id += 1; ws.term_idx = 0; continue; }
             ws.term_idx += a as u16;
         }
         
-        #[cfg(feature = "parse_trace")] { println!("chose variant {}", ws.alt_id); }
+        obs.alt_chosen(ws.g_item.id as usize, ws.alt_id, ws.i);
         
         if ws.children.capacity() == 0
         {
@@ -462,13 +513,14 @@ pub (crate) fn pred_recdec_parse_impl_recursive(
         while (ws.term_idx as usize) < alt.matching_terms.len()
         {
             let term = &alt.matching_terms[ws.term_idx as usize].t;
+            obs.term_reached(ws.g_item.id as usize, ws.alt_id, ws.term_idx as usize, ws.i);
             let mut matched = false;
             match &term
             {
                 MatchingTermE::Rule(id) =>
                 {
-                    let mut child = pred_recdec_parse_impl_recursive(global, *id, tokens, ws.i, depth + 1);
-                    check_recovery(global, tokens, &mut ws, &mut child, *id)?;
+                    let mut child = pred_recdec_parse_impl_recursive(global, obs, *id, tokens, ws.i, depth + 1);
+                    check_recovery(global, obs, tokens, ws, &mut child, *id)?;
                     #[cfg(feature = "deep_errors")]
                     {
                         if let Err(e) = child
@@ -484,8 +536,6 @@ pub (crate) fn pred_recdec_parse_impl_recursive(
                         ws.poisoned = true;
                     }
                     ws.i += child.get_real_token_count() as usize;
-                    #[cfg(feature = "parse_trace")]
-                    { println!("added {} to i via child", child.get_real_token_count()); }
                     ws.children.push(child);
                     matched = true;
                 }
@@ -493,10 +543,10 @@ pub (crate) fn pred_recdec_parse_impl_recursive(
                 {
                     if let Some(qx) = alt.matching_terms.get(ws.term_idx as usize + 1) && let MatchingTermE::Rule(id) = &qx.t
                     {
+                        obs.became(ws.g_item.id as usize, *id, ws.i);
                         ws.g_item = &global.g.points[*id];
                         ws.alt_id = 0;
                         ws.term_idx = 0;
-                        #[cfg(feature = "parse_trace")] { println!("became {} at {}, depth {depth}", ws.g_item.name, ws.i); }
                         if matches!(term, MatchingTermE::Directive(MatchDirective::BecomeAs))
                         {
                             ws.chosen_name_id = ws.g_item.name_id;
@@ -506,7 +556,7 @@ pub (crate) fn pred_recdec_parse_impl_recursive(
                 }
                 _ => {}
             }
-            handle_matchterm(global, tokens, &mut ws, term, &mut matched)?;
+            handle_matchterm(global, obs, tokens, ws, term, &mut matched)?;
             
             if !matched
             {
@@ -522,13 +572,12 @@ pub (crate) fn pred_recdec_parse_impl_recursive(
         
         //println!("Validating {:?}", ws);
         
-        #[cfg(feature = "parse_trace")] { println!("accepted {} from {} to {}, depth {depth}", global.g.string_cache_inv[ws.g_item.name_id as usize], ws.token_start, ws.i); }
         let mut token_count = (ws.i - ws.token_start) as u32;
         if ws.poisoned
         {
             token_count = token_count ^ !0u32;
         }
-        return Ok(ASTNode::new(Some(ws.children), token_count, ws.chosen_name_id));
+        return Ok(ASTNode::new(Some(std::mem::take(&mut ws.children)), token_count, ws.chosen_name_id));
     }
     
     build_err!(
@@ -541,8 +590,16 @@ pub (crate) fn pred_recdec_parse_impl_recursive(
 }
 
 #[inline(never)]
-pub (crate) fn pred_recdec_parse_impl_lifo(
-    global : &mut PrdGlobal,
+fn observe_step<'a, 'b, O : ParseObserver + ?Sized>(
+    global : &'a PrdGlobal<'b>, obs : &mut O, tokens : &'a [Token],
+    kind : StepKind, stack : &'a [WorkState<'b>], ws : &'a WorkState<'b>)
+{
+    obs.step(&ParseState { kind, global, tokens, stack, top : ws });
+}
+
+#[inline(never)]
+pub (crate) fn pred_recdec_parse_impl_lifo<O : ParseObserver + ?Sized>(
+    global : &mut PrdGlobal, obs : &mut O,
     gp_id : usize, tokens : &[Token], _token_start : usize,
 ) -> Result<ASTNode, Box<PrdError>>
 {
@@ -550,11 +607,10 @@ pub (crate) fn pred_recdec_parse_impl_lifo(
     stack.reserve(128);
     let mut ready_child : Option<(Result<_, _>, u32)> = None;
     let mut ws = make_workstate(&global.g.points[gp_id], _token_start);
+    obs.rule_entered(gp_id, ws.i);
+    if obs.wants_steps() { observe_step(global, obs, tokens, StepKind::RuleEntered, &stack, &ws); }
     
-    #[cfg(feature = "parse_trace")]
-    { println!("entered {} at {}", global.g.string_cache_inv[ws.g_item.name_id as usize], ws.i); }
-    
-    'top: while (ws.alt_id as usize) < ws.g_item.forms.len()
+    'top: loop
     {
         macro_rules! set_ready_child { ($ex:expr) => { {
             ready_child = Some($ex);
@@ -572,8 +628,6 @@ pub (crate) fn pred_recdec_parse_impl_lifo(
                     ws.poisoned = true;
                 }
                 ws.i += child.get_real_token_count() as usize;
-                #[cfg(feature = "parse_trace")]
-                { println!("added {} to i via child", child.get_real_token_count()); }
                 ws.children.push(child);
                 ws.term_idx += 1;
                 
@@ -581,13 +635,14 @@ pub (crate) fn pred_recdec_parse_impl_lifo(
                 
                 if ws.term_idx as usize >= alt.matching_terms.len()
                 {
+                    obs.rule_exited(ws.g_item.id as usize, ws.token_start, ws.i, true);
                     if stack.len() == 0
                     {
-                        return Ok(ASTNode::new(Some(ws.children), (ws.i - ws.token_start) as u32, ws.chosen_name_id));
+                        return Ok(ASTNode::new(Some(std::mem::take(&mut ws.children)), finished_token_count(&ws), ws.chosen_name_id));
                     }
                     else
                     {
-                        set_ready_child!((Ok(ASTNode::new(Some(ws.children), (ws.i - ws.token_start) as u32, ws.chosen_name_id)), ws.g_item.name_id));
+                        set_ready_child!((Ok(ASTNode::new(Some(std::mem::take(&mut ws.children)), finished_token_count(&ws), ws.chosen_name_id)), ws.g_item.id));
                     }
                 }
             }
@@ -597,21 +652,26 @@ pub (crate) fn pred_recdec_parse_impl_lifo(
         
         macro_rules! errify { ($x:expr) => { {
             let _e = $x;
+            if _e.is_err() { obs.rule_exited(ws.g_item.id as usize, ws.token_start, ws.i, false); }
             if stack.len() > 0 {
                 if let Err(e) = _e {
-                    engage_ready_child!((Err(e), 0));
+                    engage_ready_child!((Err(e), ws.g_item.id));
                 } else { _e.unwrap() }
             } else { _e? }
         }}}
         
-        #[cfg(feature = "parse_trace")]
-        let depth = stack.len() + 1;
-        
         let mut alt = &ws.g_item.forms[ws.alt_id as usize];
         
         if let Some((mut child, id)) = ready_child.take()
         {
-            errify!(check_recovery(global, tokens, &mut ws, &mut child, id as usize));
+            errify!(check_recovery(global, obs, tokens, &mut ws, &mut child, id as usize));
+            #[cfg(feature = "deep_errors")]
+            {
+                if let Err(e) = &mut child
+                {
+                    e.err_message = format!("In {}: {}", ws.g_item.name, e.err_message);
+                }
+            }
             
             let child = errify!(child);
             if child.is_poisoned()
@@ -619,20 +679,19 @@ pub (crate) fn pred_recdec_parse_impl_lifo(
                 ws.poisoned = true;
             }
             ws.i += child.get_real_token_count() as usize;
-            #[cfg(feature = "parse_trace")]
-            { println!("added {} to i via child", child.get_real_token_count()); }
             ws.children.push(child);
             ws.term_idx += 1;
             
             if ws.term_idx as usize >= alt.matching_terms.len()
             {
+                obs.rule_exited(ws.g_item.id as usize, ws.token_start, ws.i, true);
                 if stack.len() == 0
                 {
-                    return Ok(ASTNode::new(Some(ws.children), (ws.i - ws.token_start) as u32, ws.chosen_name_id));
+                    return Ok(ASTNode::new(Some(std::mem::take(&mut ws.children)), finished_token_count(&ws), ws.chosen_name_id));
                 }
                 else
                 {
-                    engage_ready_child!((Ok(ASTNode::new(Some(ws.children), (ws.i - ws.token_start) as u32, ws.chosen_name_id)), ws.g_item.name_id));
+                    engage_ready_child!((Ok(ASTNode::new(Some(std::mem::take(&mut ws.children)), finished_token_count(&ws), ws.chosen_name_id)), ws.g_item.id));
                 }
             }
             continue 'top;
@@ -646,25 +705,40 @@ pub (crate) fn pred_recdec_parse_impl_lifo(
                 alt = &ws.g_item.forms[ws.alt_id as usize];
                 if alt.matching_terms.len() == 0
                 {
+                    obs.alt_chosen(ws.g_item.id as usize, ws.alt_id, ws.i);
+                    obs.rule_exited(ws.g_item.id as usize, ws.token_start, ws.i, true);
                     if stack.len() == 0
                     {
                         return Ok(ASTNode::new(Some(ws.children), (ws.i - ws.token_start) as u32, ws.chosen_name_id));
                     }
                     else
                     {
-                        engage_ready_child!((Ok(ASTNode::new(Some(ws.children), (ws.i - ws.token_start) as u32, ws.chosen_name_id)), ws.g_item.name_id));
+                        engage_ready_child!((Ok(ASTNode::new(Some(ws.children), (ws.i - ws.token_start) as u32, ws.chosen_name_id)), ws.g_item.id));
                     }
                 }
                 else
                 {
                     let mut accepted = true;
+                    let start = if obs.wants_timing() { Some(std::time::Instant::now()) } else { None };
                     let a = errify!(handle_acceptance(global, tokens, alt, &mut ws, &mut accepted));
+                    if a != 0
+                    {
+                        if let Some(start) = start { obs.guard_timed(ws.g_item.id as usize, ws.alt_id, start.elapsed()); }
+                        obs.guard_checked(ws.g_item.id as usize, ws.alt_id, ws.i, accepted);
+                    }
                     if !accepted
                     {
                         if ws.alt_id as usize + 1 >= ws.g_item.forms.len()
                         {
                             ws.alt_id += 1;
-                            break 'top;
+                            let e : Result<(), _> = build_err!(
+                                Some(ws.g_item.forms.len() as u16),
+                                &ws,
+                                "Failed to match rule {} at token position {}",
+                                global.g.string_cache_inv[ws.g_item.name_id as usize],
+                                ws.token_start,
+                            );
+                            errify!(e);
                         }
                         continue;
                     }
@@ -672,7 +746,7 @@ pub (crate) fn pred_recdec_parse_impl_lifo(
                     break;
                 }
             }
-            #[cfg(feature = "parse_trace")] { println!("chose variant {}", ws.alt_id); }
+            obs.alt_chosen(ws.g_item.id as usize, ws.alt_id, ws.i);
             
             if ws.children.capacity() == 0
             {
@@ -683,6 +757,8 @@ pub (crate) fn pred_recdec_parse_impl_lifo(
         while (ws.term_idx as usize) < alt.matching_terms.len()
         {
             let term = &alt.matching_terms[ws.term_idx as usize].t;
+            obs.term_reached(ws.g_item.id as usize, ws.alt_id, ws.term_idx as usize, ws.i);
+            if obs.wants_steps() { observe_step(global, obs, tokens, StepKind::Term, &stack, &ws); }
             match &term
             {
                 MatchingTermE::Rule(id) =>
@@ -690,9 +766,8 @@ pub (crate) fn pred_recdec_parse_impl_lifo(
                     let next_ws = make_workstate(&global.g.points[*id], ws.i);
                     stack.push(ws);
                     ws = next_ws;
-                    
-                    #[cfg(feature = "parse_trace")]
-                    { println!("entered {} at {}, depth {depth}", global.g.string_cache_inv[ws.g_item.name_id as usize], ws.i); }
+                    obs.rule_entered(*id, ws.i);
+                    if obs.wants_steps() { observe_step(global, obs, tokens, StepKind::RuleEntered, &stack, &ws); }
                     
                     continue 'top;
                 }
@@ -700,21 +775,22 @@ pub (crate) fn pred_recdec_parse_impl_lifo(
                 {
                     if let Some(qx) = alt.matching_terms.get(ws.term_idx as usize + 1) && let MatchingTermE::Rule(id) = &qx.t
                     {
+                        obs.became(ws.g_item.id as usize, *id, ws.i);
                         ws.g_item = &global.g.points[*id];
                         ws.alt_id = 0;
                         ws.term_idx = 0;
-                        #[cfg(feature = "parse_trace")] { println!("became {} at {}, depth {depth}", ws.g_item.name, ws.i); }
                         if matches!(term, MatchingTermE::Directive(MatchDirective::BecomeAs))
                         {
                             ws.chosen_name_id = ws.g_item.name_id;
                         }
+                        if obs.wants_steps() { observe_step(global, obs, tokens, StepKind::RuleEntered, &stack, &ws); }
                         continue 'top;
                     }
                 }
                 _ => {}
             }
             let mut matched = false;
-            errify!(handle_matchterm(global, tokens, &mut ws, term, &mut matched));
+            errify!(handle_matchterm(global, obs, tokens, &mut ws, term, &mut matched));
             
             if !matched
             {
@@ -728,30 +804,22 @@ pub (crate) fn pred_recdec_parse_impl_lifo(
             ws.term_idx += 1;
         }
         
-        #[cfg(feature = "parse_trace")] { println!("accepted {} from {} to {}, depth {depth}", global.g.string_cache_inv[ws.g_item.name_id as usize], ws.token_start, ws.i); }
         let mut token_count = (ws.i - ws.token_start) as u32;
         if ws.poisoned
         {
             token_count = token_count ^ !0u32;
         }
         
+        obs.rule_exited(ws.g_item.id as usize, ws.token_start, ws.i, true);
         if stack.len() == 0
         {
             return Ok(ASTNode::new(Some(ws.children), token_count, ws.chosen_name_id));
         }
         else
         {
-            engage_ready_child!((Ok(ASTNode::new(Some(ws.children), token_count, ws.chosen_name_id)), ws.g_item.name_id));
+            engage_ready_child!((Ok(ASTNode::new(Some(ws.children), token_count, ws.chosen_name_id)), ws.g_item.id));
         }
     }
-    
-    build_err!(
-        Some(ws.g_item.forms.len() as u16),
-        &ws,
-        "Failed to match rule {} at token position {}",
-        global.g.string_cache_inv[ws.g_item.name_id as usize],
-        ws.token_start,
-    )
 }
 
 /// Visit the AST with a possibly-impure callback. The AST itself cannot be modified this way.
@@ -804,23 +872,34 @@ pub (crate) fn visit_ast_recursive(n : &ASTNode, f : &mut dyn FnMut(&ASTNode) ->
 ///
 /// However, this version is about 4% faster than [`parse`].
 /// 
-/// See also: [`ASTNode`], [`parse`]
+/// See also: [`ASTNode`], [`parse`], [`parse_recursive_with_observer`]
 pub fn parse_recursive(
     g : &Grammar, root_rule_name : &str, tokens : &[Token],
     guards : Rc<HashMap<String, Guard>>,
     hooks : Rc<HashMap<String, Hook>>,
 ) -> Result<ASTNode, Box<PrdError>>
+{
+    #[cfg(feature = "parse_trace")]
+    let mut obs = TracePrinter::new(g);
+    #[cfg(not(feature = "parse_trace"))]
+    let mut obs = NoObserver;
+    parse_recursive_with_observer(g, root_rule_name, tokens, guards, hooks, &mut obs)
+}
+
+#[allow(unused)]
+/// Same as [`parse_recursive`], but reports parse events to the given [`ParseObserver`](`super::trace::ParseObserver`).
+pub fn parse_recursive_with_observer<O : ParseObserver + ?Sized>(
+    g : &Grammar, root_rule_name : &str, tokens : &[Token],
+    guards : Rc<HashMap<String, Guard>>,
+    hooks : Rc<HashMap<String, Hook>>,
+    obs : &mut O,
+) -> Result<ASTNode, Box<PrdError>>
 {
     let gp_id = g.by_name.get(root_rule_name).unwrap();
-    let mut global = PrdGlobal { guards, hooks, udata : <_>::default(), udata_r : <_>::default(), g };
+    check_guards_and_hooks(g, &guards, &hooks)?;
+    let mut global = PrdGlobal::new(g, guards, hooks, tokens);
     
-    if let Some(f) = global.hooks.get("init")
-    {
-        let f = Rc::clone(&f);
-        let _ = f(&mut global, tokens, 0, &mut vec!());
-    }
-    
-    pred_recdec_parse_impl_recursive(&mut global, *gp_id, tokens, 0, 0)
+    pred_recdec_parse_impl_recursive(&mut global, obs, *gp_id, tokens, 0, 0)
 }
 
 #[allow(unused)]
@@ -832,29 +911,77 @@ pub fn parse_recursive(
 /// 
 /// However, this version is about 4% slower than [`parse`]. I'm going to keep working on optimizing it.
 /// 
-/// This implementation does not yet support the "deep_errors" feature. When it does, adding support for it will not be considered a breaking change.
-/// 
 /// The exact algorithm may change in any update, as long as it produces the same result and is safe in the same circumstances.
 /// 
-/// See also: [`ASTNode`], [`parse_recursive`]
+/// See also: [`ASTNode`], [`parse_recursive`], [`parse_with_observer`]
 pub fn parse(
     g : &Grammar, root_rule_name : &str, tokens : &[Token],
     guards : Rc<HashMap<String, Guard>>,
     hooks : Rc<HashMap<String, Hook>>,
 ) -> Result<ASTNode, Box<PrdError>>
+{
+    #[cfg(feature = "parse_trace")]
+    let mut obs = TracePrinter::new(g);
+    #[cfg(not(feature = "parse_trace"))]
+    let mut obs = NoObserver;
+    parse_with_observer(g, root_rule_name, tokens, guards, hooks, &mut obs)
+}
+
+#[allow(unused)]
+/// Same as [`parse`], but reports parse events to the given [`ParseObserver`](`super::trace::ParseObserver`).
+///
+/// This is the building block for debuggers, coverage tools, profilers, etc. The parser is generic over the observer, so an observer that ignores most events costs little to nothing.
+pub fn parse_with_observer<O : ParseObserver + ?Sized>(
+    g : &Grammar, root_rule_name : &str, tokens : &[Token],
+    guards : Rc<HashMap<String, Guard>>,
+    hooks : Rc<HashMap<String, Hook>>,
+    obs : &mut O,
+) -> Result<ASTNode, Box<PrdError>>
 {
     let gp_id = g.by_name.get(root_rule_name).unwrap();
-    let mut global = PrdGlobal { guards, hooks, udata : <_>::default(), udata_r : <_>::default(), g };
+    check_guards_and_hooks(g, &guards, &hooks)?;
+    let mut global = PrdGlobal::new(g, guards, hooks, tokens);
     
-    if let Some(f) = global.hooks.get("init")
+    pred_recdec_parse_impl_lifo(&mut global, obs, *gp_id, tokens, 0)
+}
+
+/// Checks that `guards` and `hooks` have every guard and hook that the grammar uses, so that a missing one is an error right away instead of whenever parsing reaches it.
+///
+/// The parse functions in this module do this themselves. The error points at the first place where a missing guard or hook is used, with a token index of 0.
+///
+/// See also: [`unused_guards_and_hooks`], [`Grammar::required_guards`](super::bnf::Grammar::required_guards), [`Grammar::required_hooks`](super::bnf::Grammar::required_hooks)
+pub fn check_guards_and_hooks(g : &Grammar, guards : &HashMap<String, Guard>, hooks : &HashMap<String, Hook>) -> Result<(), Box<PrdError>>
+{
+    if g.used_guards.iter().all(|x| guards.contains_key(x)) && g.used_hooks.iter().all(|x| hooks.contains_key(x))
     {
-        let f = Rc::clone(&f);
-        let _ = f(&mut global, tokens, 0, &mut vec!());
+        return Ok(());
     }
-    
-    pred_recdec_parse_impl_lifo(&mut global, *gp_id, tokens, 0)
+    let mut missing = vec!();
+    let mut first = None;
+    for (is_hook, name, rule, alt, term) in g.guard_and_hook_uses()
+    {
+        let found = if is_hook { hooks.contains_key(&**name) } else { guards.contains_key(&**name) };
+        if found { continue; }
+        let desc = format!("{} {name}", if is_hook { "hook" } else { "guard" });
+        if !missing.contains(&desc) { missing.push(desc); }
+        first.get_or_insert((is_hook, rule, alt, term));
+    }
+    let (is_hook, rule, alt, term) = first.unwrap();
+    let id = g.points[rule].name_id;
+    let msg = format!("Guards and hooks used by the grammar but not given to the parser: {} (first used in {})", missing.join(", "), g.points[rule].name);
+    Err(error_builder(msg, 0, id, id, alt as u16, is_hook.then_some(term as u16)))
 }
 
+/// Lists the guards and hooks in `guards` and `hooks` that the grammar never uses (other than the `init` hook), like `guard name` or `hook name`, sorted. Passing these to the parser isn't an error, but it's usually a mistake, like a typo in a name.
+pub fn unused_guards_and_hooks(g : &Grammar, guards : &HashMap<String, Guard>, hooks : &HashMap<String, Hook>) -> Vec<String>
+{
+    let mut ret = guards.keys().filter(|x| g.used_guards.binary_search(x).is_err()).map(|x| format!("guard {x}")).collect::<Vec<_>>();
+    ret.sort();
+    let mut unused_hooks = hooks.keys().filter(|x| *x != "init" && g.used_hooks.binary_search(x).is_err()).map(|x| format!("hook {x}")).collect::<Vec<_>>();
+    unused_hooks.sort();
+    ret.extend(unused_hooks);
+    ret
+}
 
 #[allow(unused)]
 /// For debugging only: print out the given AST.
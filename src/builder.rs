@@ -0,0 +1,246 @@
+// Building grammars in code
+
+use crate::bnf::*;
+
+/// Builds a [`Grammar`] in code instead of from BNF text, e.g. to generate rules from an operator table.
+///
+/// Rules and alternations are added in order. Each alternation is built with an [`AltBuilder`], whose methods mirror the BNF syntax. [`GrammarBuilder::build`] does the same validation as [`bnf_to_grammar`], so mistakes like references to undefined rules or duplicate rules are reported there.
+///
+/// ```
+/// # use pred_recdec::builder::*;
+/// let g = GrammarBuilder::new()
+///     .comment("//")
+///     .rule("call")
+///         .alt(|a| a.rule("name").lit("(").become_("call_args"))
+///     .rule("call_args")
+///         .alt(|a| a.auto(")"))
+///         .alt(|a| a.rule("name").become_("call_args"))
+///     .rule("name")
+///         .alt(|a| a.regex("[a-z]+"))
+///     .build()
+///     .unwrap();
+/// assert_eq!(g.points.len(), 3);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct GrammarBuilder {
+    rules : Vec<(String, Vec<Vec<String>>)>,
+    error : Option<String>,
+}
+
+impl GrammarBuilder {
+    /// Creates an empty grammar builder.
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+    /// Starts a new rule. Following calls to [`GrammarBuilder::alt`] add alternations to it.
+    pub fn rule(mut self, name : &str) -> Self
+    {
+        self.rules.push((name.to_string(), vec!()));
+        self
+    }
+    /// Adds an alternation to the current rule. BNF: `| ...`
+    pub fn alt(mut self, f : impl FnOnce(AltBuilder) -> AltBuilder) -> Self
+    {
+        let alt = f(AltBuilder::default()).terms;
+        match self.rules.last_mut()
+        {
+            Some((name, forms)) if !is_magic_rule(name) => forms.push(alt),
+            _ => { self.error.get_or_insert("GrammarBuilder::alt must come after GrammarBuilder::rule".to_string()); }
+        }
+        self
+    }
+    fn magic(mut self, name : &str, alt : Vec<String>) -> Self
+    {
+        match self.rules.iter_mut().find(|(n, _)| n == name)
+        {
+            Some((_, forms)) => forms.push(alt),
+            None => self.rules.push((name.to_string(), vec!(alt))),
+        }
+        self
+    }
+    /// Tells the tokenizer to pair up these tokens. BNF: `__BRACKET_PAIRS ::= left right`
+    pub fn bracket_pair(self, left : &str, right : &str) -> Self
+    {
+        self.magic("__BRACKET_PAIRS", vec!(left.to_string(), right.to_string()))
+    }
+    /// Adds a kind of single-line comment. BNF: `__COMMENTS ::= "start"`
+    pub fn comment(self, start : &str) -> Self
+    {
+        self.magic("__COMMENTS", vec!(bnf_quote(start)))
+    }
+    /// Adds a kind of pair-based comment. BNF: `__COMMENT_PAIRS ::= start end`
+    pub fn comment_pair(self, start : &str, end : &str) -> Self
+    {
+        self.magic("__COMMENT_PAIRS", vec!(start.to_string(), end.to_string()))
+    }
+    /// Adds a kind of nesting pair-based comment. BNF: `__COMMENT_PAIRS_NESTED ::= start end`
+    pub fn comment_pair_nested(self, start : &str, end : &str) -> Self
+    {
+        self.magic("__COMMENT_PAIRS_NESTED", vec!(start.to_string(), end.to_string()))
+    }
+    /// Adds a kind of comment, given as a regex. BNF: ```__COMMENT_REGEXES ::= r`pattern`r```
+    pub fn comment_regex(self, pattern : &str) -> Self
+    {
+        self.magic("__COMMENT_REGEXES", vec!(format!("r`{pattern}`r")))
+    }
+    /// Adds a reserved word. BNF: `__RESERVED_WORDS ::= word`
+    pub fn reserved_word(self, word : &str) -> Self
+    {
+        self.magic("__RESERVED_WORDS", vec!(word.to_string()))
+    }
+    /// Infers predicates for a rule's alternations, or every rule's if given `*`. BNF: `__INFER_PEEKS ::= rule`
+    pub fn infer_peeks(self, rule : &str) -> Self
+    {
+        self.magic("__INFER_PEEKS", vec!(rule.to_string()))
+    }
+    /// Builds the grammar.
+    ///
+    /// Next step: [`tokenize`].
+    pub fn build(self) -> Result<Grammar, GrammarError>
+    {
+        if let Some(e) = self.error { return Err(e.into()); }
+        bnf_build(self.rules)
+    }
+}
+
+/// One alternation of a rule being built by a [`GrammarBuilder`]. Each method adds one term, in order, and means the same thing as the BNF given in its docs.
+#[derive(Clone, Debug, Default)]
+pub struct AltBuilder {
+    terms : Vec<String>,
+}
+
+impl AltBuilder {
+    fn push(mut self, terms : &[&str]) -> Self
+    {
+        self.terms.extend(terms.iter().map(|x| x.to_string()));
+        self
+    }
+    /// Literal terminal. BNF: `"text"`
+    pub fn lit(self, text : &str) -> Self
+    {
+        self.push(&[&bnf_quote(text)])
+    }
+    /// Regex terminal that's also registered with the tokenizer. BNF: ```r`pattern`r```
+    pub fn regex(self, pattern : &str) -> Self
+    {
+        self.push(&[&format!("r`{pattern}`r")])
+    }
+    /// Regex terminal that isn't registered with the tokenizer. BNF: ```R`pattern`r```
+    pub fn regex_unlexed(self, pattern : &str) -> Self
+    {
+        self.push(&[&format!("R`{pattern}`r")])
+    }
+    /// Regex terminal that only checks the start of the token, and isn't registered with the tokenizer. BNF: ```A`pattern`r```
+    pub fn regex_prefix(self, pattern : &str) -> Self
+    {
+        self.push(&[&format!("A`{pattern}`r")])
+    }
+    /// Call of another rule. BNF: `name`
+    pub fn rule(self, name : &str) -> Self
+    {
+        self.push(&[name])
+    }
+    /// Checks the text of the token `n` tokens ahead. BNF: `@peek(n, "text")`
+    pub fn peek(self, n : isize, text : &str) -> Self
+    {
+        self.push(&["@peek", "(", &n.to_string(), ",", &bnf_quote(text), ")"])
+    }
+    /// Checks the token `n` tokens ahead against a regex. BNF: ```@peekr(n, r`pattern`r)```
+    pub fn peekr(self, n : isize, pattern : &str) -> Self
+    {
+        self.push(&["@peekr", "(", &n.to_string(), ",", &format!("r`{pattern}`r"), ")"])
+    }
+    /// Like [`AltBuilder::peekr`], but reserved words never match. BNF: ```@peekres(n, r`pattern`r)```
+    pub fn peekres(self, n : isize, pattern : &str) -> Self
+    {
+        self.push(&["@peekres", "(", &n.to_string(), ",", &format!("r`{pattern}`r"), ")"])
+    }
+    /// Peeks for a literal, then matches it. BNF: `@auto "text"`
+    pub fn auto(self, text : &str) -> Self
+    {
+        self.push(&["@auto", &bnf_quote(text)])
+    }
+    /// Peeks for a regex, then matches it. BNF: ```@auto r`pattern`r```
+    pub fn auto_regex(self, pattern : &str) -> Self
+    {
+        self.push(&["@auto", &format!("r`{pattern}`r")])
+    }
+    /// Checks for the end of the token stream. BNF: `@eof`
+    pub fn eof(self) -> Self
+    {
+        self.push(&["@eof"])
+    }
+    /// Calls a user-provided guard. BNF: `@guard(name)`
+    pub fn guard(self, name : &str) -> Self
+    {
+        self.push(&["@guard", "(", name, ")"])
+    }
+    /// Calls a user-provided hook. BNF: `!hook(name)`
+    pub fn hook(self, name : &str) -> Self
+    {
+        self.push(&["!hook", "(", name, ")"])
+    }
+    /// Lets the rule recover from errors by seeking to a token that matches the regex. BNF: ```@recover r`pattern`r```
+    pub fn recover(self, pattern : &str) -> Self
+    {
+        self.push(&["@recover", &format!("r`{pattern}`r")])
+    }
+    /// Same as [`AltBuilder::recover`], but stops right before the matching token. BNF: ```@recover_before r`pattern`r```
+    pub fn recover_before(self, pattern : &str) -> Self
+    {
+        self.push(&["@recover_before", &format!("r`{pattern}`r")])
+    }
+    /// Tail call, keeping the current AST node name. BNF: `$become name`
+    ///
+    /// (`become` is a reserved keyword in Rust.)
+    pub fn become_(self, name : &str) -> Self
+    {
+        self.push(&["$become", name])
+    }
+    /// Tail call, renaming the current AST node to the target. BNF: `$become_as name`
+    pub fn become_as(self, name : &str) -> Self
+    {
+        self.push(&["$become_as", name])
+    }
+    /// Matches any one token. BNF: `$any`
+    pub fn any(self) -> Self
+    {
+        self.push(&["$any"])
+    }
+    /// Don't make AST nodes for this alternation's terminals. BNF: `$pruned`
+    pub fn pruned(self) -> Self
+    {
+        self.push(&["$pruned"])
+    }
+    /// Infer predicates for every alternation of this rule. BNF: `$infer_peeks`
+    pub fn infer_peeks(self) -> Self
+    {
+        self.push(&["$infer_peeks"])
+    }
+    /// Replace the most recent child with its children. BNF: `$hoist`
+    pub fn hoist(self) -> Self
+    {
+        self.push(&["$hoist"])
+    }
+    /// Same as [`AltBuilder::hoist`], but only if the child has exactly one child. BNF: `$hoist_unit`
+    pub fn hoist_unit(self) -> Self
+    {
+        self.push(&["$hoist_unit"])
+    }
+    /// Delete the most recent child. BNF: `$drop`
+    pub fn drop(self) -> Self
+    {
+        self.push(&["$drop"])
+    }
+    /// Same as [`AltBuilder::drop`], but only if the child has no children. BNF: `$drop_empty`
+    pub fn drop_empty(self) -> Self
+    {
+        self.push(&["$drop_empty"])
+    }
+    /// Rename the current AST node, without parsing the named rule. BNF: `$rename name`
+    pub fn rename(self, name : &str) -> Self
+    {
+        self.push(&["$rename", name])
+    }
+}
@@ -6,6 +6,12 @@ pub use rustc_hash::FxBuildHasher as HashBuilder;
 
 use pred_recdec::bnf::*;
 use pred_recdec::ast::*;
+use pred_recdec::coverage::*;
+use pred_recdec::profile::*;
+use pred_recdec::debugger::*;
+
+// the operators are matched by regexes, so they have to be interned explicitly to get constant IDs
+mod c_grammar { pred_recdec::grammar!("src/grammar_c.txt", intern = ["/", "%", "+", "-", "<<", ">>", "<", "<=", ">", ">=", "==", "!=", "&", "^", "|", "&&", "||"], native); }
 
 #[inline(never)]
 fn main_impl()
@@ -14,63 +20,74 @@ fn main_impl()
     let args: Vec<String> = std::env::args().collect();
     
     macro_rules! println_wrap { ($($tts:tt)*) => { { print!($($tts)*); print!("\n"); }}}
-
-    println_wrap!("usage: cargo test test_c --release -- <filename>.c --show-output\nor: target/release/c_parser_test.exe -- <filename>.c");
+    
+    println_wrap!("usage: cargo test test_c --release -- <filename>.c --show-output\nor: target/release/c_parser_test.exe -- <filename>.c [<filename>.c ...] [--coverage] [--profile] [--profile-folded=<out.folded>] [--debug] [--break=<rule or @token>] [--native]");
     if args.len() < 3
     {
         return;
     }
-    let input_fname = args[2].to_string();
+    let mut input_fnames = Vec::new();
+    let mut coverage = false;
+    let mut profile = false;
+    let mut folded_fname = None;
+    let mut debug = false;
+    let mut native = false;
+    let mut breakpoints = Vec::new();
+    for arg in &args[2..]
+    {
+        if arg == "--coverage" { coverage = true; }
+        else if arg == "--profile" { profile = true; }
+        else if let Some(f) = arg.strip_prefix("--profile-folded=") { profile = true; folded_fname = Some(f.to_string()); }
+        else if arg == "--debug" { debug = true; }
+        else if arg == "--native" { native = true; }
+        else if let Some(b) = arg.strip_prefix("--break=")
+        {
+            debug = true;
+            if let Some(n) = b.strip_prefix("@")
+            {
+                match n.parse()
+                {
+                    Ok(n) => breakpoints.push(Breakpoint::Token(n)),
+                    Err(_) => { eprintln!("--break=@N takes a token index, not {n:?}"); std::process::exit(1); }
+                }
+            }
+            else { breakpoints.push(Breakpoint::Rule(b.to_string())); }
+        }
+        else { input_fnames.push(arg.to_string()); }
+    }
     
-    let grammar_source = std::fs::read_to_string("src/grammar_c.txt").unwrap();
-    let mut g = bnf_to_grammar(&grammar_source).unwrap();
+    let mut g = c_grammar::grammar();
     
+    use c_grammar::literals::*;
     let mut precedence_list = vec!(
-        (&["*", "/", "%"][..]),
-        (&["+", "-"]),
-        (&["<<", ">>"]),
-        (&["<", "<=", ">", ">="]),
-        (&["==", "!="]),
-        (&["&"]),  (&["^"]),  (&["|"]),  (&["&&"]),  (&["||"]),
+        (&[STAR, SLASH, PERCENT][..]),
+        (&[PLUS, MINUS]),
+        (&[LT_LT, GT_GT]),
+        (&[LT, LT_EQ, GT, GT_EQ]),
+        (&[EQ_EQ, BANG_EQ]),
+        (&[AMP]),  (&[CARET]),  (&[PIPE]),  (&[AMP_AMP]),  (&[PIPE_PIPE]),
     );
     precedence_list.reverse();
     
     let mut prec_map = Vec::default();
     for (i, l) in precedence_list.iter().enumerate()
     {
-        for s in *l
+        for index in *l
         {
-            let index : u32 = string_cache_lookup(&mut g.string_cache, &mut g.string_cache_inv, *s).1;
-            if index as usize >= prec_map.len()
+            if *index as usize >= prec_map.len()
             {
-                prec_map.resize(index as usize + 1, 0);
+                prec_map.resize(*index as usize + 1, 0);
             }
-            prec_map[index as usize] = i as u32;
+            prec_map[*index as usize] = i as u32;
         }
     }
     
     //println_wrap!("Boot time: {:?}", start.elapsed());
     
-    println_wrap!("Loading: {input_fname}");
-    let start = std::time::Instant::now();
-    let test_source = std::fs::read_to_string(input_fname).unwrap();
-    println_wrap!("Source text load time: {:?}", start.elapsed());
-    
-    let start = std::time::Instant::now();
-    let tokens = tokenize(&mut g, &test_source);
-    let tokens = tokens.unwrap();
-    let ts_len = test_source.len();
-    drop(test_source);
-    println_wrap!("Tokenization time: {:?} for {} tokens from {} bytes", start.elapsed(), tokens.len(), ts_len);
-    //let tokens = tokenize(&mut g, &"9152 6 3");
-
-    //println_wrap!("{:#?}", &tokens[..tokens.len().min(10)]);
-    
-    let start = std::time::Instant::now();
     use std::rc::Rc;
     type HashMap<K, V> = std::collections::HashMap::<K, V, crate::HashBuilder>;
     type HashSet<V> = std::collections::HashSet::<V, crate::HashBuilder>;
-
+    
     let mut hooks : HashMap<String, Rc<dyn Fn(&mut PrdGlobal, &[Token], usize, &mut Vec<ASTNode>) -> Result<usize, String>>>
         = <_>::default();
     let mut guards = HashMap::<String, Rc<dyn Fn(&mut PrdGlobal, &[Token], _) -> GuardResult>>::default();
@@ -443,7 +460,7 @@ fn main_impl()
         Rc::new(move |_global : &mut PrdGlobal, _tokens : &[Token], mut _i : usize, children : &mut Vec<ASTNode>|
         {
             if children.len() <= 3 { return Ok(0); }
-            let default_text = *_global.g.string_cache.get("infix_expression").unwrap();
+            let default_text = c_grammar::rule_names::INFIX_EXPRESSION;
             
             fn parse_expression_impl(default_text : u32, prec_map : &[u32], items : &mut Vec<ASTNode>, mut lhs : ASTNode, min_precedence : u32) -> ASTNode
             {
@@ -536,35 +553,83 @@ fn main_impl()
     let hooks = Rc::new(hooks);
     let guards = Rc::new(guards);
     
-    let ast = parse(&g, "S", &tokens[..], guards, hooks);
-    //println_wrap!("{}", ast.is_ok());
-    println_wrap!("Parse time taken: {:?} under {} items", start.elapsed(), tokens.len());
+    let mut cov = Coverage::new(&g);
+    let mut prof = Profiler::new(&g);
     
-    if let Err(e) = &ast
+    for input_fname in input_fnames
     {
-        println!("{:?}", tokens.get(e.token_index));
-        println!("{:?}", tokens.get(e.token_index.saturating_sub(1)));
+        println_wrap!("Loading: {input_fname}");
+        let start = std::time::Instant::now();
+        let test_source = std::fs::read_to_string(input_fname).unwrap();
+        println_wrap!("Source text load time: {:?}", start.elapsed());
+        
+        let start = std::time::Instant::now();
+        let tokens = tokenize(&mut g, &test_source);
+        let tokens = tokens.unwrap();
+        let ts_len = test_source.len();
+        drop(test_source);
+        println_wrap!("Tokenization time: {:?} for {} tokens from {} bytes", start.elapsed(), tokens.len(), ts_len);
+        //let tokens = tokenize(&mut g, &"9152 6 3");
+        
+        //println_wrap!("{:#?}", &tokens[..tokens.len().min(10)]);
+        
+        let start = std::time::Instant::now();
+        let ast = if coverage { parse_with_observer(&g, "S", &tokens[..], guards.clone(), hooks.clone(), &mut cov) }
+            else if profile { parse_with_observer(&g, "S", &tokens[..], guards.clone(), hooks.clone(), &mut prof) }
+            else if debug
+            {
+                let mut dbg = Debugger::stdio(&g).with_udata_printer(|global| format!("{:#?}", global.udata.get::<MyData>()));
+                for bp in &breakpoints { dbg.add_breakpoint(bp.clone()); }
+                if breakpoints.len() > 0 { dbg.start_running(); }
+                parse_with_observer(&g, "S", &tokens[..], guards.clone(), hooks.clone(), &mut dbg)
+            }
+            else if native { c_grammar::parse(&g, "S", &tokens[..], guards.clone(), hooks.clone()) }
+            else { parse(&g, "S", &tokens[..], guards.clone(), hooks.clone()) };
+        //println_wrap!("{}", ast.is_ok());
+        println_wrap!("Parse time taken: {:?} under {} items", start.elapsed(), tokens.len());
+        
+        if let Err(e) = &ast
+        {
+            println!("{:?}", tokens.get(e.token_index));
+            println!("{:?}", tokens.get(e.token_index.saturating_sub(1)));
+            // keep going so that the rest of the corpus still counts towards coverage/profiling
+            if coverage || profile { println_wrap!("Parse error: {}", e.err_message); continue; }
+        }
+        let ast = ast.unwrap();
+        
+        // visitor that visits entire AST
+        //let f : &mut dyn FnMut(&ASTNode) -> bool = &mut |_| true;
+        //visit_ast(&ast, f);
+        
+        let start = std::time::Instant::now();
+        {
+            //if let Ok(ast) = &ast { print_ast_pred_recdec(ast, &g.string_cache_inv, 0); }
+        }
+        
+        let ast = std::hint::black_box(ast);
+        drop(ast);
+        //println_wrap!("AST destruction time: {:?}", start.elapsed());
+        //println_wrap!("sizeof ASTNode {}", std::mem::size_of::<ASTNode>());
+        
+        let start = std::time::Instant::now();
+        let tokens = std::hint::black_box(tokens);
+        drop(tokens);
+        //println_wrap!("Token list destruction time: {:?}", start.elapsed());
     }
-    let ast = ast.unwrap();
-    
-    // visitor that visits entire AST
-    //let f : &mut dyn FnMut(&ASTNode) -> bool = &mut |_| true;
-    //visit_ast(&ast, f);
     
-    let start = std::time::Instant::now();
+    if coverage
     {
-        //if let Ok(ast) = &ast { print_ast_pred_recdec(ast, &g.string_cache_inv, 0); }
+        print!("{}", cov.report(&g).format(&g));
+    }
+    if profile
+    {
+        print!("{}", prof.report(&g));
+        if let Some(f) = folded_fname
+        {
+            std::fs::write(&f, prof.folded_stacks(&g)).unwrap();
+            println_wrap!("Wrote folded stacks to {f}");
+        }
     }
-    
-    let ast = std::hint::black_box(ast);
-    drop(ast);
-    //println_wrap!("AST destruction time: {:?}", start.elapsed());
-    //println_wrap!("sizeof ASTNode {}", std::mem::size_of::<ASTNode>());
-    
-    let start = std::time::Instant::now();
-    let tokens = std::hint::black_box(tokens);
-    drop(tokens);
-    //println_wrap!("Token list destruction time: {:?}", start.elapsed());
 }
 fn main()
 {
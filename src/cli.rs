@@ -0,0 +1,455 @@
+use mimalloc::MiMalloc;
+#[global_allocator]
+static GLOBAL: MiMalloc = MiMalloc;
+
+use pred_recdec::bnf::*;
+use pred_recdec::ast::*;
+use pred_recdec::coverage::*;
+use pred_recdec::debugger::*;
+
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+type HashMap<K, V> = std::collections::HashMap::<K, V, pred_recdec::HashBuilder>;
+
+const USAGE : &str = "\
+usage:
+  prd repl <grammar.txt> [root_rule] [--stub=accept|reject]
+  prd coverage <grammar.txt> <root_rule> <input files...> [--stub=accept|reject]
+  prd fmt <grammar.txt> [--write]
+  prd fmt --expanded <grammar.txt>
+  prd compile <grammar.txt> <out.prdg>
+  prd codegen <grammar.txt> [out.rs]
+  prd check <grammar.txt> [root]
+  prd railroad <grammar.txt> [out.html]
+  prd export <ebnf|antlr|tree-sitter> <grammar.txt> [out]
+  prd import <abnf|ebnf> <grammar> [out.txt]
+
+Anywhere a <grammar.txt> is expected, a grammar compiled with `prd compile` also works.
+
+Guards and hooks are Rust code, so they can't be given here. With --stub=accept or --stub=reject,
+every guard the grammar uses accepts or rejects, and every hook does nothing.";
+
+fn main()
+{
+    let mut args : Vec<String> = std::env::args().collect();
+    let stub = match args.iter().position(|x| x.starts_with("--stub="))
+    {
+        Some(i) => match &args.remove(i)["--stub=".len()..]
+        {
+            "accept" => Some(true),
+            "reject" => Some(false),
+            _ => { println!("{USAGE}"); return; }
+        }
+        None => None,
+    };
+    match args.get(1).map(|x| &**x)
+    {
+        Some("repl") if args.len() == 3 || args.len() == 4 => repl(&args[2], args.get(3).cloned(), stub),
+        Some("coverage") if args.len() >= 5 => coverage(&args[2], &args[3], &args[4..], stub),
+        Some("fmt") if args.len() == 4 && args[2] == "--expanded" => fmt_expanded(&args[3]),
+        Some("fmt") if args.len() == 3 || (args.len() == 4 && args[3] == "--write") => fmt(&args[2], args.len() == 4),
+        Some("compile") if args.len() == 4 => compile(&args[2], &args[3]),
+        Some("check") if args.len() == 3 || args.len() == 4 => check(&args[2], args.get(3).map(|x| x.as_str())),
+        Some("codegen") if args.len() == 3 || args.len() == 4 => codegen(&args[2], args.get(3).map(|x| &**x)),
+        Some("railroad") if args.len() == 3 || args.len() == 4 => railroad(&args[2], args.get(3).map(|x| &**x)),
+        Some("export") if args.len() == 4 || args.len() == 5 => export(&args[2], &args[3], args.get(4).map(|x| &**x)),
+        Some("import") if args.len() == 4 || args.len() == 5 => import(&args[2], &args[3], args.get(4).map(|x| &**x)),
+        _ => println!("{USAGE}"),
+    }
+}
+
+fn load_grammar(fname : &str) -> Result<(Grammar, Option<std::time::SystemTime>), String>
+{
+    let mtime = std::fs::metadata(fname).and_then(|m| m.modified()).ok();
+    let data = std::fs::read(fname).map_err(|e| format!("Failed to read {fname}: {e}"))?;
+    if data.starts_with(b"PRDG") { return Ok((Grammar::from_bytes(&data)?, mtime)); }
+    let source = String::from_utf8(data).map_err(|e| format!("Failed to read {fname}: {e}"))?;
+    // %include and %import paths are relative to the main grammar file
+    let dir = std::path::Path::new(fname).parent().map(|d| d.to_path_buf()).unwrap_or_default();
+    let loader = |path : &str| std::fs::read_to_string(dir.join(path)).map_err(|e| e.to_string());
+    Ok((bnf_to_grammar_with_loader(&source, loader)?, mtime))
+}
+
+fn grammar_changed(fname : &str, last : Option<std::time::SystemTime>) -> bool
+{
+    std::fs::metadata(fname).and_then(|m| m.modified()).ok() != last
+}
+
+// Guards and hooks to parse with. With a stub, every guard the grammar uses accepts (true) or rejects (false), and every hook does nothing.
+fn stubs(g : &Grammar, stub : Option<bool>) -> (Rc<HashMap<String, Guard>>, Rc<HashMap<String, Hook>>)
+{
+    let (mut guards, mut hooks) = (HashMap::default(), HashMap::default());
+    if let Some(accept) = stub
+    {
+        for name in g.required_guards()
+        {
+            let guard : Guard = Rc::new(move |_, _, _| if accept { GuardResult::Accept } else { GuardResult::Reject });
+            guards.insert(name, guard);
+        }
+        for name in g.required_hooks()
+        {
+            let hook : Hook = Rc::new(|_, _, _, _| Ok(0));
+            hooks.insert(name, hook);
+        }
+    }
+    (Rc::new(guards), Rc::new(hooks))
+}
+
+// Without stubs, parsing fails right away if the grammar uses any guards or hooks, so say so up front.
+fn note_guards_and_hooks(g : &Grammar, stub : Option<bool>)
+{
+    let names = g.required_guards().into_iter().map(|x| format!("guard {x}")).chain(g.required_hooks().into_iter().map(|x| format!("hook {x}"))).collect::<Vec<_>>();
+    if stub.is_none() && !names.is_empty()
+    {
+        println!("Note: the grammar uses {}, which can't be given here, so parsing will fail. Pass --stub=accept or --stub=reject to stand in for them.", names.join(", "));
+    }
+}
+
+fn print_rules(g : &Grammar)
+{
+    let mut names = g.by_name.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names
+    {
+        println!("  {name}");
+    }
+}
+
+const REPL_HELP : &str = "\
+Type an input snippet to tokenize and parse it with the current root rule.
+End a line with \\ to continue the snippet on the next line.
+Commands:
+  :rule <name>   set the root rule
+  :rules         list all rules
+  :tokens        toggle token stream output
+  :ast           toggle AST output
+  :debug <text>  parse <text> in the step-through debugger (type help inside it)
+  :reload        reload the grammar file now
+  :help          show this text
+  :quit          exit";
+
+fn repl(fname : &str, root : Option<String>, stub : Option<bool>)
+{
+    let (mut g, mut mtime) = match load_grammar(fname)
+    {
+        Ok(x) => x,
+        Err(e) => { println!("{e}"); return; }
+    };
+    let mut root = root.unwrap_or_else(|| g.points.get(0).map(|p| (*p.name).clone()).unwrap_or_default());
+    let mut show_tokens = true;
+    let mut show_ast = true;
+    
+    println!("Loaded {fname} ({} rules). Root rule: {root}. Type :help for commands.", g.points.len());
+    note_guards_and_hooks(&g, stub);
+    
+    let stdin = std::io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut input = String::new();
+    loop
+    {
+        print!("{}", if input.is_empty() { "> " } else { ". " });
+        let _ = std::io::stdout().flush();
+        let line = match lines.next()
+        {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        if let Some(l) = line.strip_suffix("\\")
+        {
+            input += l;
+            input += "\n";
+            continue;
+        }
+        input += &line;
+        let text = std::mem::take(&mut input);
+        
+        let trimmed = text.trim();
+        if trimmed == ":reload" || grammar_changed(fname, mtime)
+        {
+            match load_grammar(fname)
+            {
+                Ok((g2, mtime2)) =>
+                {
+                    g = g2;
+                    mtime = mtime2;
+                    println!("Reloaded {fname} ({} rules).", g.points.len());
+                    note_guards_and_hooks(&g, stub);
+                    if !g.by_name.contains_key(&root) { println!("Warning: root rule {root} no longer exists."); }
+                }
+                Err(e) =>
+                {
+                    // keep the old grammar around so the session stays usable while the file is being edited
+                    mtime = std::fs::metadata(fname).and_then(|m| m.modified()).ok();
+                    println!("Failed to reload {fname}, keeping old grammar: {e}");
+                }
+            }
+        }
+        
+        if let Some(snippet) = trimmed.strip_prefix(":debug ")
+        {
+            if !g.by_name.contains_key(&root) { println!("No such rule: {root}"); continue; }
+            let tokens = match tokenize(&mut g, snippet)
+            {
+                Ok(tokens) => tokens,
+                Err(e) => { println!("Tokenizer error: {}", e.err_message); continue; }
+            };
+            // the debugger reads its commands from the same input as the REPL
+            let mut dbg = Debugger::new(&g, || lines.next().and_then(|l| l.ok()), std::io::stdout());
+            let (guards, hooks) = stubs(&g, stub);
+            match parse_with_observer(&g, &root, &tokens[..], guards, hooks, &mut dbg)
+            {
+                Ok(ast) => print_ast_pred_recdec(&ast, &g.string_cache_inv, 0),
+                Err(e) => println!("Parse error: {}", e.err_message),
+            }
+            continue;
+        }
+        if trimmed.starts_with(":")
+        {
+            let mut split = trimmed.split_whitespace();
+            match (split.next(), split.next())
+            {
+                (Some(":q" | ":quit" | ":exit"), _) => break,
+                (Some(":help"), _) => println!("{REPL_HELP}"),
+                (Some(":rules"), _) => print_rules(&g),
+                (Some(":tokens"), _) => { show_tokens = !show_tokens; println!("token output: {show_tokens}"); }
+                (Some(":ast"), _) => { show_ast = !show_ast; println!("AST output: {show_ast}"); }
+                (Some(":reload"), _) => {}
+                (Some(":rule"), Some(name)) =>
+                {
+                    if g.by_name.contains_key(name) { root = name.to_string(); }
+                    else { println!("No such rule: {name}"); }
+                }
+                _ => println!("Unknown command. Type :help for commands."),
+            }
+            continue;
+        }
+        
+        run_snippet(&mut g, &root, &text, show_tokens, show_ast, stub);
+    }
+}
+
+fn run_snippet(g : &mut Grammar, root : &str, text : &str, show_tokens : bool, show_ast : bool, stub : Option<bool>)
+{
+    if !g.by_name.contains_key(root)
+    {
+        println!("No such rule: {root}");
+        return;
+    }
+    let tokens = match tokenize(g, text)
+    {
+        Ok(tokens) => tokens,
+        Err(e) => { println!("Tokenizer error: {}", e.err_message); return; }
+    };
+    if show_tokens
+    {
+        let texts = tokens.iter().map(|t| format!("{:?}", g.string_cache_inv[t.text as usize])).collect::<Vec<_>>();
+        println!("tokens ({}): {}", tokens.len(), texts.join(" "));
+    }
+    
+    let (guards, hooks) = stubs(g, stub);
+    match parse(g, root, &tokens[..], guards, hooks)
+    {
+        Ok(ast) =>
+        {
+            if show_ast { print_ast_pred_recdec(&ast, &g.string_cache_inv, 0); }
+            let consumed = ast.get_real_token_count() as usize;
+            if consumed < tokens.len()
+            {
+                println!("Note: parsed {consumed} of {} tokens.", tokens.len());
+            }
+        }
+        Err(e) =>
+        {
+            println!("Parse error: {}", e.err_message);
+            println!("  at token {} ({:?}), in rule {}, alternation {}, on behalf of {}",
+                e.token_index,
+                tokens.get(e.token_index).map(|t| g.string_cache_inv[t.text as usize].as_str()).unwrap_or("<end of input>"),
+                g.string_cache_inv[e.rule as usize],
+                e.in_alt,
+                g.string_cache_inv[e.on_behalf_of_rule as usize],
+            );
+        }
+    }
+}
+
+// Prints the grammar file reformatted, or rewrites it in place.
+fn fmt(fname : &str, write : bool)
+{
+    let formatted = std::fs::read_to_string(fname).map_err(|e| format!("Failed to read {fname}: {e}")).and_then(|s| bnf_format(&s));
+    match formatted
+    {
+        Ok(formatted) if write =>
+        {
+            if let Err(e) = std::fs::write(fname, formatted) { println!("Failed to write {fname}: {e}"); }
+        }
+        Ok(formatted) => print!("{formatted}"),
+        Err(e) => println!("{e}"),
+    }
+}
+
+// Prints the grammar as plain BNF, with includes, templates and EBNF sugar expanded.
+fn fmt_expanded(fname : &str)
+{
+    match load_grammar(fname)
+    {
+        Ok((g, _)) => print!("{}", g.to_bnf()),
+        Err(e) => println!("{e}"),
+    }
+}
+
+// Saves the grammar in its precompiled form, which loads faster.
+fn compile(fname : &str, out_fname : &str)
+{
+    match load_grammar(fname)
+    {
+        Ok((g, _)) =>
+        {
+            if let Err(e) = std::fs::write(out_fname, g.to_bytes()) { println!("Failed to write {out_fname}: {e}"); }
+        }
+        Err(e) => println!("{e}"),
+    }
+}
+
+fn check(fname : &str, root : Option<&str>)
+{
+    match load_grammar(fname)
+    {
+        Ok((g, _)) =>
+        {
+            for d in pred_recdec::analysis::analyze(&g) { println!("{}", d.describe(&g)); }
+            if let Some(root) = root
+            {
+                match pred_recdec::analysis::usage_report(&g, root)
+                {
+                    Some(report) => print!("{}", report.format(&g)),
+                    None => println!("No rule named {root}"),
+                }
+            }
+        }
+        Err(e) => println!("{e}"),
+    }
+}
+
+fn codegen(fname : &str, out_fname : Option<&str>)
+{
+    match (load_grammar(fname), out_fname)
+    {
+        (Ok((g, _)), Some(out_fname)) =>
+        {
+            if let Err(e) = std::fs::write(out_fname, pred_recdec::codegen::generate_rust(&g)) { println!("Failed to write {out_fname}: {e}"); }
+        }
+        (Ok((g, _)), None) => print!("{}", pred_recdec::codegen::generate_rust(&g)),
+        (Err(e), _) => println!("{e}"),
+    }
+}
+
+fn railroad(fname : &str, out_fname : Option<&str>)
+{
+    let title = std::path::Path::new(fname).file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    match (load_grammar(fname), out_fname)
+    {
+        (Ok((g, _)), Some(out_fname)) =>
+        {
+            if let Err(e) = std::fs::write(out_fname, pred_recdec::railroad::generate_html(&g, &title)) { println!("Failed to write {out_fname}: {e}"); }
+        }
+        (Ok((g, _)), None) => print!("{}", pred_recdec::railroad::generate_html(&g, &title)),
+        (Err(e), _) => println!("{e}"),
+    }
+}
+
+fn export(format : &str, fname : &str, out_fname : Option<&str>)
+{
+    let g = match load_grammar(fname)
+    {
+        Ok(x) => x.0,
+        Err(e) => { println!("{e}"); return; }
+    };
+    let name = std::path::Path::new(fname).file_stem().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    let text = match format
+    {
+        "ebnf" => pred_recdec::export::to_w3c_ebnf(&g),
+        "antlr" => pred_recdec::export::to_antlr(&g, &name),
+        // tree-sitter grammars start from their first rule, so use the grammar's first rule
+        "tree-sitter" => match g.points.first().and_then(|root| pred_recdec::export::to_tree_sitter(&g, &name, &root.name))
+        {
+            Some(x) => x,
+            None => { println!("Grammar has no rules"); return; }
+        },
+        _ => { println!("Unknown format {format}; expected ebnf, antlr or tree-sitter"); return; }
+    };
+    match out_fname
+    {
+        Some(out_fname) => if let Err(e) = std::fs::write(out_fname, text) { println!("Failed to write {out_fname}: {e}"); },
+        None => print!("{text}"),
+    }
+}
+
+fn import(format : &str, fname : &str, out_fname : Option<&str>)
+{
+    let text = match std::fs::read_to_string(fname)
+    {
+        Ok(x) => x,
+        Err(e) => { println!("Failed to read {fname}: {e}"); return; }
+    };
+    let imported = match format
+    {
+        "abnf" => pred_recdec::import::from_abnf(&text, None),
+        "ebnf" => pred_recdec::import::from_w3c_ebnf(&text, None),
+        _ => { println!("Unknown format {format}; expected abnf or ebnf"); return; }
+    };
+    let imported = match imported
+    {
+        Ok(x) => x,
+        Err(mut e) => { e.file = Some(fname.to_string()); println!("{e}"); return; }
+    };
+    match out_fname
+    {
+        Some(out_fname) =>
+        {
+            if let Err(e) = std::fs::write(out_fname, &imported.bnf) { println!("Failed to write {out_fname}: {e}"); }
+            print!("{}", imported.report());
+        }
+        // the report goes to stderr so that the grammar can be redirected to a file
+        None => { print!("{}", imported.bnf); eprint!("{}", imported.report()); }
+    }
+}
+
+fn coverage(fname : &str, root : &str, inputs : &[String], stub : Option<bool>)
+{
+    let mut g = match load_grammar(fname)
+    {
+        Ok(x) => x.0,
+        Err(e) => { println!("{e}"); return; }
+    };
+    if !g.by_name.contains_key(root)
+    {
+        println!("No such rule: {root}");
+        return;
+    }
+    note_guards_and_hooks(&g, stub);
+    let mut cov = Coverage::new(&g);
+    let mut failed = 0;
+    for input in inputs
+    {
+        let text = match std::fs::read_to_string(input)
+        {
+            Ok(text) => text,
+            Err(e) => { println!("{input}: failed to read: {e}"); failed += 1; continue; }
+        };
+        let tokens = match tokenize(&mut g, &text)
+        {
+            Ok(tokens) => tokens,
+            Err(e) => { println!("{input}: tokenizer error: {}", e.err_message); failed += 1; continue; }
+        };
+        // failed parses still count towards coverage, up to the point where they failed
+        let (guards, hooks) = stubs(&g, stub);
+        if let Err(e) = parse_with_observer(&g, root, &tokens[..], guards, hooks, &mut cov)
+        {
+            println!("{input}: parse error: {}", e.err_message);
+            failed += 1;
+        }
+    }
+    println!("Ran {} inputs ({} failed).", inputs.len(), failed);
+    print!("{}", cov.report(&g).format(&g));
+}
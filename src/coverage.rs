@@ -0,0 +1,154 @@
+// Grammar coverage
+
+use crate::bnf::*;
+use crate::trace::*;
+
+/// [`ParseObserver`] that records which grammar points, alternations and terms get exercised.
+///
+/// Coverage accumulates across any number of parses, so you can run a whole corpus through the same `Coverage` object and then call [`Coverage::report`]. The grammar can be re-tokenized against between parses; only its rules need to stay the same.
+///
+/// ```
+/// # {} /*
+/// let mut cov = Coverage::new(&g);
+/// for source in corpus
+/// {
+///     let tokens = tokenize(&mut g, &source).unwrap();
+///     let _ = parse_with_observer(&g, "S", &tokens[..], guards.clone(), hooks.clone(), &mut cov);
+/// }
+/// print!("{}", cov.report(&g).format(&g));
+/// # */
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    /// Number of times each grammar point was entered (or `$become`'d into). Index = grammar point ID.
+    pub points : Vec<u64>,
+    /// Number of times each alternation was chosen. Indexed by grammar point ID, then alternation index.
+    pub alts : Vec<Vec<u64>>,
+    /// Number of times each term was reached. Indexed by grammar point ID, then alternation index, then term index.
+    pub terms : Vec<Vec<Vec<u64>>>,
+}
+
+impl Coverage {
+    /// Create an empty coverage record sized for the given grammar.
+    pub fn new(g : &Grammar) -> Self
+    {
+        Self {
+            points : vec!(0; g.points.len()),
+            alts : g.points.iter().map(|p| vec!(0; p.forms.len())).collect(),
+            terms : g.points.iter().map(|p| p.forms.iter().map(|f| vec!(0; f.matching_terms.len())).collect()).collect(),
+        }
+    }
+    /// Merge another coverage record (for the same grammar) into this one.
+    pub fn merge(&mut self, other : &Coverage)
+    {
+        for (a, b) in self.points.iter_mut().zip(other.points.iter()) { *a += b; }
+        for (a, b) in self.alts.iter_mut().flatten().zip(other.alts.iter().flatten()) { *a += b; }
+        for (a, b) in self.terms.iter_mut().flatten().flatten().zip(other.terms.iter().flatten().flatten()) { *a += b; }
+    }
+    /// List everything that was never exercised. The grammar must be the same one that the coverage was recorded with.
+    pub fn report(&self, g : &Grammar) -> CoverageReport
+    {
+        let mut ret = CoverageReport::default();
+        for (i, n) in self.points.iter().enumerate()
+        {
+            if *n == 0 { ret.unused_points.push(i); }
+        }
+        for (i, alts) in self.alts.iter().enumerate()
+        {
+            for (j, n) in alts.iter().enumerate()
+            {
+                // alternations of a point that was never entered are implied by unused_points
+                if *n == 0 && self.points[i] != 0 { ret.unused_alts.push((i, j)); }
+            }
+        }
+        for (i, alts) in self.terms.iter().enumerate()
+        {
+            for (j, terms) in alts.iter().enumerate()
+            {
+                for (k, n) in terms.iter().enumerate()
+                {
+                    // same for terms of alternations that were never chosen
+                    if *n != 0 || self.alts[i][j] == 0 { continue; }
+                    // the rule name after $become/$become_as/$rename is part of the directive, not a term of its own
+                    if k > 0 && matches!(g.points[i].forms[j].matching_terms[k - 1].t,
+                        MatchingTermE::Directive(MatchDirective::Become | MatchDirective::BecomeAs | MatchDirective::Rename))
+                    {
+                        continue;
+                    }
+                    ret.unused_terms.push((i, j, k));
+                }
+            }
+        }
+        ret.total_points = self.points.len();
+        ret.total_alts = self.alts.iter().map(|x| x.len()).sum();
+        ret.total_terms = self.terms.iter().flatten().map(|x| x.len()).sum();
+        ret
+    }
+}
+
+impl ParseObserver for Coverage {
+    fn rule_entered(&mut self, rule : usize, _token : usize)
+    {
+        self.points[rule] += 1;
+    }
+    fn guard_checked(&mut self, rule : usize, alt : usize, _token : usize, _accepted : bool)
+    {
+        self.terms[rule][alt][0] += 1;
+    }
+    fn alt_chosen(&mut self, rule : usize, alt : usize, _token : usize)
+    {
+        self.alts[rule][alt] += 1;
+    }
+    fn term_reached(&mut self, rule : usize, alt : usize, term : usize, _token : usize)
+    {
+        self.terms[rule][alt][term] += 1;
+    }
+    fn became(&mut self, _rule : usize, target : usize, _token : usize)
+    {
+        self.points[target] += 1;
+    }
+}
+
+/// Produced by [`Coverage::report`]. Lists the parts of a grammar that were never exercised.
+///
+/// Alternations are only listed if their grammar point was exercised, and terms are only listed if their alternation was exercised, so that the report isn't flooded by the contents of dead rules.
+#[derive(Clone, Debug, Default)]
+pub struct CoverageReport {
+    /// Grammar point IDs that were never entered.
+    pub unused_points : Vec<usize>,
+    /// `(grammar point ID, alternation index)` pairs that were never chosen.
+    pub unused_alts : Vec<(usize, usize)>,
+    /// `(grammar point ID, alternation index, term index)` triples that were never reached.
+    pub unused_terms : Vec<(usize, usize, usize)>,
+    /// Total number of grammar points in the grammar.
+    pub total_points : usize,
+    /// Total number of alternations in the grammar.
+    pub total_alts : usize,
+    /// Total number of terms in the grammar.
+    pub total_terms : usize,
+}
+
+impl CoverageReport {
+    /// Format the report as human-readable text, using the given grammar for names. Format is not guaranteed and may change arbitrarily.
+    pub fn format(&self, g : &Grammar) -> String
+    {
+        let mut s = String::new();
+        s += &format!("Grammar points never entered: {} of {}\n", self.unused_points.len(), self.total_points);
+        for i in &self.unused_points
+        {
+            s += &format!("  {}\n", g.points[*i].name);
+        }
+        s += &format!("Alternations never chosen (in entered points): {} of {}\n", self.unused_alts.len(), self.total_alts);
+        for (i, j) in &self.unused_alts
+        {
+            let terms = g.points[*i].forms[*j].matching_terms.iter().map(|t| t.describe(g)).collect::<Vec<_>>();
+            s += &format!("  {} alt {}: {}\n", g.points[*i].name, j, terms.join(" "));
+        }
+        s += &format!("Terms never reached (in chosen alternations): {} of {}\n", self.unused_terms.len(), self.total_terms);
+        for (i, j, k) in &self.unused_terms
+        {
+            s += &format!("  {} alt {} term {}: {}\n", g.points[*i].name, j, k, g.points[*i].forms[*j].matching_terms[*k].describe(g));
+        }
+        s
+    }
+}
@@ -0,0 +1,333 @@
+// Interactive step-through grammar debugger
+
+use crate::bnf::*;
+use crate::ast::*;
+use crate::trace::*;
+use std::io::Write;
+
+/// Where a [`Debugger`] should stop.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stop whenever the named rule is entered (or `$become`'d into).
+    Rule(String),
+    /// Stop when the parse position first reaches or passes the given token index.
+    Token(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode { Step, Next(usize), Finish(usize), Continue, Detached }
+
+const DEBUGGER_HELP : &str = "\
+Commands (an empty line repeats the last command):
+  s, step            stop at the next rule entry or term
+  n, next            same, but skip over the insides of child rules
+  f, finish          run until the current rule is done
+  c, continue        run until the next breakpoint
+  b <rule>           break when <rule> is entered
+  b @<n>             break when the parse position reaches token <n>
+  bl                 list breakpoints
+  d <n>              delete breakpoint number <n>
+  bt                 show the rule stack
+  alts               show the current rule's alternations
+  children [n]       show the AST nodes produced so far by the current frame (or frame #n)
+  tokens [n]         show the next n tokens (default 8)
+  udata              show udata (needs a udata printer)
+  q, detach          stop debugging and let the parse run to completion
+  h, help            show this text";
+
+/// Interactive step-through debugger, implemented as a [`ParseObserver`].
+///
+/// Supports breakpoints on rule names and token positions, stepping through terms, and inspecting the rule stack (with chosen alternations and partially-built children) and `udata`. While single-stepping, it also prints guard results, chosen alternations, hook calls, `$become`s, and rule exits as they happen, so you can see why a rule picked the alternation it did.
+///
+/// Only works with [`parse_with_observer`], because the recursive implementation doesn't have an inspectable stack. Stops at the very first step by default; type `help` at the prompt for a list of commands.
+///
+/// ```
+/// # {} /*
+/// let mut dbg = Debugger::stdio(&g);
+/// dbg.add_breakpoint(Breakpoint::Rule("declaration".to_string()));
+/// let ast = parse_with_observer(&g, "S", &tokens[..], guards, hooks, &mut dbg);
+/// # */
+/// ```
+pub struct Debugger<'a> {
+    g : &'a Grammar,
+    input : Box<dyn FnMut() -> Option<String> + 'a>,
+    output : Box<dyn Write + 'a>,
+    udata_printer : Option<Box<dyn Fn(&PrdGlobal) -> String + 'a>>,
+    breakpoints : Vec<Breakpoint>,
+    mode : Mode,
+    last_command : String,
+    // parse position at the previous step, for token breakpoints
+    prev_token : usize,
+}
+
+impl<'a> Debugger<'a> {
+    /// Create a debugger that reads commands by calling `input` (which returns `None` at end of input) and writes to `output`.
+    pub fn new(g : &'a Grammar, input : impl FnMut() -> Option<String> + 'a, output : impl Write + 'a) -> Self
+    {
+        Self {
+            g, input : Box::new(input), output : Box::new(output), udata_printer : None,
+            breakpoints : vec!(), mode : Mode::Step, last_command : String::new(), prev_token : 0,
+        }
+    }
+    /// Create a debugger that reads commands from stdin and writes to stdout.
+    pub fn stdio(g : &'a Grammar) -> Self
+    {
+        let input = ||
+        {
+            let mut line = String::new();
+            match std::io::stdin().read_line(&mut line)
+            {
+                Ok(0) | Err(_) => None,
+                Ok(_) => Some(line),
+            }
+        };
+        Self::new(g, input, std::io::stdout())
+    }
+    /// Set the function used by the `udata` command. `udata` is type-erased, so the debugger can't print it by itself.
+    pub fn with_udata_printer(mut self, f : impl Fn(&PrdGlobal) -> String + 'a) -> Self
+    {
+        self.udata_printer = Some(Box::new(f));
+        self
+    }
+    /// Add a breakpoint.
+    pub fn add_breakpoint(&mut self, bp : Breakpoint)
+    {
+        self.breakpoints.push(bp);
+    }
+    /// Don't stop at the first step; run until the first breakpoint instead.
+    pub fn start_running(&mut self)
+    {
+        self.mode = Mode::Continue;
+    }
+    
+    fn tracing(&self) -> bool { self.mode == Mode::Step }
+    fn out(&mut self, s : &str)
+    {
+        let _ = writeln!(self.output, "{s}");
+    }
+    fn token_text(&self, state : &ParseState, i : usize) -> String
+    {
+        match state.tokens.get(i)
+        {
+            Some(t) => format!("`{}`", self.g.string_cache_inv[t.text as usize]),
+            None => "<end of input>".to_string(),
+        }
+    }
+    fn should_stop(&mut self, state : &ParseState) -> bool
+    {
+        let top = state.top();
+        let crossed = self.breakpoints.iter().any(|bp| match bp
+        {
+            Breakpoint::Rule(name) => state.kind == StepKind::RuleEntered && *self.g.points[top.rule].name == *name,
+            Breakpoint::Token(n) => top.token >= *n && self.prev_token < *n,
+        });
+        self.prev_token = top.token;
+        match self.mode
+        {
+            Mode::Detached => false,
+            Mode::Step => true,
+            Mode::Next(depth) => state.depth() <= depth || crossed,
+            Mode::Finish(depth) => state.depth() < depth || crossed,
+            Mode::Continue => crossed,
+        }
+    }
+    fn describe_frame(&self, state : &ParseState, frame : &StackFrame, entered : bool) -> String
+    {
+        let point = &self.g.points[frame.rule];
+        let mut s = point.name.to_string();
+        if *self.g.string_cache_inv[frame.name_id as usize] != *point.name
+        {
+            s += &format!(" (as {})", self.g.string_cache_inv[frame.name_id as usize]);
+        }
+        if entered
+        {
+            s += &format!(", choosing among {} alternations", point.forms.len());
+        }
+        else if let Some(term) = point.forms.get(frame.alt).and_then(|a| a.matching_terms.get(frame.term))
+        {
+            s += &format!(" alt {} term {}: {}", frame.alt, frame.term, term.describe(self.g));
+        }
+        s += &format!(", tokens {}..{} (next: {})", frame.token_start, frame.token, self.token_text(state, frame.token));
+        if frame.poisoned { s += ", poisoned"; }
+        s
+    }
+    fn show_alts(&mut self, state : &ParseState)
+    {
+        let top = state.top();
+        let point = &self.g.points[top.rule];
+        let mut lines = vec!();
+        for (i, alt) in point.forms.iter().enumerate()
+        {
+            let marker = if state.kind == StepKind::Term && i == top.alt { "*" } else { " " };
+            let terms = alt.matching_terms.iter().map(|t| t.describe(self.g)).collect::<Vec<_>>();
+            lines.push(format!("{marker} {i}: {}", if terms.is_empty() { "#empty".to_string() } else { terms.join(" ") }));
+        }
+        for l in lines { self.out(&l); }
+    }
+    fn show_children(&mut self, state : &ParseState, frame : Option<&str>)
+    {
+        let index = match frame.map(|f| f.parse::<usize>())
+        {
+            None => state.depth() - 1,
+            Some(Ok(n)) if n < state.depth() => n,
+            _ => return self.out("No such frame."),
+        };
+        let children = state.frame(index).children;
+        if children.is_empty() { return self.out("(no children yet)"); }
+        let mut s = String::new();
+        for c in children
+        {
+            ast_to_sexpr(c, self.g, &mut s);
+            s += "\n";
+        }
+        self.out(s.trim_end());
+    }
+    fn command_loop(&mut self, state : &ParseState)
+    {
+        let top = state.top();
+        let loc = format!("#{} {}", state.depth() - 1, self.describe_frame(state, &top, state.kind == StepKind::RuleEntered));
+        self.out(&loc);
+        loop
+        {
+            let _ = write!(self.output, "(prd) ");
+            let _ = self.output.flush();
+            let Some(line) = (self.input)() else
+            {
+                self.mode = Mode::Detached;
+                return;
+            };
+            let mut line = line.trim().to_string();
+            if line.is_empty() { line = self.last_command.clone(); }
+            self.last_command = line.clone();
+            
+            let mut split = line.split_whitespace();
+            match (split.next().unwrap_or(""), split.next())
+            {
+                ("s" | "step", _) => { self.mode = Mode::Step; return; }
+                ("n" | "next", _) => { self.mode = Mode::Next(state.depth()); return; }
+                ("f" | "finish", _) => { self.mode = Mode::Finish(state.depth()); return; }
+                ("c" | "continue", _) => { self.mode = Mode::Continue; return; }
+                ("q" | "detach", _) => { self.mode = Mode::Detached; return; }
+                ("b" | "break", Some(arg)) =>
+                {
+                    let bp = if let Some(n) = arg.strip_prefix("@")
+                    {
+                        match n.parse() { Ok(n) => Breakpoint::Token(n), Err(_) => { self.out("Bad token index."); continue; } }
+                    }
+                    else if self.g.by_name.contains_key(arg) { Breakpoint::Rule(arg.to_string()) }
+                    else { self.out(&format!("No such rule: {arg}")); continue; };
+                    self.breakpoints.push(bp);
+                    self.out(&format!("Breakpoint {} set.", self.breakpoints.len() - 1));
+                }
+                ("bl", _) =>
+                {
+                    let lines = self.breakpoints.iter().enumerate().map(|(i, bp)| match bp
+                    {
+                        Breakpoint::Rule(name) => format!("{i}: rule {name}"),
+                        Breakpoint::Token(n) => format!("{i}: token @{n}"),
+                    }).collect::<Vec<_>>();
+                    if lines.is_empty() { self.out("No breakpoints."); }
+                    for l in lines { self.out(&l); }
+                }
+                ("d" | "delete", Some(arg)) => match arg.parse::<usize>()
+                {
+                    Ok(n) if n < self.breakpoints.len() => { self.breakpoints.remove(n); }
+                    _ => self.out("No such breakpoint."),
+                }
+                ("bt" | "stack", _) =>
+                {
+                    let lines = (0..state.depth()).rev().map(|i|
+                        format!("#{} {}", i, self.describe_frame(state, &state.frame(i), i + 1 == state.depth() && state.kind == StepKind::RuleEntered))
+                    ).collect::<Vec<_>>();
+                    for l in lines { self.out(&l); }
+                }
+                ("alts", _) => self.show_alts(state),
+                ("children", frame) => self.show_children(state, frame),
+                ("tokens", n) =>
+                {
+                    let n = n.and_then(|n| n.parse().ok()).unwrap_or(8);
+                    let i = top.token;
+                    let texts = (i..(i + n).min(state.tokens.len())).map(|j| format!("{j}:{}", self.token_text(state, j))).collect::<Vec<_>>();
+                    self.out(&if texts.is_empty() { "<end of input>".to_string() } else { texts.join(" ") });
+                }
+                ("udata", _) =>
+                {
+                    let s = match &self.udata_printer
+                    {
+                        Some(f) => f(state.global),
+                        None => "No udata printer set (see Debugger::with_udata_printer).".to_string(),
+                    };
+                    self.out(&s);
+                }
+                ("h" | "help", _) => self.out(DEBUGGER_HELP),
+                _ => self.out("Unknown command. Type help for commands."),
+            }
+        }
+    }
+}
+
+impl<'a> ParseObserver for Debugger<'a> {
+    fn guard_checked(&mut self, rule : usize, alt : usize, token : usize, accepted : bool)
+    {
+        if !self.tracing() { return; }
+        let guard = self.g.points[rule].forms[alt].matching_terms[0].describe(self.g);
+        self.out(&format!("  {} alt {} at token {}: {} {}", self.g.points[rule].name, alt, token, guard, if accepted { "accepted" } else { "rejected" }));
+    }
+    fn alt_chosen(&mut self, rule : usize, alt : usize, _token : usize)
+    {
+        if !self.tracing() { return; }
+        self.out(&format!("  {} chose alt {}", self.g.points[rule].name, alt));
+    }
+    fn became(&mut self, rule : usize, target : usize, _token : usize)
+    {
+        if !self.tracing() { return; }
+        self.out(&format!("  {} $become {}", self.g.points[rule].name, self.g.points[target].name));
+    }
+    fn hook_called(&mut self, _rule : usize, name : &str, _token : usize, result : &Result<usize, String>)
+    {
+        if !self.tracing() { return; }
+        self.out(&format!("  !hook({name}) returned {result:?}"));
+    }
+    fn recovered(&mut self, rule : usize, from : usize, to : usize)
+    {
+        if !self.tracing() { return; }
+        self.out(&format!("  {} recovered, skipping from token {from} to {to}", self.g.points[rule].name));
+    }
+    fn rule_exited(&mut self, rule : usize, token_start : usize, token_end : usize, ok : bool)
+    {
+        if !self.tracing() { return; }
+        self.out(&format!("  {} {} tokens {}..{}", self.g.points[rule].name, if ok { "accepted" } else { "FAILED at" }, token_start, token_end));
+    }
+    fn wants_steps(&self) -> bool { self.mode != Mode::Detached }
+    fn step(&mut self, state : &ParseState)
+    {
+        if self.should_stop(state) { self.command_loop(state); }
+    }
+}
+
+/// Write an AST as a compact one-line s-expression, e.g. `item("(" ")")`. Poisoned nodes are marked with `!`.
+pub fn ast_to_sexpr(ast : &ASTNode, g : &Grammar, out : &mut String)
+{
+    // ASTs can be deeply recursive, so this is iterative. None = close paren.
+    let mut stack = vec!((Some(ast), false));
+    while let Some((node, space)) = stack.pop()
+    {
+        let Some(node) = node else { *out += ")"; continue; };
+        if space { *out += " "; }
+        if let Some(c) = &node.children
+        {
+            if node.is_poisoned() { *out += "!"; }
+            *out += &g.string_cache_inv[node.text as usize];
+            *out += "(";
+            stack.push((None, false));
+            for (i, c) in c.iter().enumerate().rev()
+            {
+                stack.push((Some(c), i > 0));
+            }
+        }
+        else
+        {
+            *out += &format!("{:?}", g.string_cache_inv[node.text as usize]);
+        }
+    }
+}
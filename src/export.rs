@@ -0,0 +1,660 @@
+// Grammar export to other formats
+
+use crate::bnf::*;
+use regex_syntax::hir::{Hir, HirKind, Class, Look};
+use std::rc::Rc;
+
+type HashMap<K, V> = std::collections::HashMap::<K, V, crate::HashBuilder>;
+type HashSet<K> = std::collections::HashSet::<K, crate::HashBuilder>;
+
+// One rule as a plain EBNF expression, with `$become` loops turned back into repetition. Shared with the railroad diagram generator.
+#[derive(Clone, Debug)]
+pub (crate) enum Expr {
+    Lit(u32),
+    Regex(RegexCacher),
+    Any,
+    Rule(usize),
+    // tail call into another rule with `$become` or `$become_as`
+    Become(usize),
+    // guard or other predicate; doesn't consume anything
+    Predicate(MatchingTerm),
+    Hook(Rc<String>),
+    Seq(Vec<Expr>),
+    Choice(Vec<Expr>),
+    // zero or more times
+    Many(Box<Expr>),
+    Empty,
+}
+
+// One alternation, and whether it ends by looping back to the start of its rule (`$become` itself).
+fn alt_expr(rule : usize, alt : &Alternation) -> (Expr, bool)
+{
+    let terms = &alt.matching_terms;
+    let mut items = vec!();
+    let mut loops = false;
+    let mut i = 0;
+    while i < terms.len()
+    {
+        let next = terms.get(i + 1).map(|t| &t.t);
+        let next_is_any = matches!(next, Some(MatchingTermE::Directive(MatchDirective::Any)));
+        match &terms[i].t
+        {
+            // `@auto x` is stored as `@peek(0, x) $any`, which just matches x
+            MatchingTermE::Peek(0, s) if next_is_any => { items.push(Expr::Lit(*s)); i += 1; }
+            MatchingTermE::PeekR(0, r) if next_is_any => { items.push(Expr::Regex(r.clone())); i += 1; }
+            // `@peek(0, x) x` doesn't say anything that x doesn't
+            MatchingTermE::Peek(0, s) if matches!(next, Some(MatchingTermE::TermLit(s2)) if s2 == s) => {}
+            MatchingTermE::Peek(..) | MatchingTermE::PeekR(..) | MatchingTermE::PeekRes(..) | MatchingTermE::Guard(_) | MatchingTermE::Eof =>
+                items.push(Expr::Predicate(terms[i].clone())),
+            MatchingTermE::Hook(name) => items.push(Expr::Hook(Rc::clone(name))),
+            MatchingTermE::TermLit(s) => items.push(Expr::Lit(*s)),
+            MatchingTermE::TermRegex(r) => items.push(Expr::Regex(r.clone())),
+            MatchingTermE::Rule(id) => items.push(Expr::Rule(*id)),
+            MatchingTermE::Directive(MatchDirective::Any) => items.push(Expr::Any),
+            MatchingTermE::Directive(MatchDirective::Become | MatchDirective::BecomeAs) =>
+            {
+                match next
+                {
+                    Some(MatchingTermE::Rule(id)) if *id == rule => loops = true,
+                    Some(MatchingTermE::Rule(id)) => items.push(Expr::Become(*id)),
+                    _ => {}
+                }
+                i += 1;
+            }
+            // only changes the AST node's name
+            MatchingTermE::Directive(MatchDirective::Rename) => i += 1,
+            // only change the shape of the AST
+            MatchingTermE::Directive(_) | MatchingTermE::_AutoTemp => {}
+        }
+        i += 1;
+    }
+    let expr = match items.len()
+    {
+        0 => Expr::Empty,
+        1 => items.pop().unwrap(),
+        _ => Expr::Seq(items),
+    };
+    (expr, loops)
+}
+
+fn choice(mut items : Vec<Expr>) -> Expr
+{
+    if items.len() == 1 { items.pop().unwrap() } else { Expr::Choice(items) }
+}
+
+// A whole rule. Alternations that `$become` the rule itself can be taken any number of times before one of the others.
+pub (crate) fn rule_expr(g : &Grammar, rule : usize) -> Expr
+{
+    let mut loops = vec!();
+    let mut ends = vec!();
+    for alt in &g.points[rule].forms
+    {
+        let (expr, is_loop) = alt_expr(rule, alt);
+        if is_loop { loops.push(expr); } else { ends.push(expr); }
+    }
+    if loops.is_empty() { return choice(ends); }
+    let repeat = Expr::Many(Box::new(choice(loops)));
+    match choice(ends)
+    {
+        Expr::Choice(ends) if ends.is_empty() => repeat,
+        Expr::Empty => repeat,
+        end => Expr::Seq(vec!(repeat, end)),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format { W3c, Antlr, TreeSitter }
+
+// Names of rules and tokens in the exported grammar, which have to be plain identifiers.
+struct Names {
+    rules : Vec<String>,
+    literals : HashMap<u32, String>,
+    regexes : HashMap<String, String>,
+    // (token name, definition), in the order that they should be defined
+    tokens : Vec<(String, String)>,
+    any : bool,
+}
+
+fn identifier(name : &str) -> String
+{
+    let mut ret = String::new();
+    for c in name.chars()
+    {
+        if c.is_ascii_alphanumeric() || c == '_' { ret.push(c); }
+        else if !ret.ends_with('_') { ret.push('_'); }
+    }
+    if ret.is_empty() || ret.starts_with(|c : char| c.is_ascii_digit()) { ret.insert(0, '_'); }
+    ret
+}
+
+fn unique(name : String, used : &mut HashSet<String>) -> String
+{
+    let mut ret = name.clone();
+    let mut n = 2;
+    while !used.insert(ret.clone())
+    {
+        ret = format!("{name}_{n}");
+        n += 1;
+    }
+    ret
+}
+
+// Readable token name for a literal, like `IF` or `LPAREN`.
+fn literal_name(text : &str) -> String
+{
+    if text.starts_with(|c : char| c.is_ascii_alphabetic() || c == '_') && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return text.to_uppercase();
+    }
+    let names = text.chars().map(|c| match c
+    {
+        '(' => "LPAREN", ')' => "RPAREN", '[' => "LBRACKET", ']' => "RBRACKET", '{' => "LBRACE", '}' => "RBRACE",
+        ',' => "COMMA", ';' => "SEMI", ':' => "COLON", '.' => "DOT", '+' => "PLUS", '-' => "MINUS", '*' => "STAR",
+        '/' => "SLASH", '%' => "PERCENT", '=' => "EQ", '<' => "LT", '>' => "GT", '!' => "BANG", '&' => "AMP",
+        '|' => "PIPE", '^' => "CARET", '~' => "TILDE", '?' => "QUESTION", '#' => "HASH", '@' => "AT", '$' => "DOLLAR",
+        '"' => "DQUOTE", '\'' => "QUOTE", '\\' => "BACKSLASH", '`' => "BACKTICK",
+        c if c.is_ascii_alphanumeric() => "",
+        _ => "T",
+    }.to_string()).collect::<Vec<_>>();
+    if names.iter().any(|x| x.is_empty()) { return "T".to_string(); }
+    names.join("_")
+}
+
+// The pattern of a regex, as written in the grammar.
+pub (crate) fn regex_source(r : &RegexCacher) -> &str
+{
+    let p = r.pattern().strip_prefix("\\A(?:").unwrap_or(r.pattern());
+    p.strip_suffix(")\\z").or_else(|| p.strip_suffix(")")).unwrap_or(p)
+}
+
+// A regex as it would be written in a grammar's peek (where r`` and R`` mean the same thing).
+fn regex_term(r : &RegexCacher) -> String
+{
+    let kind = if r.pattern().ends_with("\\z") { "R" } else { "A" };
+    format!("{kind}`{}`r", regex_source(r))
+}
+
+// A predicate as it's written in the grammar.
+fn predicate_text(g : &Grammar, t : &MatchingTerm) -> String
+{
+    let string = |id : u32| format!("{:?}", g.string_cache_inv[id as usize]);
+    match &t.t
+    {
+        MatchingTermE::Peek(n, s) => format!("@peek({n}, {})", string(*s)),
+        MatchingTermE::PeekR(n, r) => format!("@peekr({n}, {})", regex_term(r)),
+        MatchingTermE::PeekRes(n, r) => format!("@peekres({n}, {})", regex_term(r)),
+        _ => t.describe(g),
+    }
+}
+
+fn names(g : &Grammar, format : Format, rules : &[usize]) -> Names
+{
+    // names that mean something else already
+    let reserved : &[&str] = match format
+    {
+        Format::Antlr => &["EOF", "grammar", "fragment", "lexer", "parser", "import", "options", "tokens", "channels", "mode", "returns", "locals", "throws", "catch", "finally"],
+        Format::W3c => &["ANY_TOKEN"],
+        Format::TreeSitter => &["any_token"],
+    };
+    let mut used = reserved.iter().map(|x| x.to_string()).collect::<HashSet<_>>();
+    let mut ret = Names { rules : vec!(String::new(); g.points.len()), literals : <_>::default(), regexes : <_>::default(), tokens : vec!(), any : false };
+    for &id in rules
+    {
+        let name = identifier(&g.points[id].name);
+        // ANTLR parser rules have to start with a lowercase letter
+        let name = match format
+        {
+            Format::Antlr if !name.chars().any(|c| c.is_ascii_lowercase()) => name.to_lowercase(),
+            Format::Antlr => name[..1].to_lowercase() + &name[1..],
+            _ => name,
+        };
+        ret.rules[id] = unique(name, &mut used);
+    }
+    if format == Format::TreeSitter { return ret; }
+    
+    let mut literals = vec!();
+    let mut regexes = vec!();
+    let mut unnamed = 0;
+    let mut visit = |e : &Expr, rule : usize, sole : bool, ret : &mut Names|
+    {
+        match e
+        {
+            Expr::Lit(s) if !ret.literals.contains_key(s) =>
+            {
+                let name = unique(literal_name(&g.string_cache_inv[*s as usize]), &mut used);
+                ret.literals.insert(*s, name.clone());
+                literals.push((name, *s));
+            }
+            Expr::Regex(r) if !ret.regexes.contains_key(r.pattern()) =>
+            {
+                // a rule that's only a regex gives the regex its name
+                let name = if sole { identifier(&g.points[rule].name).to_uppercase() } else { unnamed += 1; format!("REGEX_{unnamed}") };
+                let name = unique(name, &mut used);
+                ret.regexes.insert(r.pattern().to_string(), name.clone());
+                regexes.push((name, r.clone()));
+            }
+            Expr::Any => ret.any = true,
+            _ => {}
+        }
+    };
+    for &id in rules
+    {
+        let e = rule_expr(g, id);
+        let sole = matches!(e, Expr::Regex(_));
+        walk(&e, &mut |x| visit(x, id, sole, &mut ret));
+    }
+    for (name, s) in literals
+    {
+        ret.tokens.push((name, string(format, &g.string_cache_inv[s as usize])));
+    }
+    for (name, r) in regexes
+    {
+        let def = regex_syntax::parse(regex_source(&r)).ok().and_then(|h| hir(format, &h))
+            .unwrap_or_else(|| format!("/* TODO: translate by hand: r`{}`r */", regex_source(&r)));
+        // A`` regexes match any token that starts with them
+        let def = if r.pattern().ends_with("\\z") { def } else { format!("{def} /* TODO: matches any token that starts with this */") };
+        ret.tokens.push((name, def));
+    }
+    ret
+}
+
+fn walk(e : &Expr, f : &mut dyn FnMut(&Expr))
+{
+    f(e);
+    match e
+    {
+        Expr::Seq(items) | Expr::Choice(items) => for x in items { walk(x, f); }
+        Expr::Many(x) => walk(x, f),
+        _ => {}
+    }
+}
+
+// String literal in the given format.
+fn string(format : Format, text : &str) -> String
+{
+    match format
+    {
+        Format::W3c =>
+        {
+            // W3C EBNF strings don't have escapes, so pick a quote that isn't in the string, and write anything else as #xN
+            let mut parts = vec!();
+            let mut current = String::new();
+            for c in text.chars()
+            {
+                let quote = if current.contains('\'') { '"' } else { '\'' };
+                if c.is_control() || (c == quote && current.contains(if quote == '"' { '\'' } else { '"' }))
+                {
+                    if !current.is_empty() { parts.push(w3c_quote(&current)); current.clear(); }
+                    if c.is_control() { parts.push(format!("#x{:X}", c as u32)); continue; }
+                }
+                current.push(c);
+            }
+            if !current.is_empty() { parts.push(w3c_quote(&current)); }
+            if parts.len() == 1 { parts.pop().unwrap() } else { format!("({})", parts.join(" ")) }
+        }
+        Format::Antlr => format!("'{}'", escape_chars(text, "'\\")),
+        Format::TreeSitter => format!("'{}'", escape_chars(text, "'\\")),
+    }
+}
+
+fn w3c_quote(text : &str) -> String
+{
+    if text.contains('\'') { format!("\"{text}\"") } else { format!("'{text}'") }
+}
+
+// Backslash-escapes the given characters and control characters, for ANTLR and JavaScript.
+fn escape_chars(text : &str, special : &str) -> String
+{
+    let mut ret = String::new();
+    for c in text.chars()
+    {
+        match c
+        {
+            '\n' => ret += "\\n",
+            '\r' => ret += "\\r",
+            '\t' => ret += "\\t",
+            c if c.is_control() => ret += &format!("\\u{:04X}", c as u32),
+            c if special.contains(c) => { ret.push('\\'); ret.push(c); }
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+// One character inside of a character class.
+fn class_char(format : Format, c : char) -> String
+{
+    match format
+    {
+        Format::W3c if c.is_control() || c.is_whitespace() || "[]^-#".contains(c) => format!("#x{:X}", c as u32),
+        Format::W3c => c.to_string(),
+        Format::Antlr if c as u32 > 0xFFFF => format!("\\u{{{:X}}}", c as u32),
+        Format::Antlr => escape_chars(&c.to_string(), "]\\-"),
+        Format::TreeSitter if c as u32 > 0xFFFF => format!("\\u{{{:X}}}", c as u32),
+        Format::TreeSitter => escape_chars(&c.to_string(), "]\\-^/["),
+    }
+}
+
+// A parsed regex in the given format, or None if it uses something that the format doesn't have (like word boundaries).
+fn hir(format : Format, h : &Hir) -> Option<String>
+{
+    // wraps x in a group, unless it's a single item already
+    let atom = |x : &Hir, s : String| match x.kind()
+    {
+        HirKind::Class(_) | HirKind::Capture(_) | HirKind::Alternation(_) => s,
+        HirKind::Literal(l) if format != Format::TreeSitter || String::from_utf8_lossy(&l.0).chars().count() == 1 => s,
+        _ if format == Format::TreeSitter => format!("(?:{s})"),
+        _ => format!("({s})"),
+    };
+    Some(match h.kind()
+    {
+        HirKind::Empty => match format { Format::TreeSitter => String::new(), _ => "''".to_string() },
+        HirKind::Literal(l) =>
+        {
+            let text = String::from_utf8_lossy(&l.0);
+            match format
+            {
+                Format::TreeSitter => escape_chars(&text, "\\^$.|?*+()[]{}/"),
+                _ => string(format, &text),
+            }
+        }
+        HirKind::Class(class) =>
+        {
+            let mut ranges = match class
+            {
+                Class::Unicode(c) => c.ranges().iter().map(|r| (r.start(), r.end())).collect::<Vec<_>>(),
+                Class::Bytes(c) => c.ranges().iter().map(|r| (r.start() as char, r.end() as char)).collect(),
+            };
+            // big classes like [^a] are shorter to write negated
+            let mut negated = false;
+            if ranges.last().is_some_and(|r| r.1 == char::MAX)
+            {
+                let mut class = class.clone();
+                class.negate();
+                ranges = match &class
+                {
+                    Class::Unicode(c) => c.ranges().iter().map(|r| (r.start(), r.end())).collect(),
+                    Class::Bytes(c) => c.ranges().iter().map(|r| (r.start() as char, r.end() as char)).collect(),
+                };
+                negated = true;
+            }
+            let items = ranges.iter().map(|(a, b)| match a == b
+            {
+                true => class_char(format, *a),
+                false => format!("{}-{}", class_char(format, *a), class_char(format, *b)),
+            }).collect::<String>();
+            match (format, negated)
+            {
+                (Format::Antlr, true) => format!("~[{items}]"),
+                (_, true) => format!("[^{items}]"),
+                (_, false) => format!("[{items}]"),
+            }
+        }
+        HirKind::Look(Look::Start | Look::End) => String::new(),
+        HirKind::Look(_) => None?,
+        HirKind::Repetition(rep) =>
+        {
+            let sub = atom(&rep.sub, hir(format, &rep.sub)?);
+            let lazy = if rep.greedy { "" } else { "?" };
+            match (rep.min, rep.max)
+            {
+                (0, None) => format!("{sub}*{lazy}"),
+                (1, None) => format!("{sub}+{lazy}"),
+                (0, Some(1)) => format!("{sub}?{lazy}"),
+                (min, max) if format == Format::TreeSitter => match max
+                {
+                    Some(max) if max == min => format!("{sub}{{{min}}}"),
+                    Some(max) => format!("{sub}{{{min},{max}}}"),
+                    None => format!("{sub}{{{min},}}"),
+                },
+                // the others don't have counted repetition, so write it out
+                (min, max) if max.unwrap_or(min) <= 16 =>
+                {
+                    let mut items = vec!(sub.clone(); min as usize);
+                    match max
+                    {
+                        Some(max) => items.extend(std::iter::repeat(format!("{sub}?")).take((max - min) as usize)),
+                        None => items.push(format!("{sub}*")),
+                    }
+                    format!("({})", items.join(" "))
+                }
+                _ => None?,
+            }
+        }
+        HirKind::Capture(cap) => match format
+        {
+            Format::TreeSitter => format!("(?:{})", hir(format, &cap.sub)?),
+            _ => format!("({})", hir(format, &cap.sub)?),
+        },
+        HirKind::Concat(items) =>
+        {
+            let items = items.iter().map(|x| hir(format, x)).collect::<Option<Vec<_>>>()?;
+            let items = items.into_iter().filter(|x| !x.is_empty()).collect::<Vec<_>>();
+            items.join(if format == Format::TreeSitter { "" } else { " " })
+        }
+        HirKind::Alternation(items) =>
+        {
+            let items = items.iter().map(|x| hir(format, x)).collect::<Option<Vec<_>>>()?;
+            match format
+            {
+                Format::TreeSitter => format!("(?:{})", items.join("|")),
+                _ => format!("({})", items.join(" | ")),
+            }
+        }
+    })
+}
+
+// A rule's expression in the given format.
+fn expr(g : &Grammar, format : Format, names : &Names, e : &Expr) -> String
+{
+    let comment = |text : String| format!("/* {} */", text.replace("*/", "* /"));
+    match e
+    {
+        Expr::Lit(s) => match format
+        {
+            Format::TreeSitter => string(format, &g.string_cache_inv[*s as usize]),
+            _ => names.literals[s].clone(),
+        },
+        Expr::Regex(r) => match format
+        {
+            Format::TreeSitter => match regex_syntax::parse(regex_source(r)).ok().and_then(|h| hir(format, &h))
+            {
+                Some(re) if r.pattern().ends_with("\\z") => format!("/{re}/"),
+                Some(re) => format!("/{re}/ {}", comment("TODO: matches any token that starts with this".to_string())),
+                None => format!("/{}/ {}", regex_source(r).replace('/', "\\/"), comment("TODO: check this regex".to_string())),
+            },
+            _ => names.regexes[r.pattern()].clone(),
+        },
+        Expr::Any => match format
+        {
+            Format::Antlr => ".".to_string(),
+            Format::TreeSitter => "$.any_token".to_string(),
+            Format::W3c => "ANY_TOKEN".to_string(),
+        },
+        Expr::Rule(id) | Expr::Become(id) => match format
+        {
+            Format::TreeSitter => format!("$.{}", names.rules[*id]),
+            _ => names.rules[*id].clone(),
+        },
+        Expr::Predicate(t) => match (&t.t, format)
+        {
+            (MatchingTermE::Eof, Format::Antlr) => "EOF".to_string(),
+            _ => comment(predicate_text(g, t)),
+        },
+        Expr::Hook(name) => comment(format!("!hook({name})")),
+        Expr::Empty => match format
+        {
+            Format::TreeSitter => "blank()".to_string(),
+            Format::W3c => "/* empty */".to_string(),
+            Format::Antlr => String::new(),
+        },
+        Expr::Seq(items) =>
+        {
+            let parts = items.iter().map(|x| group(format, x, expr(g, format, names, x))).collect::<Vec<_>>();
+            match format
+            {
+                Format::TreeSitter => tree_sitter_call("seq", items, parts),
+                _ => parts.join(" "),
+            }
+        }
+        Expr::Choice(items) =>
+        {
+            // an empty alternative makes the rest optional
+            let empty = items.iter().any(|x| matches!(x, Expr::Empty));
+            let rest = items.iter().filter(|x| !matches!(x, Expr::Empty)).collect::<Vec<_>>();
+            let parts = rest.iter().map(|x| group(format, x, expr(g, format, names, x))).collect::<Vec<_>>();
+            match (format, empty)
+            {
+                (Format::TreeSitter, true) => format!("optional({})", tree_sitter_call("choice", &rest.into_iter().cloned().collect::<Vec<_>>(), parts)),
+                (Format::TreeSitter, false) => tree_sitter_call("choice", items, parts),
+                (_, true) => format!("({})?", parts.join(" | ")),
+                (_, false) => parts.join(" | "),
+            }
+        }
+        Expr::Many(x) => match format
+        {
+            Format::TreeSitter => format!("repeat({})", expr(g, format, names, x)),
+            _ if matches!(**x, Expr::Seq(_) | Expr::Choice(_)) => format!("({})*", expr(g, format, names, x)),
+            _ => format!("{}*", expr(g, format, names, x)),
+        },
+    }
+}
+
+// Parts of a sequence or choice need parentheses if they're a choice themselves.
+fn group(format : Format, e : &Expr, s : String) -> String
+{
+    let optional = matches!(e, Expr::Choice(items) if items.iter().any(|x| matches!(x, Expr::Empty)));
+    if format != Format::TreeSitter && matches!(e, Expr::Choice(_)) && !optional { format!("({s})") } else { s }
+}
+
+fn is_comment(e : &Expr) -> bool
+{
+    matches!(e, Expr::Predicate(_) | Expr::Hook(_))
+}
+
+// seq(...) or choice(...). Predicates and hooks are only comments, so they aren't arguments of seq(...), and become blank() when they're all there is.
+fn tree_sitter_call(name : &str, items : &[Expr], parts : Vec<String>) -> String
+{
+    if name == "choice"
+    {
+        let parts = items.iter().zip(parts).map(|(item, part)| if is_comment(item) { format!("blank() {part}") } else { part }).collect::<Vec<_>>();
+        return if parts.len() == 1 { parts[0].clone() } else { format!("choice({})", parts.join(", ")) };
+    }
+    let real = items.iter().filter(|x| !is_comment(x)).count();
+    if real == 0 { return format!("blank() {}", parts.join(" ")); }
+    // commas go between real arguments, with comments sticking to the argument after them (or the last one)
+    let mut out = String::new();
+    let mut seen = 0;
+    let mut comma = false;
+    for (item, part) in items.iter().zip(parts)
+    {
+        if comma && seen < real { out += ", "; }
+        else if !out.is_empty() { out += " "; }
+        out += &part;
+        comma = !is_comment(item);
+        seen += comma as usize;
+    }
+    if real == 1 { out } else { format!("seq({out})") }
+}
+
+/// Exports the grammar as W3C-style EBNF (the notation from the XML spec).
+///
+/// Each grammar point becomes a rule, with alternations that `$become` their own rule turned back into repetition, and `$become` into another rule as a plain reference. Literals and regexes become token rules at the end. Guards, hooks and other predicates become comments. Directives that only change the shape of the AST are left out, as are comments and whitespace. Rule names that aren't plain identifiers (template instances, imported rules) are changed to be.
+pub fn to_w3c_ebnf(g : &Grammar) -> String
+{
+    let rules = (0..g.points.len()).collect::<Vec<_>>();
+    let names = names(g, Format::W3c, &rules);
+    let mut out = "/* Exported from a pred_recdec grammar. Guards, hooks and other predicates are kept as comments. */\n\n".to_string();
+    for &id in &rules
+    {
+        out += &format!("{} ::= {}\n", names.rules[id], expr(g, Format::W3c, &names, &rule_expr(g, id)));
+    }
+    out += "\n/* Tokens */\n";
+    for (name, def) in &names.tokens
+    {
+        out += &format!("{name} ::= {def}\n");
+    }
+    if names.any
+    {
+        out += &format!("ANY_TOKEN ::= {}\n", names.tokens.iter().map(|x| x.0.as_str()).collect::<Vec<_>>().join(" | "));
+    }
+    out
+}
+
+/// Exports the grammar as an ANTLR4 combined grammar (a `.g4` file) called `name`, in the same way as [`to_w3c_ebnf`].
+///
+/// Literals and regexes become lexer rules, with literals first, so that keywords win over identifiers. Whitespace and the grammar's comments are skipped. Regexes that can't be written in ANTLR's lexer syntax (e.g. ones with word boundaries) become `TODO` comments. `@eof` becomes `EOF`.
+///
+/// This is a starting point, not a drop-in replacement: ANTLR chooses between alternations with its own lookahead instead of the grammar's guards, and its lexer doesn't know which tokens the parser expects, so e.g. soft keywords need more work.
+pub fn to_antlr(g : &Grammar, name : &str) -> String
+{
+    let rules = (0..g.points.len()).collect::<Vec<_>>();
+    let names = names(g, Format::Antlr, &rules);
+    let mut out = format!("// Exported from a pred_recdec grammar. Guards, hooks and other predicates are kept as comments.\ngrammar {};\n\n", identifier(name));
+    for &id in &rules
+    {
+        out += &format!("{}\n    : {}\n    ;\n\n", names.rules[id], expr(g, Format::Antlr, &names, &rule_expr(g, id)));
+    }
+    for (name, def) in &names.tokens
+    {
+        out += &format!("{name} : {def} ;\n");
+    }
+    for (i, start) in g.comments.iter().enumerate()
+    {
+        out += &format!("COMMENT_{} : {} ~[\\r\\n]* -> skip ;\n", i + 1, string(Format::Antlr, start));
+    }
+    for (i, (l, r)) in g.comment_pairs.iter().enumerate()
+    {
+        out += &format!("BLOCK_COMMENT_{} : {} .*? {} -> skip ;\n", i + 1, string(Format::Antlr, l), string(Format::Antlr, r));
+    }
+    for (i, (l, r)) in g.comment_pairs_nested.iter().enumerate()
+    {
+        let name = format!("NESTED_COMMENT_{}", i + 1);
+        out += &format!("{name} : {} ({name} | .)*? {} -> skip ;\n", string(Format::Antlr, l), string(Format::Antlr, r));
+    }
+    for r in &g.comment_regexes
+    {
+        out += &format!("// TODO: comment regex r`{}`r\n", r.as_str().strip_prefix("\\A").unwrap_or(r.as_str()));
+    }
+    out += "WS : [ \\t\\r\\n]+ -> skip ;\n";
+    out
+}
+
+/// Exports the grammar as a tree-sitter `grammar.js` skeleton for a language called `name`, starting from the rule `root` (tree-sitter grammars start with their first rule). Returns `None` if `root` isn't a rule.
+///
+/// Rules are converted in the same way as [`to_w3c_ebnf`], except that literals and regexes stay inline, which is how tree-sitter grammars are normally written. Whitespace and the grammar's comments go in `extras`.
+///
+/// This is a starting point: tree-sitter doesn't allow rules other than the first to match nothing, and hooks usually need an external scanner.
+pub fn to_tree_sitter(g : &Grammar, name : &str, root : &str) -> Option<String>
+{
+    let root = *g.by_name.get(root)?;
+    let rules = std::iter::once(root).chain((0..g.points.len()).filter(|x| *x != root)).collect::<Vec<_>>();
+    let names = names(g, Format::TreeSitter, &rules);
+    let mut out = "// Exported from a pred_recdec grammar. Guards, hooks and other predicates are kept as comments.\n".to_string();
+    out += &format!("module.exports = grammar({{\n  name: '{}',\n\n  extras: $ => [\n    /\\s/,\n", identifier(name));
+    for start in &g.comments
+    {
+        out += &format!("    token(seq({}, /.*/)),\n", string(Format::TreeSitter, start));
+    }
+    for (l, r) in g.comment_pairs.iter().chain(g.comment_pairs_nested.iter())
+    {
+        let (l, r) = (escape_chars(l, "\\^$.|?*+()[]{}/"), escape_chars(r, "\\^$.|?*+()[]{}/"));
+        out += &format!("    token(/{l}[^]*?{r}/),\n");
+    }
+    out += "  ],\n\n  rules: {\n";
+    let mut any = false;
+    for &id in &rules
+    {
+        let e = rule_expr(g, id);
+        walk(&e, &mut |x| any |= matches!(x, Expr::Any));
+        let text = expr(g, Format::TreeSitter, &names, &e);
+        let text = if is_comment(&e) { format!("blank() {text}") } else { text };
+        out += &format!("    {}: $ => {text},\n\n", names.rules[id]);
+    }
+    if any
+    {
+        out += "    // TODO: any single token\n    any_token: $ => /\\S+/,\n";
+    }
+    out += "  }\n});\n";
+    Some(out)
+}
@@ -0,0 +1,963 @@
+// Grammar import from other formats
+
+use crate::bnf::*;
+use crate::analysis::{infer_peeks, PeekConflict};
+
+type HashMap<K, V> = std::collections::HashMap::<K, V, crate::HashBuilder>;
+
+// A rule's definition, as read from ABNF or EBNF.
+#[derive(Clone, Debug)]
+enum Ast {
+    // text, and whether it's case-insensitive
+    Str(String, bool),
+    // character ranges, and whether they're negated
+    Chars(Vec<(char, char)>, bool),
+    Rule(String),
+    Seq(Vec<Ast>),
+    Choice(Vec<Ast>),
+    // item, least and most times (None = unbounded)
+    Repeat(Box<Ast>, u32, Option<u32>),
+    // EBNF `a - b`
+    Except(Box<Ast>, Box<Ast>),
+    // ABNF `<prose>`
+    Prose(String),
+    Empty,
+}
+
+// Characters that the tokenizer skips between tokens.
+const WHITESPACE : [char; 4] = [' ', '\t', '\r', '\n'];
+
+// Most repetitions to write out for bounded repetition, like ABNF `1*4x`; more than this is treated as unbounded.
+const MAX_REPEAT : u32 = 16;
+
+/// A grammar converted from another format by [`from_w3c_ebnf`] or [`from_abnf`].
+pub struct Imported {
+    /// The grammar, as BNF text (see [`Grammar::to_bnf`]).
+    pub bnf : String,
+    /// The grammar itself.
+    pub grammar : Grammar,
+    /// Alternations that need a guard written by hand, because no `@peek` or `@peekr` could be inferred for them (see [`infer_peeks`]). Until they get one, the alternations after them are never chosen.
+    pub conflicts : Vec<PeekConflict>,
+    /// Parts of the source that couldn't be converted exactly, like ABNF prose values.
+    pub notes : Vec<String>,
+}
+
+impl Imported {
+    /// Human-readable list of the conflicts and notes. Format is not guaranteed and may change arbitrarily.
+    pub fn report(&self) -> String
+    {
+        let mut s = String::new();
+        s += &format!("Alternations that need a guard: {}\n", self.conflicts.len());
+        for c in &self.conflicts
+        {
+            s += &format!("  {}\n", c.describe(&self.grammar));
+        }
+        s += &format!("Notes: {}\n", self.notes.len());
+        for note in &self.notes
+        {
+            s += &format!("  {note}\n");
+        }
+        s
+    }
+}
+
+// Character-by-character reader, keeping track of the line and column for errors.
+struct Reader {
+    chars : Vec<char>,
+    pos : usize,
+    line : usize,
+    column : usize,
+}
+
+impl Reader {
+    fn new(text : &str) -> Self { Reader { chars : text.chars().collect(), pos : 0, line : 1, column : 1 } }
+    fn peek(&self) -> Option<char> { self.chars.get(self.pos).copied() }
+    fn peek_at(&self, n : usize) -> Option<char> { self.chars.get(self.pos + n).copied() }
+    fn starts_with(&self, s : &str) -> bool { s.chars().enumerate().all(|(i, c)| self.peek_at(i) == Some(c)) }
+    fn next(&mut self) -> Option<char>
+    {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' { self.line += 1; self.column = 1; } else { self.column += 1; }
+        Some(c)
+    }
+    fn skip(&mut self, n : usize) { for _ in 0..n { self.next(); } }
+    fn take_while(&mut self, f : impl Fn(char) -> bool) -> String
+    {
+        let mut ret = String::new();
+        while let Some(c) = self.peek() && f(c)
+        {
+            ret.push(c);
+            self.next();
+        }
+        ret
+    }
+    fn error(&self, msg : String) -> GrammarError
+    {
+        let mut e = GrammarError::new(GrammarErrorKind::Syntax, msg);
+        e.snippet = self.chars[self.pos..].iter().take_while(|c| !c.is_whitespace()).take(20).collect();
+        e.line = self.line;
+        e.column = self.column;
+        e
+    }
+}
+
+// Reads a number in the given base.
+fn number(r : &mut Reader, base : u32) -> Result<u32, GrammarError>
+{
+    let digits = r.take_while(|c| c.is_digit(base));
+    u32::from_str_radix(&digits, base).map_err(|_| r.error(format!("Expected a number")))
+}
+
+fn char_from(r : &Reader, n : u32) -> Result<char, GrammarError>
+{
+    char::from_u32(n).ok_or_else(|| r.error(format!("Not a character: {n:#x}")))
+}
+
+// ---- W3C EBNF ----
+
+// Skips whitespace, /* comments */, and [ WFC: ... ] and [ VC: ... ] annotations.
+fn ebnf_skip(r : &mut Reader)
+{
+    loop
+    {
+        r.take_while(char::is_whitespace);
+        if r.starts_with("/*")
+        {
+            while r.peek().is_some() && !r.starts_with("*/") { r.next(); }
+            r.skip(2);
+        }
+        else if r.peek() == Some('[') && ["WFC:", "VC:", "wfc:", "vc:"].iter().any(|x| r.chars[r.pos + 1..].iter().skip_while(|c| c.is_whitespace()).collect::<String>().starts_with(x))
+        {
+            while r.peek().is_some_and(|c| c != ']') { r.next(); }
+            r.next();
+        }
+        else { return; }
+    }
+}
+
+fn is_name_char(c : char) -> bool { c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':') }
+
+// Is the reader at the start of a rule, i.e. `[1] name ::=` or `name ::=`?
+fn ebnf_at_rule(r : &Reader) -> bool
+{
+    let rest = &r.chars[r.pos..];
+    let mut i = 0;
+    if rest.first() == Some(&'[')
+    {
+        i = 1;
+        while rest.get(i).is_some_and(|c| c.is_ascii_alphanumeric()) { i += 1; }
+        if rest.get(i) != Some(&']') { return false; }
+        i += 1;
+        while rest.get(i).is_some_and(|c| c.is_whitespace()) { i += 1; }
+    }
+    let start = i;
+    while rest.get(i).is_some_and(|c| is_name_char(*c)) { i += 1; }
+    if i == start { return false; }
+    while rest.get(i).is_some_and(|c| c.is_whitespace()) { i += 1; }
+    rest[i..].starts_with(&[':', ':', '='])
+}
+
+// One character of a [class] or #xN.
+fn ebnf_class_char(r : &mut Reader) -> Result<char, GrammarError>
+{
+    if r.starts_with("#x")
+    {
+        r.skip(2);
+        let n = number(r, 16)?;
+        return char_from(r, n);
+    }
+    r.next().ok_or_else(|| r.error(format!("Unterminated character class")))
+}
+
+fn ebnf_primary(r : &mut Reader) -> Result<Ast, GrammarError>
+{
+    ebnf_skip(r);
+    match r.peek()
+    {
+        Some('(') =>
+        {
+            r.next();
+            let ret = ebnf_choice(r)?;
+            ebnf_skip(r);
+            if r.next() != Some(')') { return Err(r.error(format!("Expected )"))); }
+            Ok(ret)
+        }
+        Some(q @ ('"' | '\'')) =>
+        {
+            r.next();
+            let text = r.take_while(|c| c != q);
+            if r.next() != Some(q) { return Err(r.error(format!("Unterminated string"))); }
+            Ok(if text.is_empty() { Ast::Empty } else { Ast::Str(text, false) })
+        }
+        Some('#') =>
+        {
+            let c = ebnf_class_char(r)?;
+            Ok(Ast::Str(c.to_string(), false))
+        }
+        Some('[') =>
+        {
+            r.next();
+            let negated = r.peek() == Some('^');
+            if negated { r.next(); }
+            let mut ranges = vec!();
+            while r.peek() != Some(']') || ranges.is_empty() && r.peek_at(1) == Some(']')
+            {
+                let a = ebnf_class_char(r)?;
+                let b = if r.peek() == Some('-') && r.peek_at(1) != Some(']') { r.next(); ebnf_class_char(r)? } else { a };
+                ranges.push((a, b));
+            }
+            r.next();
+            Ok(Ast::Chars(ranges, negated))
+        }
+        Some(c) if is_name_char(c) => Ok(Ast::Rule(r.take_while(is_name_char))),
+        _ => Err(r.error(format!("Expected a name, string, character class, or group"))),
+    }
+}
+
+// primary, then any number of ?, * and +
+fn ebnf_postfix(r : &mut Reader) -> Result<Ast, GrammarError>
+{
+    let mut ret = ebnf_primary(r)?;
+    loop
+    {
+        let (min, max) = match r.peek()
+        {
+            Some('?') => (0, Some(1)),
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            _ => return Ok(ret),
+        };
+        r.next();
+        ret = Ast::Repeat(Box::new(ret), min, max);
+    }
+}
+
+// item, optionally followed by `- item`
+fn ebnf_except(r : &mut Reader) -> Result<Ast, GrammarError>
+{
+    let ret = ebnf_postfix(r)?;
+    ebnf_skip(r);
+    if r.peek() == Some('-')
+    {
+        r.next();
+        return Ok(Ast::Except(Box::new(ret), Box::new(ebnf_postfix(r)?)));
+    }
+    Ok(ret)
+}
+
+fn ebnf_choice(r : &mut Reader) -> Result<Ast, GrammarError>
+{
+    let mut alts = vec!();
+    loop
+    {
+        let mut items = vec!();
+        loop
+        {
+            ebnf_skip(r);
+            if r.peek().is_none_or(|c| c == '|' || c == ')') || ebnf_at_rule(r) { break; }
+            items.push(ebnf_except(r)?);
+        }
+        alts.push(match items.len() { 0 => Ast::Empty, 1 => items.pop().unwrap(), _ => Ast::Seq(items) });
+        if r.peek() != Some('|') { break; }
+        r.next();
+    }
+    Ok(if alts.len() == 1 { alts.pop().unwrap() } else { Ast::Choice(alts) })
+}
+
+fn read_w3c_ebnf(text : &str) -> Result<Vec<(String, Ast)>, GrammarError>
+{
+    let mut r = Reader::new(text);
+    let mut rules = vec!();
+    loop
+    {
+        ebnf_skip(&mut r);
+        if r.peek().is_none() { return Ok(rules); }
+        if !ebnf_at_rule(&r) { return Err(r.error(format!("Expected a rule, like name ::= ..."))); }
+        // rule number, like [1]
+        if r.peek() == Some('[') { r.take_while(|c| c != ']'); r.next(); ebnf_skip(&mut r); }
+        let name = r.take_while(is_name_char);
+        r.take_while(char::is_whitespace);
+        r.skip(3);
+        rules.push((name, ebnf_choice(&mut r)?));
+    }
+}
+
+// ---- ABNF ----
+
+// The core rules from RFC 5234 appendix B.1, which ABNF grammars use without defining them.
+const ABNF_CORE : &str = "
+ALPHA = %x41-5A / %x61-7A
+BIT = \"0\" / \"1\"
+CHAR = %x01-7F
+CR = %x0D
+CRLF = CR LF
+CTL = %x00-1F / %x7F
+DIGIT = %x30-39
+DQUOTE = %x22
+HEXDIG = DIGIT / \"A\" / \"B\" / \"C\" / \"D\" / \"E\" / \"F\"
+HTAB = %x09
+LF = %x0A
+LWSP = *(WSP / CRLF WSP)
+OCTET = %x00-FF
+SP = %x20
+VCHAR = %x21-7E
+WSP = SP / HTAB
+";
+
+// Skips whitespace and ; comments, including line breaks if the next line continues the rule (starts with whitespace).
+fn abnf_skip(r : &mut Reader)
+{
+    loop
+    {
+        r.take_while(|c| c == ' ' || c == '\t');
+        if r.peek() == Some(';') { r.take_while(|c| c != '\n'); }
+        if r.starts_with("\r\n") && r.peek_at(2).is_some_and(|c| c == ' ' || c == '\t') { r.skip(2); }
+        else if r.peek() == Some('\n') && r.peek_at(1).is_some_and(|c| c == ' ' || c == '\t') { r.next(); }
+        else { return; }
+    }
+}
+
+fn abnf_element(r : &mut Reader) -> Result<Ast, GrammarError>
+{
+    match r.peek()
+    {
+        Some('(' | '[') =>
+        {
+            let close = if r.next() == Some('(') { ')' } else { ']' };
+            let ret = abnf_choice(r)?;
+            abnf_skip(r);
+            if r.next() != Some(close) { return Err(r.error(format!("Expected {close}"))); }
+            Ok(if close == ']' { Ast::Repeat(Box::new(ret), 0, Some(1)) } else { ret })
+        }
+        Some('"') =>
+        {
+            r.next();
+            let text = r.take_while(|c| c != '"' && c != '\n');
+            if r.next() != Some('"') { return Err(r.error(format!("Unterminated string"))); }
+            Ok(if text.is_empty() { Ast::Empty } else { Ast::Str(text, true) })
+        }
+        Some('%') =>
+        {
+            r.next();
+            let base = match r.next().map(|c| c.to_ascii_lowercase())
+            {
+                // RFC 7405 case-sensitive and case-insensitive strings
+                Some(kind @ ('s' | 'i')) =>
+                {
+                    let Ast::Str(text, _) = abnf_element(r)? else { return Err(r.error(format!("Expected a string after %{kind}"))) };
+                    return Ok(Ast::Str(text, kind == 'i'));
+                }
+                Some('x') => 16,
+                Some('d') => 10,
+                Some('b') => 2,
+                _ => return Err(r.error(format!("Expected %x, %d, %b, %s or %i"))),
+            };
+            let first = number(r, base)?;
+            let first = char_from(r, first)?;
+            if r.peek() == Some('-')
+            {
+                r.next();
+                let last = number(r, base)?;
+                return Ok(Ast::Chars(vec!((first, char_from(r, last)?)), false));
+            }
+            let mut text = first.to_string();
+            while r.peek() == Some('.')
+            {
+                r.next();
+                let n = number(r, base)?;
+                text.push(char_from(r, n)?);
+            }
+            Ok(Ast::Str(text, false))
+        }
+        Some('<') =>
+        {
+            r.next();
+            let text = r.take_while(|c| c != '>');
+            r.next();
+            Ok(Ast::Prose(text))
+        }
+        Some(c) if c.is_ascii_alphabetic() => Ok(Ast::Rule(r.take_while(|c| c.is_ascii_alphanumeric() || c == '-'))),
+        _ => Err(r.error(format!("Expected a rule name, string, number, group or option"))),
+    }
+}
+
+// [min] * [max] element, or n element
+fn abnf_repetition(r : &mut Reader) -> Result<Ast, GrammarError>
+{
+    let min = r.take_while(|c| c.is_ascii_digit());
+    let (min, max) = if r.peek() == Some('*')
+    {
+        r.next();
+        let max = r.take_while(|c| c.is_ascii_digit());
+        (min.parse().unwrap_or(0), max.parse().ok())
+    }
+    else if !min.is_empty()
+    {
+        let n = min.parse().map_err(|_| r.error(format!("Repetition count too big")))?;
+        (n, Some(n))
+    }
+    else
+    {
+        return abnf_element(r);
+    };
+    Ok(Ast::Repeat(Box::new(abnf_element(r)?), min, max))
+}
+
+fn abnf_choice(r : &mut Reader) -> Result<Ast, GrammarError>
+{
+    let mut alts = vec!();
+    loop
+    {
+        let mut items = vec!();
+        loop
+        {
+            abnf_skip(r);
+            if r.peek().is_none_or(|c| matches!(c, '/' | ')' | ']' | '\r' | '\n')) { break; }
+            items.push(abnf_repetition(r)?);
+        }
+        alts.push(match items.len() { 0 => Ast::Empty, 1 => items.pop().unwrap(), _ => Ast::Seq(items) });
+        if r.peek() != Some('/') { break; }
+        r.next();
+    }
+    Ok(if alts.len() == 1 { alts.pop().unwrap() } else { Ast::Choice(alts) })
+}
+
+// Rule names in ABNF are case-insensitive, so every reference is changed to how the rule's name is first written.
+fn abnf_names(ast : &mut Ast, names : &HashMap<String, String>)
+{
+    match ast
+    {
+        Ast::Rule(name) => if let Some(n) = names.get(&name.to_lowercase()) { *name = n.clone(); },
+        Ast::Seq(items) | Ast::Choice(items) => for x in items { abnf_names(x, names); },
+        Ast::Repeat(x, _, _) => abnf_names(x, names),
+        Ast::Except(a, b) => { abnf_names(a, names); abnf_names(b, names); }
+        _ => {}
+    }
+}
+
+// Also returns how many of the rules were written in the text, rather than being core rules.
+fn read_abnf(text : &str) -> Result<(Vec<(String, Ast)>, usize), GrammarError>
+{
+    let mut rules : Vec<(String, Ast)> = vec!();
+    let mut names = HashMap::default();
+    let mut r = Reader::new(text);
+    loop
+    {
+        // skip blank lines and comments
+        r.take_while(char::is_whitespace);
+        if r.peek() == Some(';') { r.take_while(|c| c != '\n'); continue; }
+        if r.peek().is_none() { break; }
+        let (line, column) = (r.line, r.column);
+        let name = r.take_while(|c| c.is_ascii_alphanumeric() || c == '-');
+        if name.is_empty() || !name.starts_with(|c : char| c.is_ascii_alphabetic()) { return Err(r.error(format!("Expected a rule name"))); }
+        abnf_skip(&mut r);
+        if r.next() != Some('=') { return Err(r.error(format!("Expected = or =/ after {name}"))); }
+        let incremental = r.peek() == Some('/');
+        if incremental { r.next(); }
+        let ast = abnf_choice(&mut r)?;
+        abnf_skip(&mut r);
+        if r.peek().is_some_and(|c| !c.is_whitespace()) { return Err(r.error(format!("Unexpected {}", r.peek().unwrap()))); }
+        
+        let key = name.to_lowercase();
+        match (names.get(&key), incremental)
+        {
+            (None, false) =>
+            {
+                names.insert(key, name.clone());
+                rules.push((name, ast));
+            }
+            // =/ adds alternatives to an existing rule
+            (Some(existing), true) =>
+            {
+                let rule = &mut rules.iter_mut().find(|x| &x.0 == existing).unwrap().1;
+                let mut alts = match std::mem::replace(rule, Ast::Empty) { Ast::Choice(alts) => alts, x => vec!(x) };
+                match ast { Ast::Choice(more) => alts.extend(more), x => alts.push(x) }
+                *rule = Ast::Choice(alts);
+            }
+            (Some(_), false) | (None, true) =>
+            {
+                let mut e = GrammarError::new(GrammarErrorKind::DuplicateRule, match incremental
+                {
+                    true => format!("{name} =/ ... adds to a rule that isn't defined"),
+                    false => format!("Duplicate rule {name}; use =/ to add alternatives to a rule"),
+                }).in_rule(&name).at(&name);
+                (e.line, e.column) = (line, column);
+                return Err(e);
+            }
+        }
+    }
+    // the core rules, if they're used without being defined
+    let written = rules.len();
+    let core = read_core_rules();
+    loop
+    {
+        let mut used = vec!();
+        for (_, ast) in &rules { rule_refs(ast, &mut used); }
+        let missing = core.iter().filter(|(name, _)| used.iter().any(|u| u.eq_ignore_ascii_case(name)) && !names.contains_key(&name.to_lowercase())).cloned().collect::<Vec<_>>();
+        if missing.is_empty() { break; }
+        for (name, ast) in missing
+        {
+            names.insert(name.to_lowercase(), name.clone());
+            rules.push((name, ast));
+        }
+    }
+    for (_, ast) in &mut rules { abnf_names(ast, &names); }
+    Ok((rules, written))
+}
+
+fn read_core_rules() -> Vec<(String, Ast)>
+{
+    let mut r = Reader::new(ABNF_CORE);
+    let mut rules = vec!();
+    loop
+    {
+        r.take_while(char::is_whitespace);
+        if r.peek().is_none() { return rules; }
+        let name = r.take_while(|c| c.is_ascii_alphanumeric());
+        r.skip(2);
+        rules.push((name, abnf_choice(&mut r).unwrap()));
+    }
+}
+
+// ---- Conversion ----
+
+fn rule_refs(ast : &Ast, out : &mut Vec<String>)
+{
+    match ast
+    {
+        Ast::Rule(name) => out.push(name.clone()),
+        Ast::Seq(items) | Ast::Choice(items) => for x in items { rule_refs(x, out); },
+        Ast::Repeat(x, _, _) => rule_refs(x, out),
+        Ast::Except(a, b) => { rule_refs(a, out); rule_refs(b, out); }
+        _ => {}
+    }
+}
+
+// What kind of rule each rule is.
+struct Kinds {
+    // only ever matches whitespace, which the tokenizer skips
+    whitespace : HashMap<String, bool>,
+    // matched as a single token with a regex
+    token : HashMap<String, bool>,
+    // can match nothing
+    empty : HashMap<String, bool>,
+}
+
+fn is_whitespace(ast : &Ast, ws : &HashMap<String, bool>) -> bool
+{
+    match ast
+    {
+        Ast::Str(s, _) => s.chars().all(|c| WHITESPACE.contains(&c)),
+        Ast::Chars(ranges, false) => ranges.iter().all(|(a, b)| (*a..=*b).all(|c| WHITESPACE.contains(&c))),
+        Ast::Chars(_, true) | Ast::Prose(_) => false,
+        Ast::Rule(name) => ws[name],
+        Ast::Seq(items) | Ast::Choice(items) => items.iter().all(|x| is_whitespace(x, ws)),
+        Ast::Repeat(x, _, _) | Ast::Except(x, _) => is_whitespace(x, ws),
+        Ast::Empty => true,
+    }
+}
+
+fn can_be_empty(ast : &Ast, empty : &HashMap<String, bool>) -> bool
+{
+    match ast
+    {
+        Ast::Str(..) | Ast::Chars(..) => false,
+        Ast::Rule(name) => empty[name],
+        Ast::Seq(items) => items.iter().all(|x| can_be_empty(x, empty)),
+        Ast::Choice(items) => items.iter().any(|x| can_be_empty(x, empty)),
+        Ast::Repeat(x, min, _) => *min == 0 || can_be_empty(x, empty),
+        Ast::Except(x, _) => can_be_empty(x, empty),
+        Ast::Prose(_) | Ast::Empty => true,
+    }
+}
+
+// Does a sequence in the rule have whitespace as one of its items (so the rule is made of several tokens)?
+fn has_spaced_seq(ast : &Ast, ws : &HashMap<String, bool>) -> bool
+{
+    match ast
+    {
+        Ast::Seq(items) => items.iter().any(|x| !matches!(x, Ast::Empty) && is_whitespace(x, ws)) || items.iter().any(|x| has_spaced_seq(x, ws)),
+        Ast::Choice(items) => items.iter().any(|x| has_spaced_seq(x, ws)),
+        Ast::Repeat(x, _, _) | Ast::Except(x, _) => has_spaced_seq(x, ws),
+        _ => false,
+    }
+}
+
+fn has_prose(ast : &Ast) -> bool
+{
+    match ast
+    {
+        Ast::Prose(_) => true,
+        Ast::Seq(items) | Ast::Choice(items) => items.iter().any(has_prose),
+        Ast::Repeat(x, _, _) | Ast::Except(x, _) => has_prose(x),
+        _ => false,
+    }
+}
+
+fn kinds(rules : &[(String, Ast)], tokens : Option<&[&str]>) -> Result<Kinds, GrammarError>
+{
+    let defs = rules.iter().map(|(n, a)| (n.clone(), a)).collect::<HashMap<_, _>>();
+    let refs = rules.iter().map(|(n, a)| { let mut v = vec!(); rule_refs(a, &mut v); (n.clone(), v) }).collect::<HashMap<_, _>>();
+    // every rule that's used has to be defined
+    for (name, used) in &refs
+    {
+        if let Some(u) = used.iter().find(|u| !defs.contains_key(*u))
+        {
+            return Err(GrammarError::new(GrammarErrorKind::UnknownRule, format!("Not a defined grammar rule: '{u}' (context: '{name}')")).in_rule(name).at(u));
+        }
+    }
+    // rules that can reach themselves
+    let recursive = |start : &String|
+    {
+        let mut seen = vec!(start);
+        let mut stack = refs[start].iter().collect::<Vec<_>>();
+        while let Some(x) = stack.pop()
+        {
+            if x == start { return true; }
+            if seen.contains(&x) { continue; }
+            seen.push(x);
+            stack.extend(refs[x].iter());
+        }
+        false
+    };
+    
+    // these only ever get more false (or more true, for `empty`), so go until nothing changes
+    let mut whitespace = rules.iter().map(|(n, _)| (n.clone(), true)).collect::<HashMap<_, _>>();
+    let mut empty = rules.iter().map(|(n, _)| (n.clone(), false)).collect::<HashMap<_, _>>();
+    let mut changed = true;
+    while changed
+    {
+        changed = false;
+        for (name, ast) in rules
+        {
+            let ws = is_whitespace(ast, &whitespace);
+            let em = can_be_empty(ast, &empty);
+            changed |= ws != whitespace[name] || em != empty[name];
+            whitespace.insert(name.clone(), ws);
+            empty.insert(name.clone(), em);
+        }
+    }
+    let mut token = rules.iter().map(|(n, a)| (n.clone(), !recursive(n) && !has_prose(a))).collect::<HashMap<_, _>>();
+    let mut changed = true;
+    while changed
+    {
+        changed = false;
+        for (name, ast) in rules
+        {
+            // when guessing, whitespace between parts means that they're separate tokens
+            let tok = token[name] && (tokens.is_some() || !has_spaced_seq(ast, &whitespace)) && refs[name].iter().all(|x| token[x] || whitespace[x]);
+            changed |= tok != token[name];
+            token.insert(name.clone(), tok);
+        }
+    }
+    if let Some(tokens) = tokens
+    {
+        if let Some(name) = tokens.iter().find(|t| defs.contains_key(**t) && !token[**t])
+        {
+            return Err(GrammarError::new(GrammarErrorKind::Other, format!("{name} can't be a token: it's recursive, or uses a rule that can't be part of a token")).in_rule(name));
+        }
+        // besides the given ones, only rules that are only used by tokens are tokens (which are written into their regexes)
+        let could_be = token;
+        token = rules.iter().map(|(n, _)| (n.clone(), tokens.contains(&n.as_str()))).collect();
+        let mut changed = true;
+        while changed
+        {
+            changed = false;
+            for (name, _) in rules
+            {
+                let users = rules.iter().filter(|(n, _)| refs[n].contains(name)).collect::<Vec<_>>();
+                if !token[name] && could_be[name] && !users.is_empty() && users.iter().all(|(n, _)| token[n])
+                {
+                    token.insert(name.clone(), true);
+                    changed = true;
+                }
+            }
+        }
+    }
+    Ok(Kinds { whitespace, token, empty })
+}
+
+// A character in a regex character class.
+fn class_char(c : char) -> String
+{
+    match c
+    {
+        '[' | ']' | '\\' | '^' | '-' | '&' | '~' => format!("\\{c}"),
+        // a backtick could end the regex literal
+        c if c.is_ascii_graphic() && c != '`' => c.to_string(),
+        c => format!("\\x{{{:X}}}", c as u32),
+    }
+}
+
+// The inside of a character class for the AST, if it only ever matches one character.
+fn class_items(ast : &Ast, defs : &HashMap<&str, &Ast>) -> Option<String>
+{
+    let range = |a : char, b : char| if a == b { class_char(a) } else { format!("{}-{}", class_char(a), class_char(b)) };
+    match ast
+    {
+        Ast::Chars(ranges, false) => Some(ranges.iter().map(|(a, b)| range(*a, *b)).collect()),
+        Ast::Chars(ranges, true) => Some(format!("[^{}]", ranges.iter().map(|(a, b)| range(*a, *b)).collect::<String>())),
+        Ast::Str(s, ci) if s.chars().count() == 1 =>
+        {
+            let c = s.chars().next()?;
+            let (lower, upper) = (c.to_ascii_lowercase(), c.to_ascii_uppercase());
+            Some(if *ci && lower != upper { format!("{}{}", class_char(upper), class_char(lower)) } else { class_char(c) })
+        }
+        Ast::Rule(name) => class_items(defs[name.as_str()], defs),
+        Ast::Choice(items) => items.iter().map(|x| class_items(x, defs)).collect(),
+        Ast::Except(a, b) => Some(format!("[{}--{}]", class_items(a, defs)?, class_items(b, defs)?)),
+        _ => None,
+    }
+}
+
+// A bracketed character class for the AST, if it only ever matches one character.
+fn as_class(ast : &Ast, defs : &HashMap<&str, &Ast>) -> Option<String>
+{
+    match class_items(ast, defs)?
+    {
+        // already a class
+        items if items.starts_with('[') && matches!(ast, Ast::Chars(_, true) | Ast::Except(..)) => Some(items),
+        items => Some(format!("[{items}]")),
+    }
+}
+
+struct Converter<'a> {
+    defs : HashMap<&'a str, &'a Ast>,
+    kinds : Kinds,
+    notes : Vec<String>,
+    // rules to output, in order: (name, alternations of terms)
+    out : Vec<(String, Vec<Vec<String>>)>,
+    // how many groups each rule has made, for naming them
+    groups : HashMap<String, usize>,
+}
+
+impl<'a> Converter<'a> {
+    // Regex for a token rule's definition.
+    fn regex(&mut self, rule : &str, ast : &Ast) -> String
+    {
+        if !matches!(ast, Ast::Str(..)) && let Some(class) = as_class(ast, &self.defs) { return class; }
+        match ast
+        {
+            Ast::Str(s, true) if s.chars().any(|c| c.is_alphabetic()) => format!("(?i:{})", regex::escape(s)),
+            Ast::Str(s, _) => regex::escape(s),
+            Ast::Rule(name) =>
+            {
+                let def = self.defs[name.as_str()];
+                match def
+                {
+                    Ast::Choice(_) => format!("(?:{})", self.regex(rule, def)),
+                    _ => self.regex(rule, def),
+                }
+            }
+            Ast::Seq(items) => items.iter().map(|x| match x
+            {
+                Ast::Choice(_) => format!("(?:{})", self.regex(rule, x)),
+                _ => self.regex(rule, x),
+            }).collect(),
+            Ast::Choice(items) => items.iter().map(|x| self.regex(rule, x)).collect::<Vec<_>>().join("|"),
+            Ast::Repeat(x, min, max) =>
+            {
+                let sub = self.regex(rule, x);
+                // classes and single characters don't need a group
+                let single = as_class(x, &self.defs).is_some() || matches!(&**x, Ast::Str(s, false) if s.chars().count() == 1);
+                let sub = if single { sub } else { format!("(?:{sub})") };
+                match (min, max)
+                {
+                    (0, Some(1)) => format!("{sub}?"),
+                    (0, None) => format!("{sub}*"),
+                    (1, None) => format!("{sub}+"),
+                    (n, None) => format!("{sub}{{{n},}}"),
+                    (n, Some(m)) if n == m => format!("{sub}{{{n}}}"),
+                    (n, Some(m)) => format!("{sub}{{{n},{m}}}"),
+                }
+            }
+            Ast::Except(a, _) =>
+            {
+                self.notes.push(format!("{rule}: the exception in `a - b` can't be written as a regex, so it was left out"));
+                self.regex(rule, a)
+            }
+            Ast::Empty | Ast::Chars(..) | Ast::Prose(_) => String::new(),
+        }
+    }
+    
+    fn group_name(&mut self, rule : &str, kind : &str) -> String
+    {
+        let n = self.groups.entry(rule.to_string()).or_insert(0);
+        *n += 1;
+        format!("{rule}__{kind}{n}")
+    }
+    
+    // Alternations of terms for a (part of a) non-token rule.
+    fn alts(&mut self, rule : &str, ast : &Ast) -> Vec<Vec<String>>
+    {
+        match ast
+        {
+            Ast::Choice(items) => items.iter().flat_map(|x| self.alts(rule, x)).collect(),
+            _ => vec!(self.terms(rule, ast, true)),
+        }
+    }
+    
+    // Adds a generated rule, and returns the terms that use it.
+    fn group(&mut self, name : String, alts : Vec<Vec<String>>, last : bool) -> Vec<String>
+    {
+        self.out.push((name.clone(), alts));
+        if last { vec!("$become".to_string(), name) } else { vec!(name, "$hoist".to_string()) }
+    }
+    
+    // Terms for a (part of a) non-token rule. `last` is whether nothing comes after it in its alternation.
+    fn terms(&mut self, rule : &str, ast : &Ast, last : bool) -> Vec<String>
+    {
+        match ast
+        {
+            _ if !matches!(ast, Ast::Empty | Ast::Prose(_)) && is_whitespace(ast, &self.kinds.whitespace) => vec!(),
+            Ast::Empty => vec!(),
+            Ast::Str(s, true) if s.chars().any(|c| c.is_alphabetic()) => vec!(format!("r`(?i:{})`r", regex::escape(s))),
+            Ast::Str(s, _) => vec!(bnf_quote(s)),
+            Ast::Chars(..) => vec!(format!("r`{}`r", self.regex(rule, ast))),
+            Ast::Rule(name) if self.kinds.token[name] && self.kinds.empty[name] =>
+            {
+                // tokens can't be empty, so the rule is optional instead
+                let opt = self.group_name(rule, "opt");
+                self.group(opt, vec!(vec!(name.clone()), vec!()), last)
+            }
+            Ast::Rule(name) => vec!(name.clone()),
+            Ast::Prose(text) =>
+            {
+                let hook = text.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect::<String>();
+                self.notes.push(format!("{rule}: the prose value <{text}> became !hook({hook})"));
+                vec!("!hook".to_string(), "(".to_string(), hook, ")".to_string())
+            }
+            Ast::Except(a, _) =>
+            {
+                self.notes.push(format!("{rule}: the exception in `a - b` was left out, since only tokens can have exceptions"));
+                self.terms(rule, a, last)
+            }
+            Ast::Seq(items) =>
+            {
+                let n = items.len();
+                items.iter().enumerate().flat_map(|(i, x)| self.terms(rule, x, last && i + 1 == n)).collect()
+            }
+            Ast::Choice(_) =>
+            {
+                let name = self.group_name(rule, "group");
+                let alts = self.alts(&name, ast);
+                self.group(name, alts, last)
+            }
+            Ast::Repeat(x, min, max) =>
+            {
+                let (min, max) = match max
+                {
+                    Some(max) if *max > MAX_REPEAT =>
+                    {
+                        self.notes.push(format!("{rule}: up to {max} repetitions became any number of them"));
+                        (*min, None)
+                    }
+                    _ => (*min, *max),
+                };
+                let mut ret = vec!();
+                for _ in 0..min { ret.extend(self.terms(rule, x, false)); }
+                match max
+                {
+                    Some(max) =>
+                    {
+                        for i in min..max
+                        {
+                            let name = self.group_name(rule, "opt");
+                            let mut alts = self.alts(&name, x);
+                            alts.push(vec!());
+                            ret.extend(self.group(name, alts, last && i + 1 == max));
+                        }
+                    }
+                    None =>
+                    {
+                        let name = self.group_name(rule, "rep");
+                        let mut alts = self.alts(&name, x);
+                        for alt in &mut alts { alt.extend(["$become".to_string(), name.clone()]); }
+                        alts.push(vec!());
+                        ret.extend(self.group(name, alts, last));
+                    }
+                }
+                ret
+            }
+        }
+    }
+}
+
+// The first `written` rules are the ones written in the grammar, and the rest were added (e.g. ABNF core rules).
+fn convert(rules : Vec<(String, Ast)>, written : usize, tokens : Option<&[&str]>) -> Result<Imported, GrammarError>
+{
+    if rules.is_empty() { return Err(GrammarError::new(GrammarErrorKind::Syntax, format!("No rules"))); }
+    let kinds = kinds(&rules, tokens)?;
+    // token rules only need to be rules of their own if something other than a token uses them, or if nothing does (so they're a start rule)
+    let mut used = vec!(rules[0].0.clone());
+    let mut referenced = vec!();
+    for (name, ast) in &rules
+    {
+        if !kinds.token[name] { rule_refs(ast, &mut used); }
+        let mut refs = vec!();
+        rule_refs(ast, &mut refs);
+        referenced.extend(refs.into_iter().filter(|r| r != name));
+    }
+    used.extend(rules.iter().map(|(name, _)| name).filter(|name| !referenced.contains(name)).cloned());
+    let mut c = Converter { defs : rules.iter().map(|(n, a)| (n.as_str(), a)).collect(), kinds, notes : vec!(), out : vec!(), groups : <_>::default() };
+    let mut folded = vec!();
+    let mut skipped = vec!();
+    for (k, (name, ast)) in rules.iter().enumerate()
+    {
+        if c.kinds.whitespace[name] && name != &rules[0].0
+        {
+            if k < written { skipped.push(name.as_str()); }
+            continue;
+        }
+        if c.kinds.token[name]
+        {
+            if used.contains(name) && !c.kinds.whitespace[name]
+            {
+                let regex = c.regex(name, ast);
+                c.out.push((name.clone(), vec!(vec!(format!("r`{regex}`r")))));
+            }
+            else if k < written { folded.push(name.as_str()); }
+            continue;
+        }
+        let start = c.out.len();
+        let alts = c.alts(name, ast);
+        c.out.insert(start, (name.clone(), alts));
+    }
+    // empty alternations go last, since they can't have a guard
+    for (_, alts) in &mut c.out
+    {
+        if let Some(i) = alts.iter().position(|a| a.is_empty()) && i + 1 != alts.len()
+        {
+            let empty = alts.remove(i);
+            alts.push(empty);
+        }
+    }
+    if !folded.is_empty() { c.notes.push(format!("{}: only used inside tokens, so they're part of those instead of rules of their own", folded.join(", "))); }
+    if !skipped.is_empty() { c.notes.push(format!("{}: only match whitespace, which the tokenizer skips, so they were left out", skipped.join(", "))); }
+    let mut grammar = grammar_convert(&c.out)?;
+    let ids = (0..grammar.points.len()).collect::<Vec<_>>();
+    let conflicts = infer_peeks(&mut grammar, &ids);
+    Ok(Imported { bnf : grammar.to_bnf(), grammar, conflicts, notes : c.notes })
+}
+
+/// Converts a grammar written in W3C-style EBNF (the notation from the XML spec) to a [`Grammar`], adding `@peek` and `@peekr` guards where the tokens that each alternation can start with say which one to take (see [`infer_peeks`]).
+///
+/// EBNF describes text character by character, but grammars here match tokens, so some rules become tokens, each matched with a single regex: the ones named in `tokens`, or if `tokens` is `None`, every rule that isn't recursive, only uses other tokens, and doesn't have whitespace between its parts. Rules that only match whitespace are left out, since the tokenizer skips whitespace. Groups, `?`, `*` and `+` become rules named like the ones made by EBNF sugar (e.g. `rule__rep1`).
+///
+/// Rule numbers (`[1]`), comments, and `[ WFC: ... ]` and `[ VC: ... ]` annotations are ignored. The first rule, and any other rule that nothing uses, can be used as the start rule. Rules that don't become rules of their own, because they're only used inside tokens or only match whitespace, are listed in [`Imported::notes`].
+pub fn from_w3c_ebnf(text : &str, tokens : Option<&[&str]>) -> Result<Imported, GrammarError>
+{
+    let rules = read_w3c_ebnf(text)?;
+    let written = rules.len();
+    convert(rules, written, tokens)
+}
+
+/// Converts a grammar written in ABNF (RFC 5234, with RFC 7405 `%s` and `%i` strings) to a [`Grammar`], in the same way as [`from_w3c_ebnf`].
+///
+/// The core rules (`ALPHA`, `DIGIT`, `CRLF`, etc) are added if they're used but not defined. Strings are case-insensitive unless written with `%s`, so ones with letters become regexes. Prose values (`<...>`) become hooks, since they can't be converted.
+pub fn from_abnf(text : &str, tokens : Option<&[&str]>) -> Result<Imported, GrammarError>
+{
+    let (rules, written) = read_abnf(text)?;
+    convert(rules, written, tokens)
+}
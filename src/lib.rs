@@ -16,6 +16,9 @@
 //!
 //! You want:
 //! - [bnf::bnf_to_grammar]
+//! - [bnf::bnf_to_grammar_with_loader] (optional)
+//! - [builder::GrammarBuilder] (optional)
+//! - [bnf::Grammar::from_bytes] (optional)
 //! - [bnf::Grammar]
 //! - [bnf::tokenize]
 //! - [bnf::Token]
@@ -73,11 +76,25 @@
 //! # */
 //! ```
 //!
+//! Before parsing anything, the parse functions check that the guards and hooks maps have every guard and hook that the grammar uses, and fail right away if any are missing, instead of whenever parsing reaches them. Ones that the grammar never uses are allowed; `ast::unused_guards_and_hooks` lists them. `Grammar::required_guards` and `Grammar::required_hooks` list the names the grammar uses.
+//!
+//! ## Observing the parser
+//!
+//! [ast::parse_with_observer] and [ast::parse_recursive_with_observer] take a [trace::ParseObserver], which is called back with structured events: rule entry and exit, guard results, chosen alternations, `$become`, hook calls, and error recovery, all with token positions. This is meant for building debuggers, coverage tools, and profilers. Observers are generic, so there's no cost when you don't use one.
+//!
+//! The `parse_trace` cargo feature makes [ast::parse] and [ast::parse_recursive] use [trace::TracePrinter], which prints every event to stdout.
+//!
+//! [coverage::Coverage] is an observer that records which grammar points, alternations, and terms get exercised, across any number of parses. [coverage::Coverage::report] lists everything that never was.
+//!
+//! [profile::Profiler] is an observer that measures time and call counts per rule, guard, and hook, plus regex cache misses, and can output folded stacks for flamegraphs.
+//!
+//! [debugger::Debugger] is an interactive step-through debugger with breakpoints on rules and token positions, and inspection of the rule stack, partial children, and `udata`.
+//!
 //! ## BNF Extensions
 //!
 //! Mini glossary: nonterminal = "call of another rule", terminal = "immediate match of a token's contents".
 //!
-//! Common EBNF syntax like `[]` or `()?` is not supported. Don't worry, you can make flat lists just fine.
+//! Common EBNF syntax like `[]`, `()?`, `()*` and `()+`, and separated lists (`$sep_by`), are supported as sugar over plain BNF; see "EBNF sugar" below.
 //! 
 //! Extensions from pure BNF are:
 //! 
@@ -110,16 +127,126 @@
 //!- `$become_as nonterminal` performs a tail call, replacing the current AST node's name with that of the target.
 //!- `$any` matches and includes any one token as a terminal.
 //!- `$pruned` specifies that this particular production doesn't generate AST nodes for bare terminals. This is useful for reducing AST bloat. For example, `@peek(1, ")") "(" ")" $pruned` is a non-empty production but produces zero AST nodes.
+//!- `$infer_peeks`, written in any alternation of a rule, gives every alternation of that rule except the last a `@peek` or `@peekr` for the tokens that it can start with, unless it already starts with a guard. If two alternations can start with the same token, the second token is checked instead, and so on, as long as everything before it is exactly one token. If that doesn't tell them apart, the grammar is rejected with `GrammarErrorKind::PeekConflict`, and the alternation needs a predicate written by hand. Rules made from EBNF groups in the rule get it too.
 //!- `$hoist` deletes the most recent child and moves its children into the current AST node's child list.
 //!- `$hoist_unit` does the same, but only if the child has exactly one child.
 //!- `$drop` deletes the most recent child.
 //!- `$drop_empty` does the same, but only if the child has exactly zero children.
 //!- `$rename nonterminal` renames the current AST node, giving it the same name as `nonterminal` but does NOT invoke a run of parsing `nonterminal` (i.e. it's skipped over).
 //! 
+//! Guards must be the first term of their alternation, `$become` and `$become_as` must be followed by a rule name that ends the alternation, `$rename` must be followed by a rule name, and `$hoist`, `$hoist_unit`, `$drop` and `$drop_empty` must come after something that adds to the AST. Grammars that break these rules are rejected when they're loaded.
+//! 
 //! You'll note that there's no "negative rule-match-check predicate" extension (e.g. no "parse A, but only if it doesn't also parse B"). This is by design. Rule-level negation is way too powerful, and requires an extremely sophisticated parser generator (e.g. packrat) to handle both correctly and cheaply. For any reasonably simple implementation, it would be incompatible with impure hooks. `__RESERVED_WORDS`, described below, is the only exception, because it's easy to define in a sane way.
 //!
 //! For negative token predicates (peeks), you can refactor the grammar slightly, or if it's a particularly complicated peek, write a custom guard. So this isn't a limitation.
 //!
+//! ## EBNF sugar
+//!
+//! Groups are desugared into generated rules before the grammar is built:
+//!
+//! - `( a b | c )` - Inline group. Becomes a rule named `<rule>__group<N>`.
+//! - `[ ... ]` or `( ... )?` - Optional group. Becomes `<rule>__opt<N>`, with an extra empty alternation at the end.
+//! - `( ... )*` - Zero or more. Becomes `<rule>__rep<N>`, where every alternation ends with `$become <rule>__rep<N>`, plus an empty alternation at the end.
+//! - `( ... )+` - One or more. Becomes `<rule>__rep<N>` (one required item) and `<rule>__rep<N>_more` (the rest, like `*`).
+//!
+//! `<rule>` is the name of the rule the group appears in, and `<N>` counts groups within that rule, from 1, outermost first. The `*`, `+` or `?` must come right after the `)`.
+//!
+//! Since there's no backtracking, every alternative of an optional or repeated group must begin with a predicate (`@peek`, `@peekr`, `@peekres`, `@guard`, `@auto`, or `@eof`); that's how the parser decides whether to take it. Grammars that break this rule are rejected, unless the rule uses `$infer_peeks`, which writes them for you.
+//!
+//! Groups don't create AST nodes of their own: their contents are added to the node of the rule they appear in. If the group is the last thing in an alternation, this is done with `$become` (so long lists don't use any stack); otherwise the generated rule is called and `$hoist`ed. `$pruned` applies to the contents of groups as well.
+//!
+//! For example, `list ::= "[" @peek(0, "x") "x" ( @peek(0, ",") "," "x" )* "]"` produces a flat `list` node containing every token.
+//!
+//! `$sep_by(element, separator)` is sugar for a flat list of one or more `element`s separated by `separator`, like `x, x, x`. `element` is a rule name or literal, and `separator` is a string or regex literal. To allow a trailing separator or an empty list, give the token that comes after the list, and then `trailing` and/or `empty`: `$sep_by(element, ",", "]", trailing, empty)`. The token after the list is only peeked at, not consumed. Like groups, the list doesn't create an AST node of its own, and `$pruned` applies to it (dropping the separators).
+//!
+//! For example, `array ::= $pruned "[" $sep_by(element, ",", "]", empty) "]"` parses `[]`, `[1]` and `[1, 2]` into a flat `array` node. It becomes rules named `array__sep1`, `array__sep1_tail` and (with `trailing`) `array__sep1_next`.
+//!
+//! ## Templates
+//!
+//! Rules can take parameters, for families of rules that only differ in which token or rule they use:
+//!
+//! ```text
+//! star_until(X, END) ::=
+//!     @peek(0, END) #empty
+//!     | X $become star_until(X, END)
+//! block_item_list ::= block_item $become star_until(block_item, "}")
+//! ```
+//!
+//! Parameters are plain names, and are replaced wherever they appear as a whole term in the template's body, including inside predicates like `@peek(0, END)`. Arguments are rule names, literals, or other template calls (e.g. `list(pair(key, ":"), ",")`).
+//!
+//! Every distinct set of arguments becomes a concrete rule, named after the template and its arguments, like `star_until<block_item,"}">`; this is also the name of its AST nodes. Templates that are never used don't produce any rules. A template can't share its name with a plain rule.
+//!
+//! ## Including other grammars
+//!
+//! With [`bnf_to_grammar_with_loader`](bnf::bnf_to_grammar_with_loader), a grammar can pull in rules from other grammars, one directive per line:
+//!
+//! - `%include "path"` adds the other grammar's rules as-is.
+//! - `%import prefix "path"` adds them renamed to `prefix.name` (e.g. `expr.sum`), so they can't clash with yours. References between the other grammar's own rules are renamed to match.
+//!
+//! Paths are passed to your loader callback exactly as written, so they can refer to files, in-memory strings, or anything else. Magic pseudo-rules like `__COMMENTS` are merged across grammars, and defining the same rule in two places is an error that reports both locations.
+//!
+//! ## Dialects
+//!
+//! A grammar can be extended into a dialect with [`Grammar::with_overlay`](bnf::Grammar::with_overlay), which takes more BNF that can also modify existing rules:
+//!
+//! - `%append name ::= ...` adds alternations to the end of a rule.
+//! - `%prepend name ::= ...` adds alternations to the start of a rule, so they're tried first.
+//! - `%replace name ::= ...` replaces all of a rule's alternations.
+//!
+//! Magic pseudo-rules are merged, so `__RESERVED_WORDS ::= typeof` in an overlay adds a reserved word. The base grammar isn't changed, so e.g. C99 could be the base of both a GNU dialect and a C11 dialect. These directives also work in normal grammars, for modifying rules that came from `%include` or `%import`.
+//!
+//! ## Building grammars in code
+//!
+//! [`GrammarBuilder`](builder::GrammarBuilder) builds a grammar without writing BNF text, e.g. `GrammarBuilder::new().rule("x").alt(|a| a.peek(0, "(").lit("(").rule("y").become_("z"))`. Every BNF term has a matching method, and `build` does the same validation as `bnf_to_grammar`.
+//!
+//! ## Precompiled grammars
+//!
+//! [`Grammar::to_bytes`](bnf::Grammar::to_bytes) saves a built grammar in a compact binary form, and [`Grammar::from_bytes`](bnf::Grammar::from_bytes) loads it back without parsing or validating any BNF. This is useful for short-lived processes and WASM bundles, e.g. with `include_bytes!`. Regexes are still compiled on load. The format is tied to the crate version.
+//!
+//! ## Compile-time grammars
+//!
+//! With the `macros` feature, `grammar!` builds a grammar at compile time, so mistakes in it are compile errors. It expands to a `grammar()` function that loads the precompiled grammar, plus constants for rule IDs (`rules::NAME`), the interned IDs of rule names as found in AST nodes (`rule_names::NAME`), and the interned IDs of literals (`literals::STAR` for `"*"`, etc). Strings that only regexes match can be interned up front with `intern = [...]` to get constants too.
+//!
+//! ```
+//! # {} /*
+//! mod c_grammar { pred_recdec::grammar!("src/grammar_c.txt", intern = ["+", "-"]); }
+//! let mut g = c_grammar::grammar();
+//! let plus = c_grammar::literals::PLUS;
+//! # */
+//! ```
+//!
+//! ## Native parsers
+//!
+//! `codegen::generate_rust` turns a grammar into Rust source for a recursive-descent parser, with a function per rule, peeks compiled to comparisons of interned string IDs, and `$become` compiled to loops. Its `parse` function takes the same arguments as `ast::parse`, calls guards and hooks the same way, and produces the same ASTs and errors (including the rules that errors came up through, with the `deep_errors` feature), but faster, e.g. about 30% faster for the C grammar. Like `ast::parse`, it keeps its own stack of rules instead of recursing, so there's no depth limit. Write it to a file from a build script or with `prd codegen` and `include!` it in a module, or have `grammar!` generate it with the `native` option. It must be given the same grammar it was generated from.
+//!
+//! ```
+//! # {} /*
+//! mod c_grammar { pred_recdec::grammar!("src/grammar_c.txt", native); }
+//! let g = c_grammar::grammar();
+//! let ast = c_grammar::parse(&g, "S", &tokens[..], guards, hooks);
+//! # */
+//! ```
+//!
+//! ## Grammar analysis
+//!
+//! `analysis::analyze` checks a grammar for alternations that can never be chosen or that look like mistakes, without parsing anything: alternations after an unguarded one, guards that only accept what an earlier guard already accepts (e.g. `@peek(0, "if")` after ```@peekr(0, r`[a-z]+`r)```), empty alternations that aren't last, `@peek(0, ...)` for a token that the alternation can't start with, peeks past the end of what the alternation can consume, and left recursion or `$become` cycles that don't consume anything (which make parsing hit the depth limit or loop forever), along with the path of rules around the cycle. It returns structured `Diagnostic`s with the rule and alternation index. `analysis::first_sets` computes the FIRST set of each rule, i.e. which tokens it can start with, and whether it can match nothing. `analysis::usage_report` lists the rules that can't be reached from a given root rule, the rules that nothing refers to, and the literals and regexes that the tokenizer looks for but that only unreachable rules use.
+//!
+//! ## Railroad diagrams
+//!
+//! `railroad::generate_html` makes a standalone HTML page with a railroad (syntax) diagram of every rule, each followed by its BNF, with rule names linking to their diagrams, for publishing a grammar as the documentation of its language. Literals and regexes are round boxes, rules are square boxes, predicates are dashed boxes, and hooks are dotted boxes. Alternations that `$become` their own rule are drawn as loops. `railroad::rule_svg` draws a single rule as SVG.
+//!
+//! ## Exporting to other formats
+//!
+//! `export::to_w3c_ebnf`, `export::to_antlr` and `export::to_tree_sitter` turn a grammar into W3C-style EBNF, an ANTLR4 `.g4` grammar, or a tree-sitter `grammar.js` skeleton, for getting started with other tools or for specs. Alternations that `$become` their own rule turn back into repetition, literals and regexes become lexer rules (ANTLR and EBNF) or stay inline (tree-sitter), and guards, hooks and other predicates become comments, since the other formats have nothing like them. Regexes that can't be translated are left as `TODO` comments.
+//!
+//! ## Importing ABNF and EBNF
+//!
+//! `import::from_abnf` and `import::from_w3c_ebnf` go the other way, turning an ABNF (RFC 5234) or W3C-style EBNF grammar into BNF for this crate. Those formats are written character by character, so rules that only use characters and other such rules become token regexes, and whitespace rules are dropped, since the tokenizer skips whitespace; pass a list of token rules if the guess is wrong. Repetition and options become helper rules, like the ones EBNF sugar makes. Each alternation except the last gets a `@peek` or `@peekr` built from the tokens it can start with (see `analysis::infer_peeks`). Alternations that start with the same tokens as a later one can't be told apart that way, so they're listed in the result's `conflicts` and need a guard written by hand. Parts that can't be converted, like ABNF prose values, are listed in its `notes`.
+//!
+//! ## Load errors
+//!
+//! Grammars that fail to load give a `bnf::GrammarError`, with what kind of mistake it is (`GrammarErrorKind`), the rule and `%include`d file it's in, and the line, column, and text of the offending term where possible. It displays as the message followed by the location, e.g. `Not a defined grammar rule: 'U' (context: 'S') (line 1, column 11)`, and converts into a `String` for code that only wants the message.
+//!
 //! ## Magic pseudo-rules
 //!
 //! The following magic pseudo rule names are available (e.g. `__COMMENTS ::= //`):
@@ -130,17 +257,43 @@
 //! - `__COMMENT_PAIRS_NESTED` - Same, but nesting, like in Rust.
 //! - `__COMMENT_REGEXES` - Same, but formed as a regex. These are slower than the above, because the Rust `regex` crate doesn't have a JIT.
 //! - `__RESERVED_WORDS` - e.g. `::= auto break case` - Specifies a list of token contents that are not allowed to be "accepted" by regex terminals like ```r`[a-zA-Z_]+`r```
+//! - `__INFER_PEEKS` e.g. `::= expr stmt` - Gives these rules predicates like `$infer_peeks` does, or every rule with `::= *`.
+
+pub use pred_recdec_core::{bnf, serialize, codegen, analysis};
+/// Module for building grammars in code.
+pub mod builder;
+/// Module for drawing grammars as railroad diagrams.
+pub mod railroad;
+/// Module for exporting grammars to other parser generators' formats.
+pub mod export;
+/// Module for converting grammars from ABNF and W3C-style EBNF.
+pub mod import;
 
-/// Module for BNF and Grammar-related stuff.
-pub mod bnf;
+/// Builds a grammar at compile time. Requires the `macros` feature.
+#[cfg(feature = "macros")]
+pub use pred_recdec_macros::grammar;
 
 /// Module for parsing and AST-related stuff.
 pub mod ast;
 
+/// Module for observing the parser while it runs (tracing, debugging, etc).
+pub mod trace;
+
+/// Module for measuring which parts of a grammar get exercised.
+pub mod coverage;
+/// Module for measuring where parse time goes.
+pub mod profile;
+/// Module for interactively stepping through a parse.
+pub mod debugger;
+
 mod json;
 
-// Thing
-pub (crate) use rustc_hash::FxBuildHasher as HashBuilder;
+/// Hasher used by this crate's hash maps, e.g. the ones that hold guards and hooks.
+pub use rustc_hash::FxBuildHasher as HashBuilder;
+
+// lets grammar! refer to this crate by name in tests
+#[cfg(test)]
+extern crate self as pred_recdec;
 
 #[cfg(test)]
 mod test {
@@ -233,4 +386,1014 @@ mod test {
         
         assert_eq!(*g.string_cache_inv[ast.children.as_ref().unwrap()[1].text as usize], "ax");
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_observer() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        pub use trace::*;
+        
+        #[derive(Default)]
+        struct Log { events : Vec<String> }
+        impl ParseObserver for Log {
+            fn rule_entered(&mut self, rule : usize, token : usize) { self.events.push(format!("enter {rule} {token}")); }
+            fn alt_chosen(&mut self, rule : usize, alt : usize, _token : usize) { self.events.push(format!("alt {rule} {alt}")); }
+            fn guard_checked(&mut self, rule : usize, alt : usize, _token : usize, accepted : bool) { self.events.push(format!("guard {rule} {alt} {accepted}")); }
+            fn became(&mut self, rule : usize, target : usize, _token : usize) { self.events.push(format!("become {rule} {target}")); }
+            fn rule_exited(&mut self, rule : usize, start : usize, end : usize, ok : bool) { self.events.push(format!("exit {rule} {start} {end} {ok}")); }
+        }
+        
+        let grammar_source = r#"
+    S ::= item $become list
+    list ::= @peek(0, ",") "," item $become list | #empty
+    item ::= @peek(0, "(") "(" ")" | "x"
+        "#;
+        let mut g = bnf_to_grammar(&grammar_source).unwrap();
+        let tokens = tokenize(&mut g, "x, ()").unwrap();
+        
+        use std::rc::Rc;
+        let mut expected = None;
+        for recursive in [false, true]
+        {
+            let mut log = Log::default();
+            let ast = if recursive { parse_recursive_with_observer(&g, "S", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default()), &mut log) }
+                else { parse_with_observer(&g, "S", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default()), &mut log) };
+            assert_eq!(ast_to_shape_string(&ast.unwrap()), "++.-.+..--");
+            assert_eq!(log.events.first().unwrap(), "enter 0 0");
+            assert_eq!(log.events.last().unwrap(), "exit 1 0 4 true");
+            assert!(log.events.contains(&"become 0 1".to_string()));
+            assert!(log.events.contains(&"guard 2 0 true".to_string()));
+            assert!(log.events.contains(&"exit 2 2 4 true".to_string()));
+            // both implementations must report the exact same events
+            if let Some(expected) = &expected { assert_eq!(expected, &log.events); }
+            expected = Some(log.events);
+        }
+        
+        let tokens = tokenize(&mut g, "x, (").unwrap();
+        let mut log = Log::default();
+        assert!(parse_with_observer(&g, "S", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default()), &mut log).is_err());
+        let entered = log.events.iter().filter(|x| x.starts_with("enter")).count();
+        let exited = log.events.iter().filter(|x| x.starts_with("exit")).count();
+        assert_eq!(entered, exited);
+    }
+    
+    #[test]
+    fn test_coverage() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        pub use coverage::*;
+        
+        let grammar_source = r#"
+    S ::= item $become list
+    list ::= @peek(0, ",") "," item $become list | #empty
+    item ::= @peek(0, "(") "(" ")" | "x"
+    unused ::= "y"
+        "#;
+        let mut g = bnf_to_grammar(&grammar_source).unwrap();
+        
+        use std::rc::Rc;
+        let mut cov = Coverage::new(&g);
+        let tokens = tokenize(&mut g, "x").unwrap();
+        parse_with_observer(&g, "S", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default()), &mut cov).unwrap();
+        let report = cov.report(&g);
+        assert_eq!(report.unused_points, vec!(3));
+        assert_eq!(report.unused_alts, vec!((1, 0), (2, 0)));
+        assert!(report.unused_terms.is_empty());
+        
+        // a failed parse still counts up to where it failed (the failing term itself counts as reached)
+        let mut cov2 = Coverage::new(&g);
+        let tokens = tokenize(&mut g, "x, (").unwrap();
+        assert!(parse_with_observer(&g, "S", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default()), &mut cov2).is_err());
+        cov.merge(&cov2);
+        let report = cov.report(&g);
+        assert_eq!(report.unused_points, vec!(3));
+        assert!(report.unused_alts.is_empty());
+        assert_eq!(report.unused_terms, vec!((1, 0, 3)));
+    }
+    
+    #[test]
+    fn test_profile() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        pub use profile::*;
+        
+        let grammar_source = r#"
+    S ::= item $become list
+    list ::= @peek(0, ",") "," item $become list | #empty
+    item ::= @peek(0, "(") "(" !hook(nothing) ")" | r`[a-z]+`r
+        "#;
+        let mut g = bnf_to_grammar(&grammar_source).unwrap();
+        let tokens = tokenize(&mut g, "x, (), y").unwrap();
+        
+        use std::rc::Rc;
+        let mut hooks = std::collections::HashMap::<String, Rc<dyn Fn(&mut PrdGlobal, &[Token], usize, &mut Vec<ASTNode>) -> Result<usize, String>>, HashBuilder>::default();
+        hooks.insert("nothing".to_string(), Rc::new(|_, _, _, _| Ok(0)));
+        let mut prof = Profiler::new(&g);
+        parse_with_observer(&g, "S", &tokens[..], Rc::new(<_>::default()), Rc::new(hooks), &mut prof).unwrap();
+        
+        assert_eq!(prof.rules.iter().map(|x| x.calls).collect::<Vec<_>>(), vec!(1, 3, 3));
+        assert_eq!(prof.hooks["nothing"].calls, 1);
+        assert_eq!(prof.guards["@peek(0, \"(\")"].calls, 3);
+        assert_eq!(prof.guards["@peek(0, \",\")"].calls, 3);
+        
+        let folded = prof.folded_stacks(&g);
+        assert!(folded.lines().all(|l| l.rsplit(' ').next().unwrap().parse::<u64>().is_ok()));
+        // the root frame is named after whatever it $become'd into
+        assert!(folded.lines().any(|l| l.starts_with("list;item;!hook(nothing) ")));
+        assert!(prof.report(&g).contains("nothing"));
+    }
+    
+    #[test]
+    fn test_debugger() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        pub use debugger::*;
+        
+        let grammar_source = r#"
+    S ::= item $become list
+    list ::= @peek(0, ",") "," item $become list | #empty
+    item ::= @peek(0, "(") "(" ")" | "x"
+        "#;
+        let mut g = bnf_to_grammar(&grammar_source).unwrap();
+        let tokens = tokenize(&mut g, "x, ()").unwrap();
+        
+        use std::rc::Rc;
+        use std::cell::RefCell;
+        let commands = RefCell::new(vec!("b item", "c", "c", "bt", "children 0", "alts", "s", "s", "q").into_iter());
+        let output = Rc::new(RefCell::new(Vec::<u8>::new()));
+        struct Out(Rc<RefCell<Vec<u8>>>);
+        impl std::io::Write for Out {
+            fn write(&mut self, buf : &[u8]) -> std::io::Result<usize> { self.0.borrow_mut().extend_from_slice(buf); Ok(buf.len()) }
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+        let mut dbg = Debugger::new(&g, || commands.borrow_mut().next().map(|x| x.to_string()), Out(output.clone()));
+        let ast = parse_with_observer(&g, "S", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default()), &mut dbg);
+        drop(dbg);
+        assert_eq!(ast_to_shape_string(&ast.unwrap()), "++.-.+..--");
+        
+        let output = String::from_utf8(output.borrow().clone()).unwrap();
+        println!("{output}");
+        // first stop, then the two item breakpoints
+        assert!(output.contains("#0 S, choosing among 1 alternations, tokens 0..0 (next: `x`)"));
+        assert!(output.contains("#1 item, choosing among 2 alternations, tokens 0..0 (next: `x`)"));
+        assert!(output.contains("#1 item, choosing among 2 alternations, tokens 2..2 (next: `(`)"));
+        // bt and children at the second breakpoint
+        assert!(output.contains("#0 list (as S) alt 0 term 2: item, tokens 0..2 (next: `(`)"));
+        assert!(output.contains("item(\"x\")\n\",\"\n"));
+        assert!(output.contains("  1: \"x\""));
+        // single-stepping shows guard results
+        assert!(output.contains("  item alt 0 at token 2: @peek(0, \"(\") accepted"));
+        assert!(output.contains("#1 item alt 0 term 1: \"(\", tokens 2..2 (next: `(`)"));
+    }
+    
+    #[test]
+    fn test_ebnf() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        
+        let grammar_source = r#"
+    S ::= "[" ( @peek(0, "x") "x" | @peek(0, "(") ( "(" ")" ) )* "]" [ @peek(0, ";") ";" ]
+    T ::= ( @peek(0, "x") "x" ( @peek(0, ",") "," )? )+ $pruned
+        "#;
+        let mut g = bnf_to_grammar(&grammar_source).unwrap();
+        let mut names = g.points.iter().map(|p| p.name.to_string()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!("S", "S__group2", "S__opt3", "S__rep1", "T", "T__opt2", "T__rep1", "T__rep1_more"));
+        
+        use std::rc::Rc;
+        let tokens = tokenize(&mut g, "[ x () x ] ;").unwrap();
+        let ast = parse(&g, "S", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).unwrap();
+        // groups are flattened into the node that uses them
+        assert_eq!(ast_to_shape_string(&ast), "+.......-");
+        let tokens = tokenize(&mut g, "[ ]").unwrap();
+        let ast = parse(&g, "S", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).unwrap();
+        assert_eq!(ast_to_shape_string(&ast), "+..-");
+        
+        let tokens = tokenize(&mut g, "x, x x").unwrap();
+        let ast = parse(&g, "T", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).unwrap();
+        assert_eq!(ast_to_shape_string(&ast), "+-");
+        assert_eq!(ast.get_real_token_count(), 4);
+        let tokens = tokenize(&mut g, ",").unwrap();
+        assert!(parse(&g, "T", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).is_err());
+        
+        // repeated and optional groups need a predicate to decide whether to continue
+        assert!(bnf_to_grammar(r#"S ::= ( "x" )*"#).err().unwrap().to_string().contains("must begin with a predicate"));
+        assert!(bnf_to_grammar(r#"S ::= [ @peek(0, "x") "x" | "y" ]"#).err().unwrap().to_string().contains("must begin with a predicate"));
+        assert!(bnf_to_grammar(r#"S ::= ( "x" ]"#).is_err());
+        assert!(bnf_to_grammar(r#"S ::= ( "x""#).is_err());
+        assert!(bnf_to_grammar(r#"S ::= "x" )"#).is_err());
+        // plain groups don't
+        assert!(bnf_to_grammar(r#"S ::= ( "x" | "y" ) "z""#).is_ok());
+    }
+    
+    #[test]
+    fn test_placement() {
+        use crate::*;
+        pub use bnf::*;
+        
+        let err = |source : &str| bnf_to_grammar(source).err().unwrap().err_message;
+        assert_eq!(err(r#"S ::= "x" @peek(0, "y") x
+    x ::= "x""#), "@peek must be the first term of an alternation (context: S alt 0 term 1)");
+        assert_eq!(err(r#"S ::= "x" | "y" @eof"#), "@eof must be the first term of an alternation (context: S alt 1 term 1)");
+        assert_eq!(err(r#"S ::= "x" $become"#), "$become must be followed by a rule name, at the end of the alternation (context: S alt 0 term 1)");
+        assert_eq!(err(r#"S ::= $become_as S "x""#), "$become_as must be followed by a rule name, at the end of the alternation (context: S alt 0 term 0)");
+        assert_eq!(err(r#"S ::= "x" $rename "y""#), "$rename must be followed by a rule name (context: S alt 0 term 1)");
+        assert_eq!(err(r#"S ::= $hoist "x""#), "$hoist must come after something that adds to the AST (context: S alt 0 term 0)");
+        assert_eq!(err(r#"S ::= $pruned "x" $drop"#), "$drop must come after something that adds to the AST (context: S alt 0 term 1)");
+        assert_eq!(err(r#"S ::= "x" $drop $drop_empty"#), "$drop_empty must come after something that adds to the AST (context: S alt 0 term 2)");
+        
+        assert!(bnf_to_grammar(r#"S ::= @peek(0, "x") "x" $hoist_unit $drop | !hook(h) $drop $drop | $rename S $become S"#).is_ok());
+    }
+    
+    #[test]
+    fn test_grammar_errors() {
+        use crate::*;
+        pub use bnf::*;
+        
+        let err = |source : &str| bnf_to_grammar(source).err().unwrap();
+        let where_ = |e : &GrammarError| (e.kind, e.rule.clone(), e.line, e.column, e.snippet.clone());
+        
+        let e = err("S ::= \"x\"\nT ::= r`abc");
+        assert_eq!(where_(&e), (GrammarErrorKind::UnterminatedRegex, None, 2, 7, "r`abc".to_string()));
+        assert_eq!(e.to_string(), "Unterminated regex (line 2, column 7)");
+        let e = err("S ::= \"x\"\nT ::= \"a\" r`(`r");
+        assert_eq!(where_(&e), (GrammarErrorKind::InvalidRegex, Some("T".to_string()), 2, 11, "r`(`r".to_string()));
+        let e = err("S ::= \"x\" U");
+        assert_eq!(where_(&e), (GrammarErrorKind::UnknownRule, Some("S".to_string()), 1, 11, "U".to_string()));
+        let e = err("S ::= \"x\"\n\nS ::= \"y\"");
+        assert_eq!(where_(&e), (GrammarErrorKind::DuplicateRule, Some("S".to_string()), 3, 1, "S".to_string()));
+        let e = err("S ::= \"x\" @peek(x, \"y\")");
+        assert_eq!(where_(&e), (GrammarErrorKind::BadPredicate, Some("S".to_string()), 1, 11, "@peek".to_string()));
+        let e = err("S ::= @peekr(0, \"a\") \"x\"");
+        assert_eq!(where_(&e), (GrammarErrorKind::BadPredicate, Some("S".to_string()), 1, 7, "@peekr".to_string()));
+        assert!(e.err_message.contains("@peekr/@peekres only accept regex strings"), "{}", e.err_message);
+        let e = err("S ::= !hook \"x\"");
+        assert_eq!(where_(&e), (GrammarErrorKind::BadPredicate, Some("S".to_string()), 1, 7, "!hook".to_string()));
+        assert!(e.err_message.contains("must be !hook(name)"), "{}", e.err_message);
+        let e = err("S ::= \"x\"\n  | \"y\" $become");
+        assert_eq!(where_(&e), (GrammarErrorKind::Placement, Some("S".to_string()), 2, 9, "$become".to_string()));
+        let e = err("S ::= \"x\" @peek(0 \"a\")");
+        assert_eq!(where_(&e), (GrammarErrorKind::BadPredicate, Some("S".to_string()), 1, 11, "@peek".to_string()));
+        // unclosed groups, including argument lists, point at their opening bracket
+        for (source, column) in [("S ::= (\"a\"", 7), ("S ::= $sep_by(\"a\"", 14), ("S ::= @guard(", 13), ("S ::= !hook(", 12)]
+        {
+            assert_eq!(where_(&err(source)), (GrammarErrorKind::Syntax, Some("S".to_string()), 1, column, "(".to_string()));
+        }
+        let e = err("S ::= \"x\"\n  | [ \"y\"\nT ::= \"z\"");
+        assert_eq!(where_(&e), (GrammarErrorKind::Syntax, Some("S".to_string()), 2, 5, "[".to_string()));
+        let e = err("__BRACKET_PAIRS ::= (\nS ::= \"x\"");
+        assert_eq!(where_(&e), (GrammarErrorKind::Syntax, Some("__BRACKET_PAIRS".to_string()), 1, 21, "(".to_string()));
+        assert_eq!(err("S ::= \"x").kind, GrammarErrorKind::BrokenLiteral);
+        assert_eq!(err("S \"x\"").kind, GrammarErrorKind::Syntax);
+        assert_eq!(err("S ::= t(\"x\", \"y\")\nt(A) ::= A").kind, GrammarErrorKind::Template);
+        
+        // errors inside included files say which file they're in
+        let files = |path : &str| -> Result<String, String>
+        {
+            match path
+            {
+                "a.txt" => Ok("x ::= \"a\"\ny ::= \"b\" \"c".to_string()),
+                _ => Err("not found".to_string()),
+            }
+        };
+        let e = bnf_to_grammar_with_loader("%include \"a.txt\"\nS ::= x", files).err().unwrap();
+        assert_eq!((e.kind, e.file.as_deref(), e.line, e.column), (GrammarErrorKind::BrokenLiteral, Some("a.txt"), 2, 11));
+        assert_eq!(e.to_string(), "Broken literal text rule (line 2, column 11 of a.txt)");
+        let e = bnf_to_grammar_with_loader("S ::= \"x\"\n%include \"b.txt\"", files).err().unwrap();
+        assert_eq!((e.kind, e.file.as_deref(), e.line, e.column), (GrammarErrorKind::Include, None, 2, 11));
+        
+        // still usable as a plain string
+        let e : String = err("S ::= \"x\" U").into();
+        assert!(e.starts_with("Not a defined grammar rule: 'U'"));
+    }
+    
+    #[test]
+    fn test_sep_by() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        
+        let grammar_source = r#"
+    L ::= "{" $sep_by(item, ",", "}", trailing, empty) "}"
+    M ::= $pruned $sep_by(item, ",")
+    item ::= "x"
+        "#;
+        let mut g = bnf_to_grammar(&grammar_source).unwrap();
+        
+        use std::rc::Rc;
+        let run = |g : &mut Grammar, rule : &str, text : &str|
+        {
+            let tokens = tokenize(g, text).unwrap();
+            parse(g, rule, &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).map(|ast| ast_to_shape_string(&ast))
+        };
+        // lists are flattened into the node that uses them
+        assert_eq!(run(&mut g, "L", "{ x , x }").unwrap(), "+.+.-.+.-.-");
+        assert_eq!(run(&mut g, "L", "{ x , x , }").unwrap(), "+.+.-.+.-..-");
+        assert_eq!(run(&mut g, "L", "{ }").unwrap(), "+..-");
+        assert!(run(&mut g, "L", "{ , }").is_err());
+        assert!(run(&mut g, "L", "{ x , , }").is_err());
+        // $pruned drops the separators
+        assert_eq!(run(&mut g, "M", "x , x , x").unwrap(), "++.-+.-+.--");
+        assert!(run(&mut g, "M", "x , x ,").is_err());
+        assert!(run(&mut g, "M", "").is_err());
+        
+        assert!(bnf_to_grammar(r#"S ::= $sep_by(item)"#).is_err());
+        assert!(bnf_to_grammar(r#"S ::= $sep_by(item, ",", trailing)"#).is_err());
+        assert!(bnf_to_grammar(r#"S ::= $sep_by(item, ",", "}", sometimes)"#).is_err());
+        assert!(bnf_to_grammar(r#"S ::= $sep_by(@peek(0, "x"), ",")"#).is_err());
+    }
+    
+    #[test]
+    fn test_templates() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        
+        let grammar_source = r#"
+    list(X, SEP) ::= X $become list_tail(X, SEP)
+    list_tail(X, SEP) ::=
+        @auto SEP X $become list_tail(X, SEP)
+        | #empty
+    pair(A, B) ::= A B
+    unused(A) ::= A
+    S ::= list(item, ",") ";" list(pair(item, "="), "&")
+    item ::= "x"
+        "#;
+        let mut g = bnf_to_grammar(&grammar_source).unwrap();
+        let mut names = g.points.iter().map(|p| p.name.to_string()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!("S", "item", r#"list<item,",">"#, r#"list<pair<item,"=">,"&">"#, r#"list_tail<item,",">"#,
+            r#"list_tail<pair<item,"=">,"&">"#, r#"pair<item,"=">"#));
+        
+        use std::rc::Rc;
+        let tokens = tokenize(&mut g, "x , x ; x = & x =").unwrap();
+        let ast = parse(&g, "S", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).unwrap();
+        assert_eq!(ast_to_shape_string(&ast), "+++.-.+.--.+++.-.-.++.-.---");
+        
+        assert!(bnf_to_grammar(r#"S ::= t("x", "y")
+    t(A) ::= A"#).err().unwrap().to_string().contains("takes 1 arguments"));
+        assert!(bnf_to_grammar(r#"S ::= t
+    t(A) ::= A"#).is_err());
+        assert!(bnf_to_grammar(r#"S ::= t("x")
+    t(A) ::= A t(t(A))"#).err().unwrap().to_string().contains("infinitely recursive"));
+        assert!(bnf_to_grammar(r#"S ::= "x"
+    S(A) ::= A"#).is_err());
+    }
+    
+    #[test]
+    fn test_compose() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        
+        let files = |path : &str| -> Result<String, String>
+        {
+            Ok(match path
+            {
+                "common.txt" => "__COMMENTS ::= \"//\"\nitem ::= \"x\"",
+                "expr.txt" => r#"
+__COMMENTS ::= "--"
+%include "number.txt"
+sum ::= @guard(number) number $become sum_tail
+sum_tail ::= @auto "+" number $become sum_tail | #empty
+"#,
+                "number.txt" => "number ::= r`[0-9]+`r",
+                "cycle_a.txt" => "%include \"cycle_b.txt\"",
+                "cycle_b.txt" => "%include \"cycle_a.txt\"",
+                _ => return Err("not found".to_string()),
+            }.to_string())
+        };
+        let grammar_source = r#"
+%include "common.txt"
+%import e "expr.txt"
+%import e "expr.txt"
+S ::= item e.sum
+        "#;
+        let mut g = bnf_to_grammar_with_loader(&grammar_source, files).unwrap();
+        let mut names = g.points.iter().map(|p| p.name.to_string()).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!("S", "e.number", "e.sum", "e.sum_tail", "item"));
+        
+        use std::rc::Rc;
+        let mut guards = std::collections::HashMap::<String, Guard, HashBuilder>::default();
+        guards.insert("number".to_string(), Rc::new(|_, _, _| GuardResult::Accept));
+        // comments from both grammars are merged
+        let tokens = tokenize(&mut g, "x 1 + 2 // a\n-- b\n+ 3").unwrap();
+        assert!(parse(&g, "S", &tokens[..], Rc::new(guards), Rc::new(<_>::default())).is_ok());
+        
+        let err = bnf_to_grammar_with_loader("%include \"common.txt\"\nitem ::= \"y\"", files).err().unwrap().to_string();
+        assert!(err.contains("Duplicate rule item") && err.contains("line 2 of common.txt") && err.contains("line 2)"));
+        assert!(bnf_to_grammar_with_loader("%include \"cycle_a.txt\"", files).err().unwrap().to_string().contains("Include cycle"));
+        assert!(bnf_to_grammar_with_loader("%include \"missing.txt\"", files).is_err());
+        assert!(bnf_to_grammar("%include \"common.txt\"").is_err());
+    }
+    
+    #[test]
+    fn test_overlay() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        use debugger::ast_to_sexpr;
+        
+        let grammar_source = r#"
+    __RESERVED_WORDS ::= if
+    S ::= @peekres(0, r`[a-z]+`r) name | keyword
+    keyword ::= @auto "if" | @auto "else"
+    name ::= r`[a-z]+`r
+        "#;
+        let base = bnf_to_grammar(&grammar_source).unwrap();
+        let dialect = base.with_overlay(r#"
+    __RESERVED_WORDS ::= typeof
+    %append keyword ::= @auto "typeof"
+    %replace name ::= r`[a-z_]+`r
+    %prepend S ::= @peek(0, "pragma") pragma
+    pragma ::= "pragma" name
+        "#).unwrap();
+        
+        use std::rc::Rc;
+        let run = |g : &Grammar, text : &str|
+        {
+            // tokenizing needs a mutable grammar, and an empty overlay makes a copy
+            let mut g = g.with_overlay("").unwrap();
+            let tokens = tokenize(&mut g, text).unwrap();
+            let ast = parse(&g, "S", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).unwrap();
+            let mut s = String::new();
+            ast_to_sexpr(&ast, &g, &mut s);
+            s
+        };
+        assert_eq!(run(&base, "typeof"), r#"S(name("typeof"))"#);
+        assert_eq!(run(&dialect, "typeof"), r#"S(keyword("typeof"))"#);
+        assert_eq!(run(&dialect, "pragma a_b"), r#"S(pragma("pragma" name("a_b")))"#);
+        // the base grammar isn't changed
+        assert_eq!(base.points[base.by_name["keyword"]].forms.len(), 2);
+        assert_eq!(dialect.points[dialect.by_name["S"]].forms.len(), 3);
+        
+        assert!(base.with_overlay(r#"name ::= "x""#).err().unwrap().to_string().contains("the base grammar"));
+        assert!(base.with_overlay(r#"%append missing ::= "x""#).is_err());
+        assert!(base.with_overlay(r#"%append"#).is_err());
+    }
+    
+    #[test]
+    fn test_analysis() {
+        use crate::*;
+        pub use bnf::*;
+        pub use analysis::*;
+        
+        let grammar_source = r#"
+    S ::=
+        @peek(0, "a") "a" x
+        | @peek(0, "a") "b"
+        | @peekr(0, r`[a-z]+`r) x
+        | @peek(0, "q") x
+        | @peek(0, "1") "(" y
+        | @peek(2, ";") "z"
+        | #empty
+        | "c"
+    x ::= @peek(0, "x") "x" | y $become x
+    y ::= @peek(0, "h") !hook(h) | #empty
+        "#;
+        let g = bnf_to_grammar(&grammar_source).unwrap();
+        let sets = first_sets(&g);
+        let id = |s : &str| *g.string_cache.get(s).unwrap();
+        assert_eq!(sets[1].literals, vec!(id("x")));
+        assert!(sets[1].any && !sets[1].empty && sets[2].any && sets[2].empty);
+        assert!(sets[0].empty && sets[0].regexes.is_empty());
+        let first = alt_first_set(&g, &sets, 0, 4);
+        assert_eq!(first.literals, vec!(id("(")));
+        assert!(first.may_start_with(&g, id("(")) && !first.may_start_with(&g, id("1")));
+        assert!(sets[0].may_start_with(&g, id("1")));
+        
+        let kinds = analyze(&g).into_iter().map(|d| (d.rule, d.alt, d.kind)).collect::<Vec<_>>();
+        assert_eq!(kinds, vec!(
+            (0, 1, DiagnosticKind::Shadowed { by : 0 }),
+            (0, 3, DiagnosticKind::Shadowed { by : 2 }),
+            (0, 4, DiagnosticKind::PeekNeverMatches),
+            (0, 5, DiagnosticKind::PeekBeyondRule { distance : 2, max_tokens : 1 }),
+            (0, 6, DiagnosticKind::EmptyNotLast),
+            (0, 7, DiagnosticKind::Unreachable { after : 6 }),
+            // y can match nothing, so x can become itself without consuming anything
+            (1, 1, DiagnosticKind::LeftRecursion { path : vec!(1, 1) }),
+        ));
+        
+        // left recursion, and $become cycles that don't consume anything
+        let grammar_source = r#"
+    a ::= a "x" | "y"
+    b ::= opt c
+    opt ::= @peek(0, "?") "?" | #empty
+    c ::= @peek(0, "!") "!" | b
+    d ::= $become e
+    e ::= @eof | opt $become d
+    f ::= @peek(0, "?") opt $become f | "z"
+        "#;
+        let g = bnf_to_grammar(&grammar_source).unwrap();
+        let cycles = analyze(&g).into_iter().filter_map(|d| match d.kind
+        {
+            DiagnosticKind::LeftRecursion { path } => Some((d.rule, d.alt, path)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(cycles, vec!((0, 0, vec!(0, 0)), (1, 0, vec!(1, 3, 1)), (4, 0, vec!(4, 5, 4))));
+        assert_eq!(analyze(&g).iter().find(|d| d.rule == 1).unwrap().describe(&g), "b alt 0 can reach b again without consuming any tokens: b -> c -> b");
+        
+        for source in [include_str!("grammar_c.txt"), include_str!("grammar_json.txt")]
+        {
+            assert!(analyze(&bnf_to_grammar(source).unwrap()).is_empty());
+        }
+    }
+    
+    #[test]
+    fn test_usage_report() {
+        use crate::*;
+        pub use bnf::*;
+        pub use analysis::*;
+        
+        let grammar_source = r#"
+    S ::= @auto "a" $rename named | @peek(0, "(") "(" list ")"
+    list ::= @peekr(0, r`[0-9]+`r) r`[0-9]+`r $become list | #empty
+    named ::= "never entered"
+    dead ::= "x" r`[a-z]+`r $become dead_tail
+    dead_tail ::= @peek(0, ";") ";" $become dead | #empty
+    orphan ::= @peek(0, "y") "y" $become orphan | #empty
+        "#;
+        let g = bnf_to_grammar(&grammar_source).unwrap();
+        let names = |ids : &[usize]| ids.iter().map(|i| g.points[*i].name.to_string()).collect::<Vec<_>>();
+        let report = usage_report(&g, "S").unwrap();
+        assert_eq!(names(&report.unreachable), vec!("dead", "dead_tail", "orphan"));
+        assert_eq!(names(&report.unreferenced), vec!("orphan"));
+        assert_eq!(report.unused_literals, vec!(";", "x", "y"));
+        assert_eq!(report.unused_regexes, vec!("[a-z]+"));
+        
+        let report = usage_report(&g, "dead").unwrap();
+        assert_eq!(names(&report.unreachable), vec!("S", "list", "named", "orphan"));
+        assert_eq!(report.unused_literals, vec!("(", ")", "a", "never entered", "y"));
+        assert!(usage_report(&g, "missing").is_none());
+    }
+    
+    #[test]
+    fn test_railroad() {
+        use crate::*;
+        pub use bnf::*;
+        pub use railroad::*;
+        
+        let grammar_source = r#"
+    S ::= @peek(0, "(") "(" list ")" | @auto "<" !hook(log) | @guard(is_ok) "?" | @eof
+    list ::= @peekr(1, r`[0-9]+`r) item $hoist $become list | item $become_as tail
+    tail ::= @peek(0, ",") "," $become tail | #empty
+    item ::= r`[0-9]+`r
+        "#;
+        let g = bnf_to_grammar(&grammar_source).unwrap();
+        let svg = rule_svg(&g, g.by_name["S"]);
+        // @peek(0, "(") "(" and @auto "<" only show the token
+        assert!(!svg.contains("next is"));
+        assert!(svg.contains(">&quot;&lt;&quot;</text>") && svg.contains(">&quot;(&quot;</text>"));
+        assert!(svg.contains(">if is_ok</text>") && svg.contains(">hook log</text>") && svg.contains(">end of input</text>"));
+        assert!(svg.contains("<a href=\"#rule-list\">"));
+        assert!(!svg.contains("class=\"back\""));
+        
+        let svg = rule_svg(&g, g.by_name["list"]);
+        assert!(svg.contains(">token +1 is r`[0-9]+`r</text>") && svg.contains(">then tail</text>"));
+        assert!(svg.contains("class=\"back\""));
+        assert!(rule_svg(&g, g.by_name["tail"]).contains("class=\"back\""));
+        
+        let html = generate_html(&g, "Test <grammar>");
+        assert!(html.contains("<title>Test &lt;grammar&gt;</title>"));
+        for name in ["S", "list", "tail", "item"]
+        {
+            assert!(html.contains(&format!("<h2 id=\"rule-{name}\">")));
+        }
+        assert!(html.contains("<pre>tail ::=\n    @peek(0, &quot;,&quot;) &quot;,&quot; $become tail\n    | #empty\n</pre>"));
+        
+        let g = bnf_to_grammar(include_str!("grammar_c.txt")).unwrap();
+        assert_eq!(generate_html(&g, "C").matches("<svg").count(), g.points.len());
+    }
+    
+    #[test]
+    fn test_export() {
+        use crate::*;
+        pub use bnf::*;
+        pub use export::*;
+        
+        let grammar_source = r#"
+    __COMMENTS ::= "//"
+    S ::= @peek(0, "(") "(" list ")" | @auto "if" !hook(log) | @guard(is_ok) "?" $any | @peek(0, "x") "x" r`[a-z]+\b`r | @eof
+    list ::= item $become list_tail
+    list_tail ::= @peek(0, ",") "," item $become list_tail | #empty
+    item ::= r`[0-9]{2,3}`r
+        "#;
+        let g = bnf_to_grammar(&grammar_source).unwrap();
+        
+        let ebnf = to_w3c_ebnf(&g);
+        assert!(ebnf.contains("S ::= LPAREN list RPAREN | IF /* !hook(log) */ | /* @guard(is_ok) */ QUESTION ANY_TOKEN | X REGEX_1 | /* @eof */\n"));
+        // $become loops turn back into repetition
+        assert!(ebnf.contains("list_tail ::= (COMMA item)*\n"));
+        // a rule that's just a regex names its token
+        assert!(ebnf.contains("item ::= ITEM\n") && ebnf.contains("ITEM ::= ([0-9] [0-9] [0-9]?)\n"));
+        assert!(ebnf.contains("REGEX_1 ::= /* TODO: translate by hand: r`[a-z]+\\b`r */"));
+        assert!(ebnf.contains("ANY_TOKEN ::= LPAREN | RPAREN | IF | QUESTION | X | COMMA | REGEX_1 | ITEM\n"));
+        
+        let antlr = to_antlr(&g, "test grammar");
+        assert!(antlr.contains("grammar test_grammar;\n"));
+        assert!(antlr.contains("s\n    : LPAREN list RPAREN | IF /* !hook(log) */ | /* @guard(is_ok) */ QUESTION . | X REGEX_1 | EOF\n    ;"));
+        assert!(antlr.contains("IF : 'if' ;\n") && antlr.contains("COMMENT_1 : '//' ~[\\r\\n]* -> skip ;\n"));
+        
+        let ts = to_tree_sitter(&g, "test", "list").unwrap();
+        // the root goes first
+        assert!(ts.contains("  rules: {\n    list: $ => seq($.item, $.list_tail),\n"));
+        assert!(ts.contains("list_tail: $ => repeat(seq(',', $.item)),"));
+        assert!(ts.contains("item: $ => /[0-9]{2,3}/,") && ts.contains("blank() /* @eof */"));
+        assert!(ts.contains("token(seq('//', /.*/)),"));
+        assert!(to_tree_sitter(&g, "test", "nope").is_none());
+        
+        for source in [include_str!("grammar_c.txt"), include_str!("grammar_json.txt")]
+        {
+            let g = bnf_to_grammar(source).unwrap();
+            assert!(!to_antlr(&g, "x").contains("\\A(?:"));
+            assert!(to_w3c_ebnf(&g).lines().filter(|l| l.contains(" ::= ")).count() > g.points.len());
+        }
+    }
+    
+    #[test]
+    fn test_import() {
+        use crate::*;
+        use std::rc::Rc;
+        pub use import::*;
+        
+        let abnf = r#"
+greeting = salute 1*SP name [ "!" ] ; core rules are added when used
+salute   = "hi" / %s"Hey"
+salute   =/ %x79.6F ; yo
+name     = ALPHA *( ALPHA / DIGIT )
+other    = "q" ; another start rule
+"#;
+        let mut i = from_abnf(abnf, None).unwrap();
+        // whitespace rules are left to the tokenizer, and rules made only of characters become tokens
+        assert!(i.bnf.contains("greeting ::=\n    salute name $become greeting__opt1\n"));
+        assert!(i.bnf.contains("greeting__opt1 ::=\n    @peek(0, \"!\") \"!\"\n    | #empty\n"));
+        assert!(i.bnf.contains("salute ::=\n    r`(?i:hi)|Hey|yo`r\n"));
+        assert!(i.bnf.contains("name ::=\n    r`[A-Za-z][A-Za-z0-9]*`r\n"));
+        assert!(i.bnf.contains("other ::=\n    r`(?i:q)`r\n"));
+        assert!(i.conflicts.is_empty() && i.notes.is_empty());
+        bnf::bnf_to_grammar(&i.bnf).unwrap();
+        let tokens = bnf::tokenize(&mut i.grammar, "HI bob9 !").unwrap();
+        let ast = ast::parse(&i.grammar, "greeting", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).unwrap();
+        assert_eq!(ast::ast_to_shape_string(&ast), "++.-+.-.-");
+        
+        let prose = from_abnf("a = b\nb = <some prose>", None).unwrap();
+        assert!(prose.bnf.contains("b ::=\n    !hook(some_prose)\n") && prose.notes.len() == 1);
+        let e = from_abnf("a = b\na = c\nb = \"x\"\nc = \"y\"", None).err().unwrap();
+        assert!(e.kind == bnf::GrammarErrorKind::DuplicateRule && e.line == 2);
+        
+        let ebnf = r#"
+/* A small expression language */
+[1] Program ::= Statement*
+[2] Statement ::= 'let' S Name S? '=' S? Expr S? ';'
+              | 'print' S Expr S? ';'
+[3] Expr ::= Term (S? ('+' | '-') S? Term)*
+[4] Term ::= Number | Name | '(' S? Expr S? ')'
+[5] Number ::= [0-9]+ ('.' [0-9]+)?
+[6] Name ::= [a-zA-Z_] [a-zA-Z_0-9]* - Keyword
+[7] Keyword ::= 'let' | 'print'
+[8] S ::= (#x20 | #x9 | #xD | #xA)+
+"#;
+        let mut i = from_w3c_ebnf(ebnf, None).unwrap();
+        // peeks come from the tokens that each alternation can start with
+        assert!(i.bnf.contains("Program__rep1 ::=\n    @peekr(0, R`let|print`r) Statement $become Program__rep1\n    | #empty\n"));
+        assert!(i.bnf.contains("Statement ::=\n    @peek(0, \"let\") \"let\" Name \"=\" Expr \";\"\n    | \"print\" Expr \";\"\n"));
+        assert!(i.bnf.contains("Expr__rep1 ::=\n    @peekr(0, R`\\+|\\-`r) Expr__rep1__group1 $hoist Term $become Expr__rep1\n"));
+        assert!(i.bnf.contains("    | @peekr(0, R`(?:[a-zA-Z_][a-zA-Z_0-9]*)`r) Name\n    | \"(\" Expr \")\"\n"));
+        assert!(i.conflicts.is_empty());
+        assert_eq!(i.notes[1..], ["Keyword: only used inside tokens, so they're part of those instead of rules of their own", "S: only match whitespace, which the tokenizer skips, so they were left out"]);
+        let tokens = bnf::tokenize(&mut i.grammar, "let x = 1 + 2.5; print (x - 3);").unwrap();
+        let ast = ast::parse(&i.grammar, "Program", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).unwrap();
+        assert_eq!(ast::ast_to_shape_string(&ast), "++.+.-.+++.--.++.---.-+.++.+++.--.++.---.--.--");
+        
+        // two alternations starting with the same token can't be told apart with a peek
+        let i = from_w3c_ebnf(&ebnf.replace("S? ')'", "S? ')' | Name '[' S? Expr S? ']'"), None).unwrap();
+        assert_eq!(i.conflicts.len(), 1);
+        assert!(i.report().contains("Term alt 1 needs a guard: it can start with r`[a-zA-Z_][a-zA-Z_0-9]*`r, like alt 3"));
+        
+        let e = from_w3c_ebnf(ebnf, Some(&["Number", "Expr"])).err().unwrap();
+        assert_eq!(e.rule.as_deref(), Some("Expr"));
+        let e = from_w3c_ebnf("a ::= 'x' | (", None).err().unwrap();
+        assert!(e.kind == bnf::GrammarErrorKind::Syntax && e.column == 14);
+    }
+    
+    #[test]
+    fn test_infer_peeks() {
+        use crate::*;
+        use std::rc::Rc;
+        pub use bnf::*;
+        
+        let grammar_source = r#"
+    __INFER_PEEKS ::= stmt
+    prog ::= $infer_peeks ( stmt )*
+    stmt ::= name "=" expr ";" | name "(" ")" ";" | "?" expr ";"
+    expr ::= $infer_peeks term ( ( "+" | "-" ) term )*
+    term ::= @auto r`[0-9]+`r | @auto "(" expr ")" | name
+    name ::= r`[a-z]+`r
+        "#;
+        let mut g = bnf_to_grammar(&grammar_source).unwrap();
+        let bnf = g.to_bnf();
+        // the first tokens overlap, so it peeks at the second one
+        assert!(bnf.contains("stmt ::=\n    @peek(1, \"=\") name \"=\" expr \";\"\n    | @peekr(0, R`(?:[a-z]+)`r) name \"(\" \")\" \";\"\n    | \"?\" expr \";\"\n"));
+        // EBNF groups in the rule are inferred too, so they don't need predicates
+        assert!(bnf.contains("@peek(0, \"+\") \"+\"\n    | \"-\"\n"));
+        assert!(bnf.contains("@peekr(0, R`\\+|\\-`r)"));
+        let tokens = tokenize(&mut g, "x = (1 + y) - 2; f(); ? x;").unwrap();
+        let ast = ast::parse(&g, "prog", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).unwrap();
+        assert_eq!(ast::ast_to_shape_string(&ast), "+++.-.++.++.-.++.---.-.+.--.-++.-...-+.+++.---.--");
+        
+        let e = bnf_to_grammar("S ::= name \"=\" | $infer_peeks name \"=\" \"=\" | name\nname ::= r`[a-z]+`r").err().unwrap();
+        assert_eq!(e.kind, GrammarErrorKind::PeekConflict);
+        assert_eq!(e.err_message, "Can't infer peeks: S alt 0 needs a guard: it can start with r`[a-z]+`r, like alt 1");
+        assert_eq!((e.line, e.column), (1, 18));
+        let e = bnf_to_grammar("__INFER_PEEKS ::= nope\nS ::= \"x\"").err().unwrap();
+        assert_eq!(e.kind, GrammarErrorKind::UnknownRule);
+        let g = bnf_to_grammar("__INFER_PEEKS ::= *\nS ::= \"x\" | \"y\" | T\nT ::= \"z\" | \"w\"").unwrap();
+        assert!(g.to_bnf().contains("T ::=\n    @peek(0, \"z\") \"z\"\n    | \"w\"\n"));
+        let g = builder::GrammarBuilder::new().rule("S").alt(|a| a.infer_peeks().lit("x")).alt(|a| a.lit("y")).build().unwrap();
+        assert!(g.to_bnf().contains("S ::=\n    @peek(0, \"x\") \"x\"\n"));
+    }
+    
+    #[test]
+    fn test_builder() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        use builder::*;
+        
+        // the same grammar, written both ways, should parse the same way
+        let grammar_source = r#"
+    __COMMENTS ::= "//"
+    __BRACKET_PAIRS ::= ( )
+    sum ::= product $become sum_tail
+    sum_tail ::= @auto r`[+\-]`r product $become sum_tail | #empty
+    product ::= atom $become product_tail
+    product_tail ::= @auto r`\*`r atom $become product_tail | #empty
+    atom ::= @peek(0, "(") $pruned "(" sum ")" | r`[0-9]+`r
+        "#;
+        let mut g1 = bnf_to_grammar(&grammar_source).unwrap();
+        let mut b = GrammarBuilder::new().comment("//").bracket_pair("(", ")");
+        // rules can be generated from tables
+        for (rule, next, ops) in [("sum", "product", "[+\\-]"), ("product", "atom", "\\*")]
+        {
+            let tail = format!("{rule}_tail");
+            b = b.rule(rule).alt(|a| a.rule(next).become_(&tail))
+                .rule(&tail).alt(|a| a.auto_regex(ops).rule(next).become_(&tail)).alt(|a| a);
+        }
+        let mut g2 = b.rule("atom")
+            .alt(|a| a.peek(0, "(").pruned().lit("(").rule("sum").lit(")"))
+            .alt(|a| a.regex("[0-9]+"))
+            .build().unwrap();
+        
+        use std::rc::Rc;
+        let run = |g : &mut Grammar|
+        {
+            let tokens = tokenize(g, "1 + (2 - 3) * 4 // five").unwrap();
+            let ast = parse(g, "sum", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).unwrap();
+            let mut s = String::new();
+            debugger::ast_to_sexpr(&ast, g, &mut s);
+            s
+        };
+        assert_eq!(run(&mut g1), run(&mut g2));
+        
+        assert!(GrammarBuilder::new().rule("a").alt(|a| a.rule("b")).build().err().unwrap().to_string().contains("Not a defined grammar rule"));
+        assert!(GrammarBuilder::new().alt(|a| a.lit("x")).build().is_err());
+        assert!(GrammarBuilder::new().rule("a").alt(|a| a.lit("x")).rule("a").build().err().unwrap().to_string().contains("Duplicate rule"));
+        // comment starters are quoted like literals
+        let g = GrammarBuilder::new().comment("#\"").comment("\\").rule("a").alt(|a| a.lit("x")).build().unwrap();
+        assert_eq!(g.comments, ["#\"", "\\"]);
+    }
+    
+    #[test]
+    fn test_to_bnf() {
+        use crate::*;
+        pub use bnf::*;
+        
+        for fname in ["src/grammar_c.txt", "src/grammar_json.txt"]
+        {
+            let g = bnf_to_grammar(&std::fs::read_to_string(fname).unwrap()).unwrap();
+            let bnf = g.to_bnf();
+            let g2 = bnf_to_grammar(&bnf).unwrap();
+            // canonical output is stable
+            assert_eq!(bnf, g2.to_bnf());
+            assert_eq!(g.points.len(), g2.points.len());
+            assert_eq!(g.literals, g2.literals);
+            let mut lexed = g.regexes.iter().map(|r| r.0.as_str().to_string()).collect::<Vec<_>>();
+            let mut lexed2 = g2.regexes.iter().map(|r| r.0.as_str().to_string()).collect::<Vec<_>>();
+            lexed.sort();
+            lexed2.sort();
+            assert_eq!(lexed, lexed2);
+        }
+        
+        let grammar_source = r#"
+    __COMMENTS ::= "//"
+    __COMMENT_PAIRS ::= /* */
+    __COMMENT_REGEXES ::= r`--[^\n]*`r
+    __BRACKET_PAIRS ::= ( )
+    __RESERVED_WORDS ::= if else
+    S ::= @auto "x" | @peek(0, "y") $any | @peekres(-1, A`[a-z]`r) R`[a-z]+`r | @auto r`[0-9]+`r $pruned | L
+    L ::= list("\"", ",")
+    list(X, SEP) ::= X $become list_tail(X, SEP) @recover_before r`;`r
+    list_tail(X, SEP) ::= @auto SEP X $become list_tail(X, SEP) | #empty
+        "#;
+        let g = bnf_to_grammar(&grammar_source).unwrap();
+        let bnf = g.to_bnf();
+        assert!(bnf.contains("__RESERVED_WORDS ::= if else"));
+        assert!(bnf.contains(r#"list_tail<"\"",","> ::="#));
+        assert!(bnf.contains(r#"| @auto r`[0-9]+`r $pruned"#));
+        assert!(bnf.contains(r#"| @peek(0, "y") $any"#));
+        assert!(bnf.contains(r#"@recover_before R`;`r"#));
+        assert_eq!(bnf, bnf_to_grammar(&bnf).unwrap().to_bnf());
+        
+        // comment starters with quotes and backslashes survive the round trip
+        let g = bnf_to_grammar(r##"__COMMENTS ::= "//" "#\"" "\\"
+    S ::= "x""##).unwrap();
+        assert_eq!(g.comments, ["//", "#\"", "\\"]);
+        let bnf = g.to_bnf();
+        assert!(bnf.contains(r##"__COMMENTS ::= "//" | "#\"" | "\\""##));
+        assert_eq!(bnf_to_grammar(&bnf).unwrap().comments, g.comments);
+        
+        // formatting the bundled grammars is stable and doesn't change what they mean
+        for fname in ["src/grammar_c.txt", "src/grammar_json.txt", "src/grammar_json_simple.txt"]
+        {
+            let source = std::fs::read_to_string(fname).unwrap();
+            let formatted = bnf_format(&source).unwrap();
+            assert_eq!(bnf_format(&formatted).unwrap(), formatted);
+            assert_eq!(bnf_to_grammar(&formatted).unwrap().to_bnf(), bnf_to_grammar(&source).unwrap().to_bnf());
+        }
+        // escaped quotes in template instance names don't end the name
+        assert_eq!(bnf_format("S ::=  list<\"\\\"\",\",\">  x\n").unwrap(), "S ::= list<\"\\\"\",\",\"> x\n");
+        let messy = "%append  S ::=  x|y   # comment\n  # indented comment\nlist(X,SEP)  ::= X $become list( X ,SEP)\n\n\n  |@peek( 0 ,\"(\" )( a b )*  [c]\n";
+        assert_eq!(bnf_format(messy).unwrap(), "%append S ::= x | y # comment\n    # indented comment\nlist(X, SEP) ::= X $become list(X, SEP)\n\n    | @peek(0, \"(\") ( a b )* [ c ]\n");
+    }
+    
+    #[test]
+    fn test_serialize() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        use std::rc::Rc;
+        
+        let g = bnf_to_grammar(&std::fs::read_to_string("src/grammar_c.txt").unwrap()).unwrap();
+        let bytes = g.to_bytes();
+        let g2 = Grammar::from_bytes(&bytes).unwrap();
+        assert_eq!(g.to_bnf(), g2.to_bnf());
+        assert_eq!(g.string_cache_inv, g2.string_cache_inv);
+        assert_eq!(g.by_name, g2.by_name);
+        // saving again gives the same bytes, except for the order of the lexer regexes, which come from a hashmap
+        assert_eq!(g2.to_bytes().len(), bytes.len());
+        // overlays still work on a loaded grammar
+        assert!(g2.with_overlay("%append primary_expression ::= @auto \"__func__\"").is_ok());
+        
+        let mut g = bnf_to_grammar(&std::fs::read_to_string("src/grammar_json.txt").unwrap()).unwrap();
+        let mut g2 = Grammar::from_bytes(&g.to_bytes()).unwrap();
+        let run = |g : &mut Grammar|
+        {
+            let tokens = tokenize(g, r#"{"a": [1, 2.5e3, true, null], "b": {"c": "d\"e"}}"#).unwrap();
+            let ast = parse(g, "json", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).unwrap();
+            let mut s = String::new();
+            debugger::ast_to_sexpr(&ast, g, &mut s);
+            s
+        };
+        assert_eq!(run(&mut g), run(&mut g2));
+        
+        let bytes = g.to_bytes();
+        assert!(Grammar::from_bytes(&bytes[..bytes.len() - 1]).err().unwrap().to_string().contains("Truncated"));
+        assert!(Grammar::from_bytes(b"not a grammar").err().unwrap().to_string().contains("Not a precompiled grammar"));
+        let mut corrupt = bytes.clone();
+        corrupt.push(0);
+        assert!(Grammar::from_bytes(&corrupt).is_err());
+    }
+    
+    #[test]
+    fn test_grammar_macro() {
+        use crate::*;
+        pub use bnf::*;
+        
+        mod json { pred_recdec_macros::grammar!("src/grammar_json.txt", intern = ["true", "\\u"]); }
+        let mut g = json::grammar();
+        let g2 = bnf_to_grammar(&std::fs::read_to_string("src/grammar_json.txt").unwrap()).unwrap();
+        assert_eq!(g.to_bnf(), g2.to_bnf());
+        assert_eq!(json::rules::ELEMENT, g.by_name["element"]);
+        assert_eq!(json::rule_names::ELEMENT, g.points[json::rules::ELEMENT].name_id);
+        let mut lookup = |s : &str| string_cache_lookup(&mut g.string_cache, &mut g.string_cache_inv, s).1;
+        assert_eq!(json::literals::LBRACE, lookup("{"));
+        assert_eq!(json::literals::COMMA, lookup(","));
+        assert_eq!(json::literals::TRUE, lookup("true"));
+        assert_eq!(json::literals::BACKSLASH_U, lookup("\\u"));
+    }
+    
+    #[test]
+    fn test_codegen() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        use std::rc::Rc;
+        type HashMap<K, V> = std::collections::HashMap::<K, V, crate::HashBuilder>;
+        
+        mod json { pred_recdec_macros::grammar!("src/grammar_json.txt", native); }
+        mod test { pred_recdec_macros::grammar!("src/grammar_codegen_test.txt", native); }
+        
+        let mut g = json::grammar();
+        for input in [r#"{"a": [1, 2.5e3, true, null], "b": {"c": "d\"e"}, "f": []}"#, "[1, 2", "{\"a\" 1}", "[] []"]
+        {
+            let tokens = tokenize(&mut g, input).unwrap();
+            let expected = parse(&g, "json", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default()));
+            let native = json::parse(&g, "json", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default()));
+            assert_eq!(format!("{expected:?}"), format!("{native:?}"));
+        }
+        // no depth limit, same as parse
+        let deep = "[".repeat(5000) + &"]".repeat(5000);
+        let tokens = tokenize(&mut g, &deep).unwrap();
+        let native = json::parse(&g, "json", &tokens[..], Rc::new(<_>::default()), Rc::new(<_>::default())).unwrap();
+        assert_eq!(native.get_real_token_count() as usize, tokens.len());
+        
+        let mut guards = HashMap::<String, Guard>::default();
+        guards.insert("is_ok".to_string(), Rc::new(|global : &mut PrdGlobal, tokens : &[Token], i : usize|
+        {
+            match global.g.string_cache_inv[tokens[i].text as usize].as_str()
+            {
+                "bad" => GuardResult::Reject,
+                "9" => GuardResult::HardError("nine".to_string()),
+                _ => GuardResult::Accept,
+            }
+        }));
+        let mut hooks = HashMap::<String, Hook>::default();
+        hooks.insert("skip".to_string(), Rc::new(|global : &mut PrdGlobal, tokens : &[Token], i : usize, _ : &mut Vec<ASTNode>|
+        {
+            if global.g.string_cache_inv[tokens[i].text as usize].as_str() == "stop" { return Err("stopped".to_string()); }
+            Ok(1)
+        }));
+        let (guards, hooks) = (Rc::new(guards), Rc::new(hooks));
+        
+        let mut g = test::grammar();
+        let inputs = [
+            "1 2 ; x (1) (1, 2) let y = 3 {4 5} <1, 2> @abc ! skipped 7 [1, 2] [bad 1]",
+            "(1 2) 3", "let", "! stop 1", "[9]", "[bad]", "<1 2>", "{ 1 }", "@ 1",
+        ];
+        for input in inputs
+        {
+            let tokens = tokenize(&mut g, input).unwrap();
+            let expected = parse_recursive(&g, "S", &tokens[..], guards.clone(), hooks.clone());
+            let lifo = parse(&g, "S", &tokens[..], guards.clone(), hooks.clone());
+            assert_eq!(format!("{expected:?}"), format!("{lifo:?}"), "{input}");
+            let native = test::parse(&g, "S", &tokens[..], guards.clone(), hooks.clone());
+            assert_eq!(format!("{expected:?}"), format!("{native:?}"), "{input}");
+        }
+        let tokens = tokenize(&mut g, inputs[0]).unwrap();
+        assert!(test::parse(&g, "S", &tokens[..], guards.clone(), hooks.clone()).is_ok());
+        
+        // every kind of term shows up in the generated code
+        let source = codegen::generate_rust(&g);
+        assert!(source.contains("continue 'top;"));
+        assert!(source.contains("return Ok(Step::Call("));
+        assert!(source.lines().any(|l| l.contains("const RECOVER") && l.contains("Some((")));
+    }
+    
+    #[test]
+    fn test_required_guards_and_hooks() {
+        use crate::*;
+        pub use bnf::*;
+        pub use ast::*;
+        use std::rc::Rc;
+        type HashMap<K, V> = std::collections::HashMap::<K, V, crate::HashBuilder>;
+        
+        let mut g = bnf_to_grammar(r#"
+    S ::= @guard(first) "x" !hook(log) | "y" !hook(log) T
+    T ::= @guard(second) !hook(skip) | #empty
+        "#).unwrap();
+        assert_eq!(g.required_guards(), vec!("first", "second"));
+        assert_eq!(g.required_hooks(), vec!("log", "skip"));
+        
+        let mut guards = HashMap::<String, Guard>::default();
+        guards.insert("first".to_string(), Rc::new(|_, _, _| GuardResult::Accept));
+        guards.insert("second".to_string(), Rc::new(|_, _, _| GuardResult::Reject));
+        let mut hooks = HashMap::<String, Hook>::default();
+        hooks.insert("log".to_string(), Rc::new(|_, _, _, _| Ok(0)));
+        
+        // fails before parsing anything, even though the input never reaches T
+        let tokens = tokenize(&mut g, "x").unwrap();
+        for e in [
+            parse(&g, "S", &tokens[..], Rc::new(guards.clone()), Rc::new(hooks.clone())).unwrap_err(),
+            parse_recursive(&g, "S", &tokens[..], Rc::new(guards.clone()), Rc::new(hooks.clone())).unwrap_err(),
+        ]
+        {
+            assert_eq!(e.err_message, "Guards and hooks used by the grammar but not given to the parser: hook skip (first used in T)");
+            assert_eq!((e.token_index, e.rule, e.in_alt, e.alt_progress), (0, g.points[1].name_id, 0, Some(1)));
+        }
+        assert!(check_guards_and_hooks(&g, &<_>::default(), &hooks).unwrap_err().err_message.contains("guard first, guard second"));
+        
+        // extras are fine, but can be listed
+        hooks.insert("skip".to_string(), Rc::new(|_, _, _, _| Ok(1)));
+        hooks.insert("unused".to_string(), Rc::new(|_, _, _, _| Ok(0)));
+        hooks.insert("init".to_string(), Rc::new(|_, _, _, _| Ok(0)));
+        guards.insert("spare".to_string(), Rc::new(|_, _, _| GuardResult::Accept));
+        assert_eq!(unused_guards_and_hooks(&g, &guards, &hooks), vec!("guard spare", "hook unused"));
+        assert!(parse(&g, "S", &tokens[..], Rc::new(guards), Rc::new(hooks)).is_ok());
+    }
+}
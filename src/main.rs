@@ -2,15 +2,8 @@ use mimalloc::MiMalloc;
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-pub use rustc_hash::FxBuildHasher as HashBuilder;
-
-mod bnf;
-use bnf::*;
-
-mod ast;
-use ast::*;
-
-mod json;
+use pred_recdec::bnf::*;
+use pred_recdec::ast::*;
 
 fn main() {
     let grammar_source = r#"
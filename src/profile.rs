@@ -0,0 +1,239 @@
+// Parse profiling
+
+use crate::bnf::*;
+use crate::trace::*;
+use std::time::{Duration, Instant};
+
+type HashMap<K, V> = std::collections::HashMap::<K, V, crate::HashBuilder>;
+
+/// Time and call count for one rule, guard, hook, or regex. See [`Profiler`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProfileEntry {
+    /// How many times it was called (or, for regexes, how many times the cache missed).
+    pub calls : u64,
+    /// Time spent inside of it, excluding time spent in children. For rules, children are other rules, guards, and hooks.
+    pub self_time : Duration,
+    /// Time spent inside of it, including time spent in children. For recursive rules, nested calls are counted more than once.
+    pub total_time : Duration,
+}
+
+struct Frame {
+    rule : usize,
+    // node in the call stack tree; frames past the depth limit share their ancestor's node
+    node : usize,
+    clamped : bool,
+    // when the rule was entered, and when the current $become segment started
+    entered : Instant,
+    segment : Instant,
+    // time spent in children during the current segment
+    child : Duration,
+}
+
+// Folded stacks repeat the whole stack on every line, so deep recursion would make them quadratic in size.
+const FOLDED_DEPTH_LIMIT : usize = 256;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum StackLabel { Rule(usize), Leaf(usize) }
+
+/// [`ParseObserver`] that measures where parse time goes: per rule, per guard (leading predicates), and per hook.
+///
+/// Like [`Coverage`](`super::coverage::Coverage`), this accumulates across any number of parses. Measuring adds overhead to every rule entry and exit, so absolute times are inflated; compare them against each other, not against unprofiled parse times.
+///
+/// [`Profiler::report`] produces a sorted table, and [`Profiler::folded_stacks`] produces input for flamegraph tools (e.g. `inferno-flamegraph` or `flamegraph.pl`).
+///
+/// Regex cache misses are tracked by the grammar's regexes themselves (see [`RegexCacher::cache_misses`]). The report includes them for every regex in the grammar, counted since the grammar was built.
+///
+/// ```
+/// # {} /*
+/// let mut prof = Profiler::new(&g);
+/// let _ = parse_with_observer(&g, "S", &tokens[..], guards.clone(), hooks.clone(), &mut prof);
+/// print!("{}", prof.report(&g));
+/// std::fs::write("parse.folded", prof.folded_stacks(&g)).unwrap();
+/// # */
+/// ```
+#[derive(Default)]
+pub struct Profiler {
+    /// Per-rule entries. Index = grammar point ID. A `$become` counts as a call of the target rule.
+    pub rules : Vec<ProfileEntry>,
+    /// Per-guard entries, by the alternation's first predicate (e.g. `@guard(is_declaration_indicator)` or `@peek(0, "(")`). Alternations with several leading predicates are counted under the first one.
+    pub guards : HashMap<String, ProfileEntry>,
+    /// Per-hook entries, by hook name.
+    pub hooks : HashMap<String, ProfileEntry>,
+    
+    // description of each alternation's first term, which is what guards are named by
+    guard_names : Vec<Vec<String>>,
+    stack : Vec<Frame>,
+    // tree of every distinct call stack seen so far, with self time per node, for flamegraphs
+    // node 0 is the root, which doesn't correspond to any rule
+    nodes : Vec<(usize, StackLabel, Duration, usize)>,
+    node_children : HashMap<(usize, StackLabel), usize>,
+    leaf_names : Vec<String>,
+    leaf_ids : HashMap<String, usize>,
+}
+
+impl Profiler {
+    /// Create an empty profile sized for the given grammar. Also turns on timing of the grammar's regex cache misses (see [`RegexCacher::set_miss_timing`]), which stays on afterwards.
+    pub fn new(g : &Grammar) -> Self
+    {
+        for_each_regex(g, |r| r.set_miss_timing(true));
+        Self {
+            rules : vec!(<_>::default(); g.points.len()),
+            guard_names : g.points.iter().map(|p| p.forms.iter().map(|f| f.matching_terms.first().map(|t| t.describe(g)).unwrap_or_default()).collect()).collect(),
+            nodes : vec!((0, StackLabel::Rule(0), Duration::ZERO, 0)),
+            ..<_>::default()
+        }
+    }
+    fn stack_node(&mut self, parent : usize, label : StackLabel) -> usize
+    {
+        let len = self.nodes.len();
+        let node = *self.node_children.entry((parent, label)).or_insert(len);
+        if node == len { self.nodes.push((parent, label, Duration::ZERO, self.nodes[parent].3 + 1)); }
+        node
+    }
+    fn close_segment(&mut self, now : Instant)
+    {
+        let frame = self.stack.last().unwrap();
+        let self_time = (now - frame.segment).saturating_sub(frame.child);
+        self.rules[frame.rule].self_time += self_time;
+        self.nodes[frame.node].2 += self_time;
+    }
+    fn add_leaf(&mut self, leaf : &str, elapsed : Duration)
+    {
+        let leaf = match self.leaf_ids.get(leaf)
+        {
+            Some(id) => *id,
+            None =>
+            {
+                self.leaf_names.push(leaf.to_string());
+                self.leaf_ids.insert(leaf.to_string(), self.leaf_names.len() - 1);
+                self.leaf_names.len() - 1
+            }
+        };
+        let parent = self.stack.last().map(|f| f.node).unwrap_or(0);
+        let node = if self.nodes[parent].3 >= FOLDED_DEPTH_LIMIT { parent } else { self.stack_node(parent, StackLabel::Leaf(leaf)) };
+        self.nodes[node].2 += elapsed;
+        if let Some(frame) = self.stack.last_mut() { frame.child += elapsed; }
+    }
+    /// Format the profile as a human-readable table, sorted by self time, using the given grammar for names. Format is not guaranteed and may change arbitrarily.
+    pub fn report(&self, g : &Grammar) -> String
+    {
+        fn table<'a>(s : &mut String, title : &str, mut entries : Vec<(String, &'a ProfileEntry)>)
+        {
+            entries.retain(|x| x.1.calls > 0);
+            entries.sort_by(|a, b| b.1.self_time.cmp(&a.1.self_time).then(b.1.calls.cmp(&a.1.calls)));
+            *s += &format!("{title}:\n");
+            *s += &format!("  {:>12} {:>12} {:>12}  {}\n", "self", "total", "calls", "name");
+            for (name, e) in entries
+            {
+                *s += &format!("  {:>12} {:>12} {:>12}  {}\n", format!("{:.3?}", e.self_time), format!("{:.3?}", e.total_time), e.calls, name);
+            }
+        }
+        
+        let mut s = String::new();
+        table(&mut s, "Rules", self.rules.iter().enumerate().map(|(i, e)| (g.points[i].name.to_string(), e)).collect());
+        table(&mut s, "Guards", self.guards.iter().map(|(k, e)| (k.clone(), e)).collect());
+        table(&mut s, "Hooks", self.hooks.iter().map(|(k, e)| (k.clone(), e)).collect());
+        
+        let mut regexes = HashMap::<String, ProfileEntry>::default();
+        for_each_regex(g, |r|
+        {
+            // copies of the same regex share their counter
+            let (calls, time) = r.cache_misses();
+            regexes.insert(r.pattern().to_string(), ProfileEntry { calls, self_time : time, total_time : time });
+        });
+        table(&mut s, "Regex cache misses", regexes.iter().map(|(k, e)| (format!("{k:?}"), e)).collect());
+        s
+    }
+    /// Self time per call stack, in the "folded stacks" format used by flamegraph tools: one `rule;rule;rule <nanoseconds>` line per distinct stack. Guards and hooks show up as leaf frames. Stacks are cut off at a depth of 256; time spent deeper than that is attributed to the deepest frame that was kept.
+    pub fn folded_stacks(&self, g : &Grammar) -> String
+    {
+        let mut lines = Vec::new();
+        for (i, (_, _, t, _)) in self.nodes.iter().enumerate().skip(1)
+        {
+            if t.is_zero() { continue; }
+            let mut names = Vec::new();
+            let mut node = i;
+            while node != 0
+            {
+                let (parent, label, _, _) = &self.nodes[node];
+                names.push(match label
+                {
+                    StackLabel::Rule(r) => g.points[*r].name.as_str(),
+                    StackLabel::Leaf(l) => self.leaf_names[*l].as_str(),
+                });
+                node = *parent;
+            }
+            names.reverse();
+            lines.push(format!("{} {}", names.join(";"), t.as_nanos()));
+        }
+        lines.sort();
+        lines.join("\n") + "\n"
+    }
+}
+
+impl ParseObserver for Profiler {
+    fn rule_entered(&mut self, rule : usize, _token : usize)
+    {
+        let now = Instant::now();
+        self.rules[rule].calls += 1;
+        let parent = self.stack.last().map(|f| f.node).unwrap_or(0);
+        let clamped = self.nodes[parent].3 >= FOLDED_DEPTH_LIMIT;
+        let node = if clamped { parent } else { self.stack_node(parent, StackLabel::Rule(rule)) };
+        self.stack.push(Frame { rule, node, clamped, entered : now, segment : now, child : Duration::ZERO });
+    }
+    fn became(&mut self, _rule : usize, target : usize, _token : usize)
+    {
+        let now = Instant::now();
+        self.close_segment(now);
+        self.rules[target].calls += 1;
+        let last = self.stack.last().unwrap();
+        let node = if last.clamped { last.node } else { self.stack_node(self.nodes[last.node].0, StackLabel::Rule(target)) };
+        let frame = self.stack.last_mut().unwrap();
+        self.rules[frame.rule].total_time += now - frame.segment;
+        frame.rule = target;
+        frame.node = node;
+        frame.segment = now;
+        frame.child = Duration::ZERO;
+    }
+    fn rule_exited(&mut self, _rule : usize, _token_start : usize, _token_end : usize, _ok : bool)
+    {
+        let now = Instant::now();
+        self.close_segment(now);
+        let frame = self.stack.pop().unwrap();
+        self.rules[frame.rule].total_time += now - frame.segment;
+        if let Some(parent) = self.stack.last_mut() { parent.child += now - frame.entered; }
+    }
+    fn wants_timing(&self) -> bool { true }
+    fn guard_timed(&mut self, rule : usize, alt : usize, elapsed : Duration)
+    {
+        let name = std::mem::take(&mut self.guard_names[rule][alt]);
+        add_leaf_entry(&mut self.guards, &name, elapsed);
+        self.add_leaf(&name, elapsed);
+        self.guard_names[rule][alt] = name;
+    }
+    fn hook_timed(&mut self, _rule : usize, name : &str, elapsed : Duration)
+    {
+        add_leaf_entry(&mut self.hooks, name, elapsed);
+        self.add_leaf(&format!("!hook({name})"), elapsed);
+    }
+}
+
+fn add_leaf_entry(map : &mut HashMap<String, ProfileEntry>, name : &str, elapsed : Duration)
+{
+    if !map.contains_key(name) { map.insert(name.to_string(), <_>::default()); }
+    let entry = map.get_mut(name).unwrap();
+    entry.calls += 1;
+    entry.self_time += elapsed;
+    entry.total_time += elapsed;
+}
+
+fn for_each_regex(g : &Grammar, mut f : impl FnMut(&RegexCacher))
+{
+    for term in g.points.iter().flat_map(|p| p.forms.iter()).flat_map(|f| f.matching_terms.iter())
+    {
+        if let MatchingTermE::TermRegex(r) | MatchingTermE::PeekR(_, r) | MatchingTermE::PeekRes(_, r) = &term.t
+        {
+            f(r);
+        }
+    }
+}
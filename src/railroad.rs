@@ -0,0 +1,336 @@
+// Railroad diagram generation
+
+use crate::bnf::*;
+
+// Layout sizes, in pixels.
+const BOX_HEIGHT : f64 = 22.0;
+const CHAR_WIDTH : f64 = 8.0;
+const GAP : f64 = 10.0;
+const ARC : f64 = 10.0;
+const VGAP : f64 = 8.0;
+const PAD : f64 = 20.0;
+
+// Longest label before it's cut short (the full text is in a tooltip).
+const MAX_LABEL : usize = 40;
+
+#[derive(Clone, Debug)]
+enum Node {
+    // (label, CSS class, link to a rule, tooltip)
+    Box(String, &'static str, Option<String>, Option<String>),
+    Seq(Vec<Node>),
+    // first option is on the main line, the others branch off below it
+    Choice(Vec<Node>),
+    // one or more times, with the way back below
+    Loop(Box<Node>),
+    Skip,
+}
+
+impl Node {
+    // (width, height above the main line, height below it)
+    fn size(&self) -> (f64, f64, f64)
+    {
+        match self
+        {
+            Node::Box(label, ..) => (label.chars().count() as f64 * CHAR_WIDTH + 2.0 * GAP, BOX_HEIGHT / 2.0, BOX_HEIGHT / 2.0),
+            Node::Seq(items) =>
+            {
+                let sizes = items.iter().map(|x| x.size()).collect::<Vec<_>>();
+                let width = sizes.iter().map(|x| x.0).sum::<f64>() + GAP * sizes.len().saturating_sub(1) as f64;
+                (width, sizes.iter().map(|x| x.1).fold(0.0, f64::max), sizes.iter().map(|x| x.2).fold(0.0, f64::max))
+            }
+            Node::Choice(items) =>
+            {
+                let sizes = items.iter().map(|x| x.size()).collect::<Vec<_>>();
+                let width = sizes.iter().map(|x| x.0).fold(0.0, f64::max) + 4.0 * ARC;
+                let down = choice_offsets(&sizes).last().unwrap() + sizes.last().unwrap().2;
+                (width, sizes[0].1, down)
+            }
+            Node::Loop(item) =>
+            {
+                let (width, up, down) = item.size();
+                (width + 2.0 * ARC, up, (down + VGAP.max(ARC)).max(2.0 * ARC))
+            }
+            Node::Skip => (0.0, 0.0, 0.0),
+        }
+    }
+    // Draws the node with its main line entering at (x, y).
+    fn render(&self, out : &mut String, x : f64, y : f64)
+    {
+        match self
+        {
+            Node::Box(label, class, link, title) =>
+            {
+                let (width, ..) = self.size();
+                let rx = if *class == "terminal" { BOX_HEIGHT / 2.0 } else { 0.0 };
+                if let Some(link) = link { *out += &format!("<a href=\"#rule-{}\">", escape(link)); }
+                *out += &format!("<g class=\"{class}\">");
+                if let Some(title) = title { *out += &format!("<title>{}</title>", escape(title)); }
+                *out += &format!("<rect x=\"{x}\" y=\"{}\" width=\"{width}\" height=\"{BOX_HEIGHT}\" rx=\"{rx}\"/>", y - BOX_HEIGHT / 2.0);
+                *out += &format!("<text x=\"{}\" y=\"{}\">{}</text></g>", x + width / 2.0, y + 4.0, escape(label));
+                if link.is_some() { *out += "</a>"; }
+            }
+            Node::Seq(items) =>
+            {
+                let mut x = x;
+                for (i, item) in items.iter().enumerate()
+                {
+                    if i > 0
+                    {
+                        *out += &format!("<path d=\"M{x} {y}h{GAP}\"/>");
+                        x += GAP;
+                    }
+                    item.render(out, x, y);
+                    x += item.size().0;
+                }
+            }
+            Node::Choice(items) =>
+            {
+                let (width, ..) = self.size();
+                let end = x + width;
+                let offsets = choice_offsets(&items.iter().map(|x| x.size()).collect::<Vec<_>>());
+                for (i, item) in items.iter().enumerate()
+                {
+                    let item_width = item.size().0;
+                    let item_y = y + offsets[i];
+                    if i > 0
+                    {
+                        let v = offsets[i] - 2.0 * ARC;
+                        *out += &format!("<path d=\"M{x} {y}a{ARC} {ARC} 0 0 1 {ARC} {ARC}v{v}a{ARC} {ARC} 0 0 0 {ARC} {ARC}\"/>");
+                        *out += &format!("<path d=\"M{} {item_y}a{ARC} {ARC} 0 0 0 {ARC} -{ARC}v-{v}a{ARC} {ARC} 0 0 1 {ARC} -{ARC}\"/>", end - 2.0 * ARC);
+                    }
+                    else
+                    {
+                        *out += &format!("<path d=\"M{x} {y}h{}\"/>", 2.0 * ARC);
+                        *out += &format!("<path d=\"M{} {y}h{}\"/>", end - 2.0 * ARC, 2.0 * ARC);
+                    }
+                    item.render(out, x + 2.0 * ARC, item_y);
+                    *out += &format!("<path d=\"M{} {item_y}H{}\"/>", x + 2.0 * ARC + item_width, end - 2.0 * ARC);
+                }
+            }
+            Node::Loop(item) =>
+            {
+                let (width, _, down) = item.size();
+                let back = (down + VGAP.max(ARC) - 2.0 * ARC).max(0.0);
+                *out += &format!("<path d=\"M{x} {y}h{ARC}\"/>");
+                item.render(out, x + ARC, y);
+                *out += &format!("<path d=\"M{} {y}h{ARC}\"/>", x + ARC + width);
+                // the way back, from the end of the item to its start
+                *out += &format!("<path class=\"back\" d=\"M{} {y}a{ARC} {ARC} 0 0 1 {ARC} {ARC}v{back}a{ARC} {ARC} 0 0 1 -{ARC} {ARC}H{}a{ARC} {ARC} 0 0 1 -{ARC} -{ARC}v-{back}a{ARC} {ARC} 0 0 1 {ARC} -{ARC}\"/>",
+                    x + ARC + width, x + ARC);
+            }
+            Node::Skip => {}
+        }
+    }
+}
+
+// How far below the main line each option of a choice goes, leaving room for the curves that lead to it.
+fn choice_offsets(sizes : &[(f64, f64, f64)]) -> Vec<f64>
+{
+    let mut ret = vec!(0.0);
+    for i in 1..sizes.len()
+    {
+        let prev = ret[i - 1] + sizes[i - 1].2;
+        ret.push((prev + VGAP + sizes[i].1).max(ret[i - 1] + 2.0 * ARC));
+    }
+    ret
+}
+
+fn escape(text : &str) -> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Label for a box, cut short if it's too long, and the full text as a tooltip if it was.
+fn make_box(text : String, class : &'static str, link : Option<String>) -> Node
+{
+    if text.chars().count() <= MAX_LABEL { return Node::Box(text, class, link, None); }
+    let short = text.chars().take(MAX_LABEL - 1).collect::<String>() + "…";
+    Node::Box(short, class, link, Some(text))
+}
+
+// How a regex was written in the grammar.
+fn regex_label(r : &RegexCacher) -> String
+{
+    let p = r.pattern().strip_prefix("\\A(?:").unwrap_or(r.pattern());
+    let p = p.strip_suffix(")\\z").or_else(|| p.strip_suffix(")")).unwrap_or(p);
+    format!("r`{p}`r")
+}
+
+fn peek_label(n : isize, what : String) -> String
+{
+    match n
+    {
+        0 => format!("next is {what}"),
+        n => format!("token {n:+} is {what}"),
+    }
+}
+
+// Diagram of one alternation, and whether it ends by looping back to the start of its rule (`$become` itself).
+fn alt_node(g : &Grammar, rule : usize, alt : &Alternation) -> (Node, bool)
+{
+    let terms = &alt.matching_terms;
+    let string = |id : u32| format!("{:?}", g.string_cache_inv[id as usize]);
+    let mut items = vec!();
+    let mut loops = false;
+    let mut i = 0;
+    while i < terms.len()
+    {
+        let next = terms.get(i + 1).map(|t| &t.t);
+        let next_is_any = matches!(next, Some(MatchingTermE::Directive(MatchDirective::Any)));
+        match &terms[i].t
+        {
+            // `@auto x` is stored as `@peek(0, x) $any`, which just matches x
+            MatchingTermE::Peek(0, s) if next_is_any => { items.push(make_box(string(*s), "terminal", None)); i += 1; }
+            MatchingTermE::PeekR(0, r) if next_is_any => { items.push(make_box(regex_label(r), "terminal", None)); i += 1; }
+            // `@peek(0, x) x` only needs to show x
+            MatchingTermE::Peek(0, s) if matches!(next, Some(MatchingTermE::TermLit(s2)) if s2 == s) => {}
+            MatchingTermE::Peek(n, s) => items.push(make_box(peek_label(*n, string(*s)), "predicate", None)),
+            MatchingTermE::PeekR(n, r) => items.push(make_box(peek_label(*n, regex_label(r)), "predicate", None)),
+            MatchingTermE::PeekRes(n, r) => items.push(make_box(peek_label(*n, format!("{} (not reserved)", regex_label(r))), "predicate", None)),
+            MatchingTermE::Guard(name) => items.push(make_box(format!("if {name}"), "predicate", None)),
+            MatchingTermE::Eof => items.push(make_box("end of input".to_string(), "predicate", None)),
+            MatchingTermE::Hook(name) => items.push(make_box(format!("hook {name}"), "hook", None)),
+            MatchingTermE::TermLit(s) => items.push(make_box(string(*s), "terminal", None)),
+            MatchingTermE::TermRegex(r) => items.push(make_box(regex_label(r), "terminal", None)),
+            MatchingTermE::Rule(id) => items.push(make_box(g.points[*id].name.to_string(), "rule", Some(g.points[*id].name.to_string()))),
+            MatchingTermE::Directive(MatchDirective::Any) => items.push(make_box("any token".to_string(), "terminal", None)),
+            MatchingTermE::Directive(MatchDirective::Become | MatchDirective::BecomeAs) =>
+            {
+                match next
+                {
+                    Some(MatchingTermE::Rule(id)) if *id == rule => loops = true,
+                    Some(MatchingTermE::Rule(id)) =>
+                    {
+                        let name = g.points[*id].name.to_string();
+                        items.push(make_box(format!("then {name}"), "rule", Some(name)));
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            // only changes the AST node's name
+            MatchingTermE::Directive(MatchDirective::Rename) => i += 1,
+            // only change the shape of the AST
+            MatchingTermE::Directive(_) | MatchingTermE::_AutoTemp => {}
+        }
+        i += 1;
+    }
+    let node = match items.len()
+    {
+        0 => Node::Skip,
+        1 => items.pop().unwrap(),
+        _ => Node::Seq(items),
+    };
+    (node, loops)
+}
+
+fn choice(mut items : Vec<Node>) -> Node
+{
+    if items.len() == 1 { items.pop().unwrap() } else { Node::Choice(items) }
+}
+
+// Diagram of a whole rule. Alternations that `$become` the rule itself can be taken any number of times before one of the others.
+fn rule_node(g : &Grammar, rule : usize) -> Node
+{
+    let mut loops = vec!();
+    let mut ends = vec!();
+    for alt in &g.points[rule].forms
+    {
+        let (node, is_loop) = alt_node(g, rule, alt);
+        if is_loop { loops.push(node); } else { ends.push(node); }
+    }
+    if loops.is_empty() { return choice(ends); }
+    let repeat = Node::Loop(Box::new(choice(loops)));
+    if ends.is_empty() { return repeat; }
+    let repeat = Node::Choice(vec!(Node::Skip, repeat));
+    match choice(ends)
+    {
+        Node::Skip => repeat,
+        end => Node::Seq(vec!(repeat, end)),
+    }
+}
+
+/// Draws a railroad diagram of one grammar point (by ID) as a standalone SVG image.
+///
+/// Literals and regexes are round boxes, other rules are square boxes, predicates (`@peek`, `@guard`, etc) are dashed boxes, and hooks are dotted boxes. Alternations that end with `$become` back into the same rule are drawn as a loop. `@auto x` is drawn as just `x`. Directives that only change the shape of the AST aren't drawn. Boxes for other rules link to `#rule-NAME`, which is where [`generate_html`] puts them.
+///
+/// The SVG doesn't have any styles of its own; see [`STYLE`].
+pub fn rule_svg(g : &Grammar, rule : usize) -> String
+{
+    let node = rule_node(g, rule);
+    let (width, up, down) = node.size();
+    let y = PAD + up;
+    let mut out = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" class=\"railroad\" width=\"{}\" height=\"{}\">", width + 2.0 * PAD + 2.0 * GAP, up + down + 2.0 * PAD);
+    // start and end markers
+    out += &format!("<path d=\"M{} {}v{BOX_HEIGHT}M{PAD} {y}h{GAP}\"/>", PAD, y - BOX_HEIGHT / 2.0);
+    node.render(&mut out, PAD + GAP, y);
+    let end = PAD + GAP + width;
+    out += &format!("<path d=\"M{end} {y}h{GAP}m0 -{}v{BOX_HEIGHT}\"/>", BOX_HEIGHT / 2.0);
+    out += "</svg>";
+    out
+}
+
+/// CSS for the diagrams made by [`rule_svg`]. [`generate_html`] includes it.
+pub const STYLE : &str = r#"
+svg.railroad path { stroke-width: 2; stroke: #333; fill: none; }
+svg.railroad path.back { stroke: #777; }
+svg.railroad rect { stroke-width: 2; stroke: #333; fill: #fff; }
+svg.railroad .terminal rect { fill: #dfd; }
+svg.railroad .rule rect { fill: #ddf; }
+svg.railroad .predicate rect { fill: #ffd; stroke-dasharray: 5 3; }
+svg.railroad .hook rect { fill: #fdd; stroke-dasharray: 2 2; }
+svg.railroad text { font: 13px monospace; text-anchor: middle; fill: #000; }
+svg.railroad a:hover rect { fill: #bbf; }
+"#;
+
+/// Makes a standalone HTML page documenting the grammar: one railroad diagram (see [`rule_svg`]) per grammar point, in order, each followed by the rule's BNF (as produced by [`Grammar::to_bnf`]), and cross-linked to each other. Each rule's diagram has the ID `rule-NAME`.
+pub fn generate_html(g : &Grammar, title : &str) -> String
+{
+    let bnf = g.to_bnf();
+    // BNF text of each rule, from the `name ::=` line to just before the next one
+    let mut bnf_of = std::collections::HashMap::new();
+    let mut current : Option<(&str, String)> = None;
+    for line in bnf.lines()
+    {
+        if let Some(name) = line.strip_suffix(" ::=") && !line.starts_with(' ')
+        {
+            if let Some((name, text)) = current.take() { bnf_of.insert(name, text); }
+            current = Some((name, format!("{line}\n")));
+        }
+        else if let Some((_, text)) = &mut current && line.starts_with(' ')
+        {
+            *text += &format!("{line}\n");
+        }
+    }
+    if let Some((name, text)) = current.take() { bnf_of.insert(name, text); }
+    
+    let mut out = String::new();
+    out += "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n";
+    out += &format!("<title>{}</title>\n<style>{STYLE}", escape(title));
+    out += "body { font-family: sans-serif; } pre { background: #f4f4f4; padding: 8px; } nav a { margin-right: 8px; }\n</style>\n</head>\n<body>\n";
+    out += &format!("<h1>{}</h1>\n", escape(title));
+    out += "<p>Round boxes match tokens. Square boxes are other rules (click to go to them). Dashed boxes are conditions that decide which way to go; they don't consume anything. Dotted boxes run custom code that can consume tokens.</p>\n";
+    out += "<nav>\n";
+    for point in &g.points
+    {
+        out += &format!("<a href=\"#rule-{0}\">{0}</a>\n", escape(&point.name));
+    }
+    out += "</nav>\n";
+    for point in &g.points
+    {
+        let name = escape(&point.name);
+        out += &format!("<h2 id=\"rule-{name}\"><a href=\"#rule-{name}\">{name}</a></h2>\n");
+        out += &rule_svg(g, point.id as usize);
+        out += "\n";
+        if let Some((r, seek_past)) = &point.recover
+        {
+            out += &format!("<p>On a syntax error, skips ahead to the next {}, {}.</p>\n", escape(&regex_label(r)), if *seek_past { "including it" } else { "stopping before it" });
+        }
+        if let Some(text) = bnf_of.get(point.name.as_str())
+        {
+            out += &format!("<pre>{}</pre>\n", escape(text));
+        }
+    }
+    out += "</body>\n</html>\n";
+    out
+}
@@ -0,0 +1,156 @@
+// Structured parse tracing
+
+use crate::bnf::*;
+use crate::ast::*;
+use std::time::Duration;
+
+/// Receives structured events from the parser. See [`ast::parse_with_observer`](`super::ast::parse_with_observer`).
+///
+/// Every method has an empty default implementation, so you only need to implement the events you care about. The parser is generic over the observer, so unused events compile down to nothing, and parsing with [`NoObserver`] costs exactly the same as parsing without an observer.
+///
+/// Rule IDs are indexes into [`Grammar::points`]. Token positions are indexes into the token stream.
+///
+/// Events are delivered in the order they happen. Every `rule_entered` is paired with exactly one `rule_exited`, unless the parse is aborted by an error in a rule that can't recover, in which case every rule on the stack exits with `ok == false`. A `$become` doesn't exit the current rule; the `rule_exited` event reports the rule that was active after the last `$become`.
+#[allow(unused_variables)]
+pub trait ParseObserver {
+    /// The parser started parsing `rule` at token position `token`.
+    fn rule_entered(&mut self, rule : usize, token : usize) {}
+    /// A predicate (`@peek`, `@peekr`, `@peekres`, `@guard`, `@eof`) at the start of alternation `alt` of `rule` was checked.
+    fn guard_checked(&mut self, rule : usize, alt : usize, token : usize, accepted : bool) {}
+    /// Alternation `alt` of `rule` was chosen at token position `token`.
+    fn alt_chosen(&mut self, rule : usize, alt : usize, token : usize) {}
+    /// The parser started working on term number `term` (index into [`Alternation::matching_terms`]) of alternation `alt` of `rule`, at token position `token`.
+    ///
+    /// Leading predicates are reported through `guard_checked` instead, and the rule name following a `$become`, `$become_as` or `$rename` is not reported separately.
+    fn term_reached(&mut self, rule : usize, alt : usize, term : usize, token : usize) {}
+    /// `rule` performed a `$become` or `$become_as` into `target` at token position `token`.
+    fn became(&mut self, rule : usize, target : usize, token : usize) {}
+    /// The hook `name` was called from inside of `rule` at token position `token`. `result` is what the hook returned.
+    fn hook_called(&mut self, rule : usize, name : &str, token : usize, result : &Result<usize, String>) {}
+    /// `rule` failed to parse at token position `from`, but recovered (see `@recover`) by skipping ahead to token position `to`.
+    fn recovered(&mut self, rule : usize, from : usize, to : usize) {}
+    /// The parser finished parsing `rule`, which spanned from token `token_start` up to (not including) token `token_end`. `ok` is false if the rule failed to parse.
+    fn rule_exited(&mut self, rule : usize, token_start : usize, token_end : usize, ok : bool) {}
+    
+    /// Return true to have the parser measure how long predicates and hooks take, and report it through `guard_timed` and `hook_timed`. Reading the clock isn't free, so this is off by default.
+    fn wants_timing(&self) -> bool { false }
+    /// Only called if `wants_timing` returns true. The predicates at the start of alternation `alt` of `rule` took `elapsed` to check. Called right before the matching `guard_checked`.
+    fn guard_timed(&mut self, rule : usize, alt : usize, elapsed : Duration) {}
+    /// Only called if `wants_timing` returns true. The hook `name`, called from inside of `rule`, took `elapsed` to run. Called right before the matching `hook_called`.
+    fn hook_timed(&mut self, rule : usize, name : &str, elapsed : Duration) {}
+    
+    /// Return true to have the parser call `step` with a view of its entire state. Building that view isn't free, so this is off by default.
+    fn wants_steps(&self) -> bool { false }
+    /// Only called if `wants_steps` returns true, and only by [`ast::parse_with_observer`](`super::ast::parse_with_observer`) (the recursive implementation doesn't have an inspectable stack). Called whenever a rule is entered or `$become`'d into (before its predicates are checked), and right before each term is worked on.
+    fn step(&mut self, state : &ParseState) {}
+}
+
+/// Why [`ParseObserver::step`] was called.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepKind {
+    /// The top frame's rule was just entered, or `$become`'d into. No alternation has been chosen yet.
+    RuleEntered,
+    /// The top frame is about to work on its current term.
+    Term,
+}
+
+/// One level of the parser's rule stack. See [`ParseState`].
+#[derive(Clone, Copy, Debug)]
+pub struct StackFrame<'a> {
+    /// Grammar point ID of the rule being parsed (after any `$become`).
+    pub rule : usize,
+    /// Interned string ID of the name that the resulting AST node will have (differs from the rule's name after a `$become`).
+    pub name_id : u32,
+    /// Alternation being worked on. Meaningless before one has been chosen.
+    pub alt : usize,
+    /// Index of the term being worked on, within the alternation.
+    pub term : usize,
+    /// Token position where the rule started.
+    pub token_start : usize,
+    /// Current token position.
+    pub token : usize,
+    /// AST nodes produced so far by this rule.
+    pub children : &'a [ASTNode],
+    /// Whether this rule has recovered from an error.
+    pub poisoned : bool,
+}
+
+/// View of the parser's state, passed to [`ParseObserver::step`].
+pub struct ParseState<'a, 'b> {
+    /// Why the step happened.
+    pub kind : StepKind,
+    /// The parser's global state, including `udata`.
+    pub global : &'a PrdGlobal<'b>,
+    /// The token stream being parsed.
+    pub tokens : &'a [Token],
+    pub (crate) stack : &'a [WorkState<'b>],
+    pub (crate) top : &'a WorkState<'b>,
+}
+
+impl<'a, 'b> ParseState<'a, 'b> {
+    /// Number of frames on the rule stack (including the one being worked on).
+    pub fn depth(&self) -> usize { self.stack.len() + 1 }
+    /// Frame number `i` of the rule stack, counting from the outermost frame (0). Panics if `i >= depth()`.
+    pub fn frame(&self, i : usize) -> StackFrame<'a>
+    {
+        let w = if i == self.stack.len() { self.top } else { &self.stack[i] };
+        StackFrame {
+            rule : w.g_item.id as usize, name_id : w.chosen_name_id, alt : w.alt_id, term : w.term_idx as usize,
+            token_start : w.token_start, token : w.i, children : &w.children[..], poisoned : w.poisoned,
+        }
+    }
+    /// The frame being worked on.
+    pub fn top(&self) -> StackFrame<'a> { self.frame(self.stack.len()) }
+}
+
+/// Observer that ignores every event. Used by [`ast::parse`](`super::ast::parse`) and [`ast::parse_recursive`](`super::ast::parse_recursive`).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoObserver;
+
+impl ParseObserver for NoObserver {}
+
+/// Observer that prints every event to stdout. This is what the `parse_trace` feature uses.
+pub struct TracePrinter<'a> {
+    /// Grammar that's being parsed, for looking up rule names.
+    pub g : &'a Grammar,
+    depth : usize,
+}
+
+impl<'a> TracePrinter<'a> {
+    /// Create a new trace printer for the given grammar.
+    pub fn new(g : &'a Grammar) -> Self { Self { g, depth : 0 } }
+}
+
+impl<'a> ParseObserver for TracePrinter<'a> {
+    fn rule_entered(&mut self, rule : usize, token : usize)
+    {
+        self.depth += 1;
+        println!("entered {} at {}, depth {}", self.g.points[rule].name, token, self.depth);
+    }
+    fn guard_checked(&mut self, rule : usize, alt : usize, token : usize, accepted : bool)
+    {
+        println!("guard of {} alt {} at {}: {}", self.g.points[rule].name, alt, token, if accepted { "accepted" } else { "rejected" });
+    }
+    fn alt_chosen(&mut self, _rule : usize, alt : usize, _token : usize)
+    {
+        println!("chose variant {alt}");
+    }
+    fn became(&mut self, _rule : usize, target : usize, token : usize)
+    {
+        println!("became {} at {}, depth {}", self.g.points[target].name, token, self.depth);
+    }
+    fn hook_called(&mut self, rule : usize, name : &str, token : usize, result : &Result<usize, String>)
+    {
+        println!("called hook {} inside {} at {}: {:?}", name, self.g.points[rule].name, token, result);
+    }
+    fn recovered(&mut self, rule : usize, from : usize, to : usize)
+    {
+        println!("recovered {} from {} to {}", self.g.points[rule].name, from, to);
+    }
+    fn rule_exited(&mut self, rule : usize, token_start : usize, token_end : usize, ok : bool)
+    {
+        if ok { println!("accepted {} from {} to {}, depth {}", self.g.points[rule].name, token_start, token_end, self.depth); }
+        else { println!("rejected {} from {} to {}, depth {}", self.g.points[rule].name, token_start, token_end, self.depth); }
+        self.depth = self.depth.saturating_sub(1);
+    }
+}